@@ -1,4 +1,9 @@
 use crate::helper::macros::strcat;
+use crate::video::app::{PAGES_84P, PAGES_84PSE};
+use crate::compress::codec::{self, Codecs, Stream};
+use crate::load::layout::{self, Layout};
+use crate::load::profile::{self, Profile};
+use crate::load::preprocess::{self, Step};
 use std::env;
 use getopts::Occur;
 use args::Args;
@@ -14,34 +19,72 @@ pub struct VArgs {
     pub calc_fps: f64,
     pub mute: bool,
     pub dither: char,
+    pub preprocess: Vec<Step>,
+    pub hold: u8,       // Source change below which pixels keep their previous shade, 0 for off
+    pub temporal: bool,
+    pub profile: Profile,
     pub audoff: i32,
     pub cycle_limit: usize,
     pub dbg_out: bool,
     pub app_source: Option<String>,
     pub key_source: Option<String>,
     pub show_full: bool,
+    pub inter_frame: bool,
+    pub borrow: bool,
+    pub layouts: Vec<Layout>,   // Layouts besides interleaved to try every image in
+    pub verify: bool,
+    pub emulate: bool,
+    pub fit: usize,     // Target number of pages, 0 to not fit
+    pub budget: usize,
+    pub fit_budgets: Vec<usize>,    // Image bytes --fit gives each frame, empty when not fitting
+    pub preview: String,
+    pub threads: usize,
+    pub solver_bench: bool,
+    pub codecs: Codecs,
+    pub bench: bool,    // Run the bench subcommand instead of converting
 }
 
 
 pub fn getargs() -> Result<VArgs, String> {
-    let mut args = Args::new("ti-audvid-convert", "Comverts a given video and transforms it into an application to be played back on a TI-83+SE or TI-84+(SE) calculator");
+    let mut args = Args::new("ti-audvid-convert", "Comverts a given video and transforms it into an application to be played back on a TI-83+SE or TI-84+(SE) calculator. \
+                                                   Run as `ti-audvid-convert bench -f FOLDER -o OUT` to instead compare every codec on an extracted video, saving CSV (or JSON if OUT ends in .json)");
+    // The bench subcommand doesn't make an app so doesn't need a name
+    let bench = env::args().nth(1).is_some_and(|arg| arg == "bench");
     
     args.option("v", "video", "Source video file", "VID", Occur::Optional, Some("".to_string()));
     args.option("f", "folder", "Source/Dest video folder", "FOLDER", Occur::Optional, Some("".to_string()));
-    args.option("o", "out", "Output application file", "OUT", Occur::Req, None);
-    args.option("n", "name", "Output application name (8 chars max)", "NAME", Occur::Req, None);
+    args.option("o", "out", "Output application file, or results file for bench", "OUT", Occur::Req, None);
+    args.option("n", "name", "Output application name (8 chars max)", "NAME", match bench { true => Occur::Optional, false => Occur::Req }, None);
     args.option("d", "duration", "How many calculator frames to convert from the video, omit for entire video", "DUR", Occur::Optional, Some("0".to_string()));
     args.option("s", "start", "Which calculator frame to start on, default first frame", "ST", Occur::Optional, Some("0".to_string()));
     args.option("p", "fps", "Change framerate of video on the calculator, default 20fps, try to keep this close to 20 (19-21)", "FPS", Occur::Optional, Some("20.0".to_string()));
     args.option("a", "audoff", "Audio offset, a greater value means audio will play sooner, negative values allowed", "AUDOFF", Occur::Optional, Some("0".to_string()));
-    args.option("t", "dither", "The dither mode, either f for floyd-steinburg or o for ordered, deafualt=o", "DITHER", Occur::Optional, Some("o".to_string()));
+    args.option("t", "dither", "The dither mode, f for floyd-steinburg, a for atkinson, i for sierra, j for jarvis-judice-ninke, o for ordered or b for blue noise, deafualt=o. Capitalise an error diffusion mode to scan every other row right to left", "DITHER", Occur::Optional, Some("o".to_string()));
+    args.option("", "preprocess", "Steps to run frames through before dithering, in order & separated by commas: levels to stretch each scene's contrast, sharpen:AMOUNT for an unsharp mask (default 1), edges:AMOUNT to darken edges (default 0.5) and denoise:THRESHOLD to average pixels with the last frame while they change less than the threshold (default 10). Each step's output is saved in the debug folder with -g", "STEPS", Occur::Optional, Some("".to_string()));
+    args.option("", "hold", "Keep each pixel's previous shade until its source brightness (0-255) changes by at least this much, so still parts of the video don't change between frames, default is 0 for off", "HOLD", Occur::Optional, Some("0".to_string()));
+    args.option("", "profile", "Calibration profile giving how bright each shade looks & the gamma to apply, either default or a .json file, default is default", "PROFILE", Occur::Optional, Some("default".to_string()));
     args.option("c", "cycle_limit", "Adjust maximum cycle cost per frame. Intended for debug/demonstrational use", "CT", Occur::Optional, Some("120000".to_string()));
     args.option("z", "source", "Source application data. Defaults to the provide application data", "SOURCE", Occur::Optional, Some("".to_string()));
-    args.option("k", "keyfile", "Keyfile to be passed to Rabbitsign, default is to use the provided 0104.key", "KEYFILE", Occur::Optional, Some("".to_string()));
+    args.option("i", "fit", "Choose the fps, duration, cycle limit & image budgets so the app fits on a calculator, either 84+ (30 pages), 84+se (94 pages) or a number of pages", "FIT", Occur::Optional, Some("".to_string()));
+    args.option("b", "budget", "Average bytes each image frame may take, frames over budget get simplified until they fit, default is 0 for lossless", "BYTES", Occur::Optional, Some("0".to_string()));
+    args.option("l", "preview", "Also render what the calculator will show, either a .gif (audio saved next to it as a .wav) or a folder to fill with png frames & audio", "PREVIEW", Occur::Optional, Some("".to_string()));
+    args.option("j", "threads", "Number of threads to compress frames on, default is 0 for one per CPU core", "THREADS", Occur::Optional, Some("0".to_string()));
+    args.option("", "img_codec", &format!("Codec to compress images with, one of {}, default is lzss_alt. Only {} make apps that play", codec::names(Stream::Image).join(", "), codec::playable_names(Stream::Image).join(" & ")), "CODEC", Occur::Optional, Some("lzss_alt".to_string()));
+    args.option("", "aud_codec", &format!("Codec to compress audio with, one of {}, default is nib_diff", codec::names(Stream::Audio).join(", ")), "CODEC", Occur::Optional, Some("nib_diff".to_string()));
+    args.option("", "scan", "Scan orders to also try every image in, keeping whichever is smallest, any of rows, serpentine & hilbert separated by commas or all. Requires the player to be re-assembled", "ORDERS", Occur::Optional, Some("".to_string()));
+    args.option("k", "keyfile", "Keyfile used to sign the app, default is to use the provided 0104.key", "KEYFILE", Occur::Optional, Some("".to_string()));
     
     args.flag("w", "show_full", "Flag - shrink frame to fit entire video on screen");
+    args.flag("r", "inter_frame", "Flag - allow frames to copy from previously decoded frames, requires the player to be re-assembled");
+    args.flag("u", "borrow", "Flag - let frames borrow decompression time the frame before them left over, requires the player to be re-assembled");
+    args.flag("", "split", "Flag - also try storing each image as separate bit-planes and keep whichever layout is smaller, requires the player to be re-assembled");
+    args.flag("", "temporal", "Flag - show shades between the 4 the player can by flickering pixels between the shades either side every frame, leave off for viewers sensitive to flicker");
     args.flag("m", "mute", "Flag - shuts me up");
     args.flag("g", "debug", "Flag - output debug files during convert");
+    args.flag("x", "solver_bench", "Flag - also compress every image with the older pruned solver and compare sizes & times");
+    args.flag("y", "verify", "Flag - decompress each frame after converting it and check it matches");
+    #[cfg(feature = "emulator")]
+    args.flag("e", "emulate", "Flag - run the finished app's decompression on an emulated calculator and check its cycle counts");
     args.flag("h", "help", "Flag - Print this help message");
 
     /*
@@ -79,24 +122,42 @@ pub fn getargs() -> Result<VArgs, String> {
                 Err(_) => "".to_string(),
             }},
             out: args.value_of::<String>("out").unwrap(),
-            name: args.value_of::<String>("name").unwrap(),
+            name: args.value_of::<String>("name").unwrap_or_default(),
             dur: args.value_of::<usize>("duration").unwrap(),
             start: args.value_of::<usize>("start").unwrap(),
             calc_fps: args.value_of::<f64>("fps").unwrap(),
             mute: args.value_of::<bool>("mute").unwrap(),
             dither: args.value_of::<String>("dither").unwrap().chars().next().unwrap(),
+            preprocess: preprocess::parse_steps(&args.value_of::<String>("preprocess").unwrap_or_default())?,
+            hold: args.value_of::<u8>("hold").unwrap(),
+            temporal: args.value_of::<bool>("temporal").unwrap(),
+            profile: profile::find(&args.value_of::<String>("profile").unwrap())?,
             audoff: args.value_of::<i32>("audoff").unwrap(),
             cycle_limit: args.value_of::<usize>("cycle_limit").unwrap(),
             dbg_out: args.value_of::<bool>("debug").unwrap(),
-            app_source: {match args.value_of::<String>("source") {
-                Ok(s) => Some(s),
-                Err(_) => None,
-            }},
-            key_source: {match args.value_of::<String>("key") {
-                Ok(s) => Some(s),
-                Err(_) => None,
-            }},
+            app_source: args.value_of::<String>("source").ok(),
+            key_source: args.value_of::<String>("keyfile").ok(),
             show_full: args.value_of::<bool>("show_full").unwrap(),
+            inter_frame: args.value_of::<bool>("inter_frame").unwrap(),
+            borrow: args.value_of::<bool>("borrow").unwrap(),
+            layouts: {
+                let mut layouts = layout::parse_scan(&args.value_of::<String>("scan").unwrap_or_default())?;
+                if args.value_of::<bool>("split").unwrap() {
+                    layouts.insert(0, Layout::Split);
+                }
+                layouts
+            },
+            verify: args.value_of::<bool>("verify").unwrap(),
+            emulate: cfg!(feature = "emulator") && args.value_of::<bool>("emulate").unwrap(),
+            budget: args.value_of::<usize>("budget").unwrap(),
+            fit_budgets: Vec::new(),
+            preview: args.value_of::<String>("preview").unwrap_or_default(),
+            threads: args.value_of::<usize>("threads").unwrap(),
+            solver_bench: args.value_of::<bool>("solver_bench").unwrap(),
+            fit: parse_fit(&args.value_of::<String>("fit").unwrap_or_default())?,
+            codecs: Codecs {img: codec::find(&args.value_of::<String>("img_codec").unwrap(), Stream::Image)?,
+                            aud: codec::find(&args.value_of::<String>("aud_codec").unwrap(), Stream::Audio)?},
+            bench,
         } ),
         Err(err) => {
             println!("{}", err);
@@ -108,3 +169,18 @@ pub fn getargs() -> Result<VArgs, String> {
     }
 }
 
+
+
+
+fn parse_fit(fit: &str) -> Result<usize, String> {
+    // Convert calculator model into the number of pages available to apps
+    match fit.to_lowercase().as_str() {
+        "" => Ok(0),
+        "84+" => Ok(PAGES_84P),
+        "84+se" | "83+se" => Ok(PAGES_84PSE),
+        pages => match pages.parse::<usize>() {
+            Ok(pages) if pages >= 2 => Ok(pages),
+            _ => Err(format!("Unknown fit target {}, use 84+, 84+se or a number of pages", fit)),
+        },
+    }
+}
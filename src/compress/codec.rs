@@ -0,0 +1,329 @@
+use crate::compress::{lzss_alt, nib_diff, none, old};
+use crate::compress::instr::{self, Instr};
+use crate::compress::cycle_limit::CycleInstr;
+use crate::decompress;
+use std::panic::RefUnwindSafe;
+
+
+// Image & audio compression schemes behind one interface, so the rest of the converter doesn't
+// care which one a frame gets compressed with
+// Codecs get picked by name out of the registry, a new scheme only has to be added to it. Only
+// apps made with the codecs the player has decompression routines for will play, the rest are
+// there to compare sizes against (see video::bench)
+
+
+
+pub const PLAYER_IMG_ID: u8 = 1;    // Bytecode ids the player can decompress
+pub const PLAYER_AUD_ID: u8 = 1;
+pub const FILL_IMG_ID: u8 = 3;      // Also decompressed by the player
+
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Stream {
+    Image,
+    Audio,
+}
+
+pub struct CodecInput<'a> {
+    pub data: &'a [u8],
+    pub prev: Option<&'a [u8]>,     // Image the player decoded before this one
+    pub target: Option<&'a [u8]>,   // Image left in the buffer this one gets decoded into
+    pub start_samp: u8,             // Sample the audio starts from
+}
+
+pub trait Codec: Sync + RefUnwindSafe {
+    fn name(&self) -> &'static str;
+    fn stream(&self) -> Stream;
+    fn id(&self) -> u8;     // Bytecode id, unique among the codecs for a stream
+    // Instructions making up the compressed data & the sample the next frame's audio starts from
+    fn compress(&self, input: &CodecInput, cycle_limit: usize, other_cycles: &dyn Fn(isize) -> usize) -> (Vec<Box<dyn CycleInstr>>, u8);
+    fn bytecode(&self, instrs: &Vec<Box<dyn CycleInstr>>, _input: &CodecInput) -> Vec<u8> {
+        instr::gen_bytecode(instrs)
+    }
+    // Decompresses into out, which starts off with whatever the player has left in the buffer.
+    // Returns the number of compressed bytes read
+    fn decompress(&self, comp: &[u8], out: &mut [u8], input: &CodecInput) -> Result<usize, String>;
+    fn frame_cycles(&self) -> usize {   // Cycles the player spends on every frame besides the instructions
+        0
+    }
+    fn cycles(&self, instrs: &[Box<dyn CycleInstr>]) -> usize {
+        // Cycles the player takes to decompress the instructions
+        self.frame_cycles() + instrs.iter().map(|inst| inst.get_cycles()).sum::<usize>()
+    }
+}
+
+
+#[derive(Clone, Copy)]
+pub struct Codecs {
+    pub img: &'static dyn Codec,
+    pub aud: &'static dyn Codec,
+}
+
+impl Codecs {
+    pub fn playable(&self) -> bool {
+        // Whether the player can decompress apps made with these codecs
+        plays(self.img) && plays(self.aud)
+    }
+}
+
+fn plays(codec: &dyn Codec) -> bool {
+    match codec.stream() {
+        Stream::Image => codec.id() == PLAYER_IMG_ID || codec.id() == FILL_IMG_ID,
+        Stream::Audio => codec.id() == PLAYER_AUD_ID,
+    }
+}
+
+
+static REGISTRY: [&dyn Codec; 8] = [&LZSS_ALT, &NibDiffCodec{}, &LZSS_FILL, &NONE_IMG, &NONE_AUD, &RLELZSSCodec{}, &AccelCodec{}, &DiffAbsCodec{}];
+
+pub fn find(name: &str, stream: Stream) -> Result<&'static dyn Codec, String> {
+    // Look up a codec for a stream by name
+    match REGISTRY.iter().find(|codec| codec.stream() == stream && codec.name() == name) {
+        Some(&codec) => Ok(codec),
+        None => Err(format!("Unknown {} codec {}, choose from {}", match stream {
+            Stream::Image => "image",
+            Stream::Audio => "audio",
+        }, name, names(stream).join(", "))),
+    }
+}
+
+pub fn names(stream: Stream) -> Vec<&'static str> {
+    REGISTRY.iter().filter(|codec| codec.stream() == stream).map(|codec| codec.name()).collect()
+}
+
+pub fn playable_names(stream: Stream) -> Vec<&'static str> {
+    REGISTRY.iter().filter(|codec| codec.stream() == stream && plays(**codec)).map(|codec| codec.name()).collect()
+}
+
+pub fn all() -> &'static [&'static dyn Codec] {
+    &REGISTRY
+}
+
+
+
+/*          Player Codecs           */
+
+const LZSS_ALT: LZSSAltCodec = LZSSAltCodec{fill: false};
+const LZSS_FILL: LZSSAltCodec = LZSSAltCodec{fill: true};
+
+struct LZSSAltCodec {
+    fill: bool,     // Also use fill instructions
+}
+impl Codec for LZSSAltCodec {
+    fn name(&self) -> &'static str {
+        match self.fill {
+            false => "lzss_alt",
+            true => "lzss_fill",
+        }
+    }
+    fn stream(&self) -> Stream {
+        Stream::Image
+    }
+    fn id(&self) -> u8 {
+        match self.fill {
+            false => PLAYER_IMG_ID,
+            true => FILL_IMG_ID,
+        }
+    }
+    fn compress(&self, input: &CodecInput, cycle_limit: usize, other_cycles: &dyn Fn(isize) -> usize) -> (Vec<Box<dyn CycleInstr>>, u8) {
+        (lzss_alt::compress(input.data, input.prev, input.target, self.fill, cycle_limit, other_cycles), 0)
+    }
+    fn frame_cycles(&self) -> usize {
+        // Waiting for the frame, looking up its image & reading the end of it
+        238
+    }
+    fn decompress(&self, comp: &[u8], out: &mut [u8], input: &CodecInput) -> Result<usize, String> {
+        decompress::lzss_alt::dec_img(comp, out, input.prev)
+    }
+}
+
+struct NibDiffCodec {
+}
+impl Codec for NibDiffCodec {
+    fn name(&self) -> &'static str {
+        "nib_diff"
+    }
+    fn stream(&self) -> Stream {
+        Stream::Audio
+    }
+    fn id(&self) -> u8 {
+        PLAYER_AUD_ID
+    }
+    fn compress(&self, input: &CodecInput, _cycle_limit: usize, _other_cycles: &dyn Fn(isize) -> usize) -> (Vec<Box<dyn CycleInstr>>, u8) {
+        nib_diff::compress(input.data, input.start_samp)
+    }
+    fn frame_cycles(&self) -> usize {
+        // Setting up the audio & its start sample, byte-wide audio takes a little longer getting
+        // there than nibbles
+        166
+    }
+    fn bytecode(&self, instrs: &Vec<Box<dyn CycleInstr>>, input: &CodecInput) -> Vec<u8> {
+        // The player picks up the start sample right after the differences
+        let mut bytecode = instr::gen_bytecode(instrs);
+        bytecode.push(input.start_samp);
+        bytecode
+    }
+    fn decompress(&self, comp: &[u8], out: &mut [u8], _input: &CodecInput) -> Result<usize, String> {
+        let (diffs, start, read) = decompress::nib_diff::dec_aud(comp)?;
+        let samps = decompress::nib_diff::apply_diffs(&diffs, start);
+        out.copy_from_slice(&samps[..out.len()]);
+        Ok(read)
+    }
+}
+
+
+
+/*          Uncompressed            */
+
+const NONE_IMG: NoneCodec = NoneCodec{stream: Stream::Image};
+const NONE_AUD: NoneCodec = NoneCodec{stream: Stream::Audio};
+const COPY_CYCLES: usize = 21;      // ldir
+
+struct NoneCodec {
+    stream: Stream,
+}
+impl Codec for NoneCodec {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+    fn stream(&self) -> Stream {
+        self.stream
+    }
+    fn id(&self) -> u8 {
+        0
+    }
+    fn compress(&self, input: &CodecInput, _cycle_limit: usize, _other_cycles: &dyn Fn(isize) -> usize) -> (Vec<Box<dyn CycleInstr>>, u8) {
+        // The player only keeps the upper 7 bits of each sample
+        let data = match self.stream {
+            Stream::Image => none::compress(input.data.to_vec()),
+            Stream::Audio => none::compress(input.data.iter().map(|samp| samp & 0xFE).collect()),
+        };
+        let cycles = data.len() * COPY_CYCLES;
+        let last_samp = data.last().copied().unwrap_or(input.start_samp);
+        (vec![Box::new(RawInstr {data, cycles})], last_samp)
+    }
+    fn decompress(&self, comp: &[u8], out: &mut [u8], _input: &CodecInput) -> Result<usize, String> {
+        if comp.len() < out.len() {
+            return Err(format!("Only {} of {} bytes are there", comp.len(), out.len()));
+        }
+        out.copy_from_slice(&comp[..out.len()]);
+        Ok(out.len())
+    }
+}
+
+
+
+/*          Legacy Codecs           */
+
+// Schemes older players used, the player for them is gone so their cycles are rough estimates
+// The legacy audio schemes only handle 7 bit samples, the same bits the player keeps
+const ACCEL_SAMP_CYCLES: usize = 120;
+const DIFF_ABS_SAMP_CYCLES: usize = 45;
+
+struct RLELZSSCodec {
+}
+impl Codec for RLELZSSCodec {
+    fn name(&self) -> &'static str {
+        "rle_lzss"
+    }
+    fn stream(&self) -> Stream {
+        Stream::Image
+    }
+    fn id(&self) -> u8 {
+        2
+    }
+    fn compress(&self, input: &CodecInput, _cycle_limit: usize, _other_cycles: &dyn Fn(isize) -> usize) -> (Vec<Box<dyn CycleInstr>>, u8) {
+        (old::rle_lzss::compress(input.data), 0)
+    }
+    fn decompress(&self, comp: &[u8], out: &mut [u8], _input: &CodecInput) -> Result<usize, String> {
+        decompress::old::rle_lzss::dec_img(comp, out)
+    }
+}
+
+struct AccelCodec {
+}
+impl Codec for AccelCodec {
+    fn name(&self) -> &'static str {
+        "accel"
+    }
+    fn stream(&self) -> Stream {
+        Stream::Audio
+    }
+    fn id(&self) -> u8 {
+        2
+    }
+    fn compress(&self, input: &CodecInput, _cycle_limit: usize, _other_cycles: &dyn Fn(isize) -> usize) -> (Vec<Box<dyn CycleInstr>>, u8) {
+        let samps: Vec<u8> = input.data.iter().map(|samp| samp >> 1).collect();
+        let data = old::accel::compress(&samps);
+        let last_samp = input.data.last().map(|samp| samp & 0xFE).unwrap_or(input.start_samp);
+        (vec![Box::new(RawInstr {data, cycles: samps.len() * ACCEL_SAMP_CYCLES})], last_samp)
+    }
+    fn decompress(&self, comp: &[u8], out: &mut [u8], _input: &CodecInput) -> Result<usize, String> {
+        let read = decompress::old::accel::dec_aud(comp, out)?;
+        out.iter_mut().for_each(|samp| *samp <<= 1);
+        Ok(read)
+    }
+}
+
+struct DiffAbsCodec {
+}
+impl Codec for DiffAbsCodec {
+    fn name(&self) -> &'static str {
+        "diff_abs"
+    }
+    fn stream(&self) -> Stream {
+        Stream::Audio
+    }
+    fn id(&self) -> u8 {
+        3
+    }
+    fn compress(&self, input: &CodecInput, _cycle_limit: usize, _other_cycles: &dyn Fn(isize) -> usize) -> (Vec<Box<dyn CycleInstr>>, u8) {
+        let samps: Vec<u8> = input.data.iter().map(|samp| samp >> 1).collect();
+        let data = old::diff_abs::compress(&samps);
+        let last_samp = input.data.last().map(|samp| samp & 0xFE).unwrap_or(input.start_samp);
+        (vec![Box::new(RawInstr {data, cycles: samps.len() * DIFF_ABS_SAMP_CYCLES})], last_samp)
+    }
+    fn decompress(&self, comp: &[u8], out: &mut [u8], _input: &CodecInput) -> Result<usize, String> {
+        let read = decompress::old::diff_abs::dec_aud(comp, out)?;
+        out.iter_mut().for_each(|samp| *samp <<= 1);
+        Ok(read)
+    }
+}
+
+
+
+#[derive(Clone)]
+pub struct RawInstr {      // Data a codec already turned into bytes, for codecs without instructions
+    pub data: Vec<u8>,
+    pub cycles: usize,      // Estimate of what a decompression routine for it would take
+}
+impl Instr for RawInstr {
+    fn gen_bytecode(&self, _last: bool) -> Vec<u8> {
+        self.data.clone()
+    }
+    fn get_comp_size(&self) -> usize {
+        self.data.len()
+    }
+    fn get_decomp_size(&self) -> usize {
+        self.data.len()
+    }
+    fn get_decomp(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+}
+impl CycleInstr for RawInstr {
+    fn get_cycles(&self) -> usize {
+        self.cycles
+    }
+    fn is_minimum(&self) -> bool {
+        // There's no faster encoding to switch to
+        true
+    }
+    fn to_minimum(&self) -> Box<dyn CycleInstr> {
+        Box::new(self.clone())
+    }
+    fn combine_with_left(&mut self, _other: &dyn CycleInstr) {
+    }
+    fn combine_with_right(&mut self, _other: &dyn CycleInstr) {
+    }
+}
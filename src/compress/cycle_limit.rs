@@ -1,7 +1,8 @@
 use crate::compress::instr::{Instr};
+use crate::compress::graph_solve::BYTE_SCALE;
 
 
-pub trait CycleInstr: Instr {
+pub trait CycleInstr: Instr + Send {
     fn get_cycles(&self) -> usize;
     fn is_minimum(&self) -> bool;
     fn to_minimum(&self) -> Box<dyn CycleInstr>;
@@ -20,6 +21,25 @@ pub fn get_total_cycles(instr_streams: &Vec<Vec<Box<dyn CycleInstr>>>) -> usize
     total
 }
 
+pub fn priced_cycles(instrs: &[Box<dyn CycleInstr>], lambda: isize) -> usize {
+    // Cycles the instructions take if each one is converted to its most efficient encoding
+    // whenever the cycles saved are worth more than the bytes added, lambda being the price of a
+    // cycle in 1/BYTE_SCALE bytes
+    instrs.iter().map(|inst| {
+        let cycles = inst.get_cycles();
+        if lambda == 0 || inst.is_minimum() {
+            return cycles;
+        }
+        let min = inst.to_minimum();
+        let saved = cycles as isize - min.get_cycles() as isize;
+        let added = min.get_comp_size() as isize - inst.get_comp_size() as isize;
+        match lambda * saved > added * BYTE_SCALE {
+            true => min.get_cycles(),
+            false => cycles,
+        }
+    }).sum()
+}
+
 pub fn reduce_cycles_to(instr_streams: &mut Vec<Vec<Box<dyn CycleInstr>>>, reduce_to: usize) -> usize {
     // Reduces the total cycle cost of the provinded compressed data streams
     // Will iteratively convert the instructions with the worst ratio of cycle cost to encoded
@@ -39,8 +59,11 @@ pub fn reduce_cycles_to(instr_streams: &mut Vec<Vec<Box<dyn CycleInstr>>>, reduc
     info.sort_by(|a, b| a.4.partial_cmp(&b.4).unwrap());
     // Loop while cycle count is above what we want
     while total > reduce_to {
-        // Look at worst ratio
-        let (w_sind, mut w_iind, w_c, _w_s, _w_ratio) = info.pop().unwrap();
+        // Look at worst ratio, stop if everything is already as fast as it gets
+        let (w_sind, mut w_iind, w_c, _w_s, _w_ratio) = match info.pop() {
+            Some(worst) => worst,
+            None => break,
+        };
         let worst = &instr_streams[w_sind][w_iind];
         // Convert worst ratio to a most efficient encoding
         if worst.is_minimum() {
@@ -7,6 +7,7 @@
 // compressed result which is as small as possible
 
 
+use std::cmp;
 use crate::compress::instr::{/*Instr,*/ InstrGen};
 use crate::compress::cycle_limit::CycleInstr;
 
@@ -16,13 +17,18 @@ pub trait GraphFuncs {
     fn get_step_cost(&self, data: &[u8], pos: usize, uid: usize) -> isize;
     fn get_entry_cost(&self, data: &[u8], pos: usize, uid: usize) -> isize;
     fn get_cont_cost(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize;
+    fn get_cost_period(&self) -> usize;     // Continue costs are only charged every this many bytes, and cost the same each time
+    fn get_cost_class(&self, uid: usize) -> usize;  // Ids in the same class cost the same everywhere
+    fn get_entry_cycles(&self, data: &[u8], pos: usize, uid: usize) -> isize;   // Cycles the player takes on an instruction's first byte
+    fn get_cont_cycles(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize;    // and on each byte after that
 }
 
 pub struct GraphSolve<'a> {
     numt: usize,
     data: &'a [u8],
-    gtypes: Vec<Box<dyn GraphFuncs>>,
-    itypes: Vec<Box<dyn InstrGen<dyn CycleInstr>>>
+    gtypes: &'a [Box<dyn GraphFuncs>],
+    itypes: &'a [Box<dyn InstrGen<dyn CycleInstr>>],
+    lambda: isize,      // Price of a cycle, in 1/BYTE_SCALE bytes
 }
 
 
@@ -47,15 +53,306 @@ impl Node {
     
 
 
-pub fn compress(data: &[u8], instr_types: Vec<Box<dyn InstrGen<dyn CycleInstr>>>, graph_types: Vec<Box<dyn GraphFuncs>>) -> Vec<Box<dyn CycleInstr>> {
-    let gs: GraphSolve = GraphSolve { numt: instr_types.len(), data: data, itypes: instr_types, gtypes: graph_types };
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(Debug)]
+struct StateNode {
+    cost: isize,
+    from: usize,        // Node in the previous row this one's path comes from
+    instr_type: usize,
+    id: usize,
+    end: usize,         // Position the instruction's run stops at
+    phase: usize,       // Relative position in the instruction, modulo the type's cost period
+    entered: bool,      // Instruction starts on this byte instead of continuing
+    class: usize,       // The instruction's cost class
+}
+
+const NO_TYPE: usize = usize::MAX;
+
+// When the smallest encoding takes too many cycles, the solver instead minimises bytes + lambda *
+// cycles, searching for the lowest lambda that gets under the limit
+pub const BYTE_SCALE: isize = 1024;
+const MAX_LAMBDA: isize = BYTE_SCALE * 16;  // Past this cycles are all that matter
+const LAMBDA_GROWTH: isize = 4;
+const LAMBDA_STEPS: usize = 6;              // Steps narrowing lambda down once one fits
+
+
+
+pub fn compress(data: &[u8], instr_types: &[Box<dyn InstrGen<dyn CycleInstr>>], graph_types: &[Box<dyn GraphFuncs>],
+                cycle_limit: usize, other_cycles: &dyn Fn(isize) -> usize) -> Vec<Box<dyn CycleInstr>> {
+    // other_cycles gives the cycles taken by the rest of the frame when cycles cost lambda, so
+    // whatever else shares the limit gets traded off at the same price
+    // The instructions available at each position don't depend on the price, so only find them once
+    let instr_info: Vec<Vec<(usize, usize, usize)>> = (0..data.len()).map(|pos| {
+        let mut info = Vec::new();
+        for (itype, gen) in graph_types.iter().enumerate() {
+            info.extend(gen.get_instr_info(data, pos).into_iter().map(|(len, id)| (itype, len, id)));
+        }
+        info
+    }).collect();
+    let solve = |lambda: isize| {
+        let gs: GraphSolve = GraphSolve { numt: instr_types.len(), data, itypes: instr_types, gtypes: graph_types, lambda };
+        let graph = gs.make_state_graph(&instr_info);
+        gs.gen_state_instrs(graph)
+    };
+    // Use the smallest encoding if it's fast enough
+    let smallest = solve(0);
+    if total_cycles(&smallest) + other_cycles(0) <= cycle_limit {
+        return smallest;
+    }
+    // Raise the price of cycles until the encoding fits, the result only ever gets slower to
+    // decompress as lambda goes down so then search between the last two prices tried
+    let mut low = 0;
+    let mut high = 1;
+    let mut best = loop {
+        let instrs = solve(high);
+        if total_cycles(&instrs) + other_cycles(high) <= cycle_limit {
+            break instrs;
+        }
+        if high >= MAX_LAMBDA {
+            // Nothing fits, the fastest encoding found gets cut down afterwards
+            return instrs;
+        }
+        low = high;
+        high *= LAMBDA_GROWTH;
+    };
+    for _i in 0..LAMBDA_STEPS {
+        let mid = (low + high) / 2;
+        if mid == low {
+            break;
+        }
+        let instrs = solve(mid);
+        if total_cycles(&instrs) + other_cycles(mid) <= cycle_limit {
+            high = mid;
+            best = instrs;
+        } else {
+            low = mid;
+        }
+    }
+    // The rest of the frame only changes in big steps, so the price where it fits can leave the
+    // image with cycles to spare. Keep the rest as it is there and lower the price for the image
+    let rest = other_cycles(high);
+    if total_cycles(&smallest) + rest <= cycle_limit {
+        return smallest;
+    }
+    let mut low = 0;
+    for _i in 0..LAMBDA_STEPS {
+        let mid = (low + high) / 2;
+        if mid == low {
+            break;
+        }
+        let instrs = solve(mid);
+        if total_cycles(&instrs) + rest <= cycle_limit {
+            high = mid;
+            best = instrs;
+        } else {
+            low = mid;
+        }
+    }
+    best
+}
+
+pub fn compress_pruned(data: &[u8], instr_types: &[Box<dyn InstrGen<dyn CycleInstr>>], graph_types: &[Box<dyn GraphFuncs>]) -> Vec<Box<dyn CycleInstr>> {
+    // Older solver that limits how many instructions of each type it follows, kept to benchmark
+    // against
+    let gs: GraphSolve = GraphSolve { numt: instr_types.len(), data, itypes: instr_types, gtypes: graph_types, lambda: 0 };
     let graph = gs.make_graph();
     gs.gen_instrs(graph)
 }
 
 
+fn total_cycles(instrs: &[Box<dyn CycleInstr>]) -> usize {
+    instrs.iter().map(|inst| inst.get_cycles()).sum()
+}
+
+
 impl GraphSolve<'_> {
     
+    fn make_state_graph(&self, instr_info: &[Vec<(usize, usize, usize)>]) -> Vec<Vec<StateNode>> {     // instr_info holds type, len & id
+        // Finds the cheapest encoding as a shortest path where each row holds every state the
+        // encoder can be in after a byte: which instruction it's in, that instruction's id and
+        // where its run ends, and how far into the instruction it is modulo the cost period
+        // Since costs only depend on those, keeping the cheapest path into each state is enough to
+        // be optimal, states are only dropped when another state is sure to do at least as well
+        // Each state either continues from the same instruction in the previous row, or starts a
+        // new instruction from the cheapest state of the previous row, so a row only takes time
+        // proportional to its size
+        // Costs are in bytes scaled by BYTE_SCALE plus lambda times the cycles taken
+        let len_data = self.data.len();
+        let periods: Vec<usize> = self.gtypes.iter().map(|g| g.get_cost_period()).collect();
+        
+        let mut graph: Vec<Vec<StateNode>> = Vec::with_capacity(len_data+1);
+        graph.push(vec![StateNode {cost: 0, from: 0, instr_type: NO_TYPE, id: 0, end: 0, phase: 0, entered: false, class: 0}]);
+        
+        let mut group: Vec<StateNode> = Vec::new();
+        let mut longest: Vec<(usize, usize, usize, bool)> = Vec::new();     // Type, cost class, end & whether it's started yet
+        let mut margins: Vec<Vec<Option<(bool, isize)>>> = vec![Vec::new(); self.numt];     // By type then cost class & parity of where phase 0 falls
+        for pos in 0..len_data {
+            
+            let prev = &graph[pos];
+            // New instructions start from the cheapest path so far
+            let (best, best_cost) = prev.iter().enumerate().map(|(ind, node)| (ind, node.cost)).min_by_key(|&(_, cost)| cost).unwrap();
+            let enter = |itype: usize, id: usize, end: usize, class: usize, step_cost: isize| {
+                let gen = &self.gtypes[itype];
+                let cycles = match self.lambda {
+                    0 => 0,
+                    lambda => lambda * gen.get_entry_cycles(self.data, pos, id),
+                };
+                StateNode {cost: best_cost + (step_cost + gen.get_entry_cost(self.data, pos, id)) * BYTE_SCALE + cycles,
+                           from: best, instr_type: itype, id, end, phase: 0, entered: true, class}
+            };
+            // Instructions of the same type & cost class all cost the same to start here, so only
+            // the one running the furthest is worth starting. That also covers runs already going
+            longest.clear();
+            let mut note_run = |itype: usize, class: usize, end: usize| {
+                match longest.iter_mut().find(|run| (run.0, run.1) == (itype, class)) {
+                    Some(run) => run.2 = cmp::max(run.2, end),
+                    None => longest.push((itype, class, end, false)),
+                }
+            };
+            for node in prev.iter().filter(|node| node.instr_type != NO_TYPE && pos < node.end) {
+                note_run(node.instr_type, node.class, node.end);
+            }
+            for &(itype, len, id) in instr_info[pos].iter() {
+                note_run(itype, self.gtypes[itype].get_cost_class(id), cmp::min(pos + len, len_data));
+            }
+            let mut starts = |itype: usize, class: usize, end: usize| {
+                match longest.iter_mut().find(|run| (run.0, run.1, run.2, run.3) == (itype, class, end, false)) {
+                    Some(run) => {
+                        run.3 = true;
+                        true
+                    },
+                    None => false,
+                }
+            };
+            // Nodes of the same instruction run, a run being an instruction type, id & end, are kept
+            // next to each other in a row sorted by phase
+            let mut row: Vec<StateNode> = Vec::with_capacity(prev.len() + 8);
+            let mut start = 0;
+            while start < prev.len() {
+                let first = prev[start];
+                let mut stop = start + 1;
+                while stop < prev.len() && (prev[stop].instr_type, prev[stop].id, prev[stop].end) == (first.instr_type, first.id, first.end) {
+                    stop += 1;
+                }
+                if first.instr_type != NO_TYPE && pos < first.end {
+                    // Continue the run from last row, or restart it here. States stay in order of
+                    // phase, only the one wrapping back to the start has to compete with restarting
+                    let gen = &*self.gtypes[first.instr_type];
+                    let period = periods[first.instr_type];
+                    let step_cost = gen.get_step_cost(self.data, pos, first.id);
+                    group.clear();
+                    group.push(match starts(first.instr_type, first.class, first.end) {
+                        true => enter(first.instr_type, first.id, first.end, first.class, step_cost),
+                        false => StateNode {cost: isize::MAX, ..first},
+                    });
+                    for (ind, node) in prev.iter().enumerate().take(stop).skip(start) {
+                        let phase = (node.phase + 1) % period;
+                        let cost = node.cost + step_cost * BYTE_SCALE + self.phase_cost(gen, pos, node.id, phase);
+                        let cont = StateNode {cost, from: ind, phase, entered: false, ..*node};
+                        if phase != 0 {
+                            group.push(cont);
+                        } else if cost < group[0].cost {
+                            group[0] = cont;
+                        }
+                    }
+                    if group[0].cost == isize::MAX {
+                        group.remove(0);
+                    }
+                    // Drop states that can't end up cheaper than another state of the run
+                    let base = pos - group[group.len()-1].phase;
+                    let type_margins = &mut margins[first.instr_type];
+                    let key = first.class * 2 + base % 2;
+                    if type_margins.len() <= key {
+                        type_margins.resize(key + 1, None);
+                    }
+                    let (wraps_only, margin) = *type_margins[key].get_or_insert_with(|| self.phase_margin(gen, base, first.id, period));
+                    let cheapest = group.iter().map(|node| node.cost).min().unwrap();
+                    let mut min_cost = isize::MAX;
+                    for node in group.iter() {
+                        if node.cost - cheapest < margin && (node.cost < min_cost || !wraps_only) {
+                            min_cost = cmp::min(min_cost, node.cost);
+                            row.push(*node);
+                        }
+                    }
+                }
+                start = stop;
+            }
+            // Start new instructions, ones already running were restarted above
+            for &(itype, len, id) in instr_info[pos].iter() {
+                let class = self.gtypes[itype].get_cost_class(id);
+                let end = cmp::min(pos + len, len_data);
+                if starts(itype, class, end) {
+                    row.push(enter(itype, id, end, class, self.gtypes[itype].get_step_cost(self.data, pos, id)));
+                }
+            }
+            
+            graph.push(row);
+            
+        }
+        
+        graph
+        
+    }
+    
+    fn phase_cost(&self, gen: &dyn GraphFuncs, pos: usize, id: usize, phase: usize) -> isize {
+        // Cost of continuing an instruction onto a byte, apart from its step cost which is the same
+        // for every state at a position
+        gen.get_cont_cost(self.data, pos, id, phase) * BYTE_SCALE + match self.lambda {
+            0 => 0,
+            lambda => lambda * gen.get_cont_cycles(self.data, pos, id, phase),
+        }
+    }
+    
+    fn phase_margin(&self, gen: &dyn GraphFuncs, base: usize, id: usize, period: usize) -> (bool, isize) {
+        // How much more than the cheapest state of a run another state can cost and still be worth
+        // keeping, base being a position where the run's phase is 0
+        // When continuing only costs extra on the byte a new period starts, a state further into
+        // its instruction has that cost come sooner, so it's only worth keeping if it's cheaper
+        // than every state before it. And a state less far in is no better than the cheapest one
+        // once that one has paid the extra cost
+        let costs: Vec<isize> = (0..period).map(|phase| self.phase_cost(gen, base + phase, id, phase)).collect();
+        if costs[1..].iter().all(|&cost| cost == costs[period-1]) && costs[0] >= costs[period-1] {
+            return (true, cmp::max(costs[0] - costs[period-1], 1));
+        }
+        // Otherwise, costs from any phase over any number of bytes stay within a band around the
+        // average cost per byte, two states can't differ by more than twice its width
+        let total: isize = costs.iter().sum();
+        let mut sum = 0;
+        let (mut low, mut high) = (0, 0);
+        for step in 1..=period {
+            sum += costs[step % period];
+            let dev = sum * period as isize - total * step as isize;
+            low = cmp::min(low, dev);
+            high = cmp::max(high, dev);
+        }
+        (false, 2 * (high - low) / period as isize + 1)
+    }
+    
+    fn gen_state_instrs(&self, graph: Vec<Vec<StateNode>>) -> Vec<Box<dyn CycleInstr>> {
+        
+        // Follow path backwards from the cheapest final state
+        let mut list: Vec<Box<dyn CycleInstr>> = Vec::new();
+        let mut ind = match graph[graph.len()-1].iter().enumerate().min_by_key(|(_, node)| node.cost) {
+            Some((ind, _)) => ind,
+            None => return list,
+        };
+        let mut len = 0;
+        for pos in (1..graph.len()).rev() {
+            let node = &graph[pos][ind];
+            len += 1;
+            if node.entered {
+                // This is the first byte of the instruction
+                list.push(self.itypes[node.instr_type].gen_instr(self.data, pos-1, node.id, len));
+                len = 0;
+            }
+            ind = node.from;
+        }
+        
+        list.into_iter().rev().collect()
+        
+    }
+    
     fn make_graph(&self) -> Vec<Vec<Node>> {
         // Most of the magic is done in here
         // This function figures which instructions should be used at what times utilizing a
@@ -143,7 +440,7 @@ impl GraphSolve<'_> {
                             false => entr_costs[to_ind]
                         };
                         if to_node.cost == -1 || to_node.cost > to_cost {
-                            to_node.cost = to_cost as isize;
+                            to_node.cost = to_cost;
                             to_node.from = from_ind as isize;
                             // Set relative position
                             if from_ind as isize == to_node.prev_ind {
@@ -169,7 +466,7 @@ impl GraphSolve<'_> {
         // Follow path backwards and construct the list of instruction bytecodes
         let mut list: Vec<Box<dyn CycleInstr>> = Vec::new();
         // Get finishing instruction with the lowest total cost
-        let mut min_cost = isize::max_value();
+        let mut min_cost = isize::MAX;
         let mut cur_node = &graph[graph.len()-1][0];
         for node in graph[graph.len()-1].iter() {
             if node.cost < min_cost {
@@ -194,9 +491,7 @@ impl GraphSolve<'_> {
         }
 
         // Reverse list
-        let list = list.into_iter().rev().collect();
-        
-        list
+        list.into_iter().rev().collect()
     }
     
     
@@ -5,11 +5,43 @@ use crate::compress::instr::{Instr, InstrGen};
 use crate::compress::cycle_limit::{CycleInstr};
 //use num::integer::Integer;
 
-pub fn compress(data: &[u8]) -> Vec<Box<dyn CycleInstr>> {
+pub fn compress(data: &[u8], prev: Option<&[u8]>, target: Option<&[u8]>, fill: bool, cycle_limit: usize, other_cycles: &dyn Fn(isize) -> usize) -> Vec<Box<dyn CycleInstr>> {
     
-    graph_solve::compress(data, 
-            vec![Box::new(STREAM_INSTRGEN),   Box::new(LZSS_INSTRGEN),   Box::new(ALTFLIP_INSTRGEN),   Box::new(ALTWHITE_INSTRGEN),   Box::new(ALTBLACK_INSTRGEN)],
-            vec![Box::new(STREAM_GRAPHFUNCS), Box::new(LZSS_GRAPHFUNCS), Box::new(ALTFLIP_GRAPHFUNCS), Box::new(ALTWHITE_GRAPHFUNCS), Box::new(ALTBLACK_GRAPHFUNCS)])
+    let (instr_types, graph_types) = get_types(data, prev, target, fill);
+    graph_solve::compress(data, &instr_types, &graph_types, cycle_limit, other_cycles)
+    
+}
+
+pub fn compress_pruned(data: &[u8], prev: Option<&[u8]>, target: Option<&[u8]>, fill: bool) -> Vec<Box<dyn CycleInstr>> {
+    
+    // Same as compress but with the older graph solver, only used to benchmark against
+    let (instr_types, graph_types) = get_types(data, prev, target, fill);
+    graph_solve::compress_pruned(data, &instr_types, &graph_types)
+    
+}
+
+type InstrTypes = (Vec<Box<dyn InstrGen<dyn CycleInstr>>>, Vec<Box<dyn GraphFuncs>>);
+
+fn get_types(data: &[u8], prev: Option<&[u8]>, target: Option<&[u8]>, fill: bool) -> InstrTypes {
+    
+    let mut instr_types: Vec<Box<dyn InstrGen<dyn CycleInstr>>> = vec![Box::new(STREAM_INSTRGEN),   Box::new(LZSS_INSTRGEN),   Box::new(ALTFLIP_INSTRGEN),   Box::new(ALTWHITE_INSTRGEN),   Box::new(ALTBLACK_INSTRGEN)];
+    let mut graph_types: Vec<Box<dyn GraphFuncs>> = vec![Box::new(STREAM_GRAPHFUNCS), Box::new(LZSSGraphFuncs::new(data)), Box::new(ALTFLIP_GRAPHFUNCS), Box::new(ALTWHITE_GRAPHFUNCS), Box::new(ALTBLACK_GRAPHFUNCS)];
+    // Temporal references are only available when we know what the previous frame decoded to
+    if let Some(prev) = prev {
+        instr_types.push(Box::new(TEMPORAL_INSTRGEN));
+        graph_types.push(Box::new(TemporalGraphFuncs::new(data, prev)));
+    }
+    // Same for the image left over in the buffer we are decompressing into
+    if let Some(target) = target {
+        instr_types.push(Box::new(SKIP_INSTRGEN));
+        graph_types.push(Box::new(SkipGraphFuncs::new(data, target)));
+    }
+    // Fills need a player assembled with dec_fill
+    if fill {
+        instr_types.push(Box::new(FILL_INSTRGEN));
+        graph_types.push(Box::new(FILL_GRAPHFUNCS));
+    }
+    (instr_types, graph_types)
     
 }
 
@@ -26,7 +58,7 @@ impl InstrGen<dyn CycleInstr> for LZSSInstrGen {
     fn gen_instr(&self, data: &[u8], pos: usize, offset: usize, len: usize) -> Box<dyn CycleInstr> {
         let decomp: Vec<u8> = data[pos..pos+len].to_vec();
         let numsplit = num::Integer::div_ceil(&len, &LZSS_MAX_LEN);
-        Box::new(LZSSInstr {decomp: decomp, offset: offset, len: len, numsplit: numsplit})
+        Box::new(LZSSInstr {decomp, offset, len, numsplit})
     }
 }
 
@@ -78,7 +110,7 @@ impl CycleInstr for LZSSInstr {
     fn get_cycles(&self) -> usize {
         (55 + 40 + match self.offset >= 128 {
             false => 20,
-            true => 36,
+            true => 50,
         } + 58 + 20) * self.numsplit + (21 * self.len - 5 * self.numsplit)
     }
     fn is_minimum(&self) -> bool {
@@ -92,34 +124,63 @@ impl CycleInstr for LZSSInstr {
     fn combine_with_left(&mut self, _other: &dyn CycleInstr) {}
 }
 
-const LZSS_GRAPHFUNCS: LZSSGraphFuncs = LZSSGraphFuncs{};
+// Matches are found through a hash chain linking each position to the last one starting with the
+// same two bytes. The chain is walked nearest first, keeping the longest few matches in each offset
+// class since a match of the same class is just as cheap however far back it is
+const LZSS_SHORT_OFFSET: usize = 128;   // Offsets below this fit in one byte
+const LZSS_CANDIDATES: usize = 4;       // Matches returned per offset class
+const LZSS_MAX_CHAIN: usize = 256;      // Positions checked per offset class
+const NO_MATCH: usize = usize::MAX;
+
 struct LZSSGraphFuncs {
+    prev_match: Vec<usize>,     // Previous position starting with the same two bytes
+}
+impl LZSSGraphFuncs {
+    fn new(data: &[u8]) -> LZSSGraphFuncs {
+        let mut last = vec![NO_MATCH; 0x10000];
+        let mut prev_match = vec![NO_MATCH; data.len()];
+        for pos in 0..data.len().saturating_sub(1) {
+            let key = ((data[pos] as usize) << 8) + data[pos+1] as usize;
+            prev_match[pos] = last[key];
+            last[key] = pos;
+        }
+        LZSSGraphFuncs {prev_match}
+    }
 }
 impl GraphFuncs for LZSSGraphFuncs {
     fn get_instr_info(&self, data: &[u8], pos: usize) -> Vec<(usize, usize)> {   // Returns Vec<len, id>
-        // Search through data stream for all occurances that match our current position (over a
-        // certain length)
-        let mut list: Vec<(usize, usize)> = Vec::new();
-        for from_start in 0..pos {
-            let mut len = 0;
-            for to in pos..data.len() {
-                let from = (to - pos) + from_start;
-                if data[to] == data[from] {
+        let remaining = data.len() - pos;
+        let mut classes: [Vec<(usize, usize)>; 2] = [Vec::new(), Vec::new()];     // Short & long offsets, longest first
+        let mut checked = [0; 2];
+        let mut from = self.prev_match[pos];
+        while from != NO_MATCH {
+            let offset = pos - from;  // offset will be the unique identifier
+            let class = (offset >= LZSS_SHORT_OFFSET) as usize;
+            let list = &mut classes[class];
+            // Stop checking a class once it's checked enough positions or can't find anything longer
+            let done = checked[class] >= LZSS_MAX_CHAIN || (list.len() >= LZSS_CANDIDATES && list[LZSS_CANDIDATES-1].0 >= remaining);
+            if !done {
+                checked[class] += 1;
+                // Matches can run into the data they're copying
+                let mut len = 2;
+                while len < remaining && data[from+len] == data[pos+len] {
                     len += 1;
-                } else {
-                    break;
                 }
-            }
-            if len >= 2 {
-                let offset = pos - from_start;  // offset will be the unique identifier
-                list.push((len, offset));
-                // Make sure not to overload the graph with LZSS instructions
-                if list.len() >= 8 {
-                    break;
+                // Keep the longest, nearer matches win ties as they were found first
+                let ind = list.iter().position(|&(l, _)| l < len).unwrap_or(list.len());
+                if ind < LZSS_CANDIDATES {
+                    list.insert(ind, (len, offset));
+                    list.truncate(LZSS_CANDIDATES);
                 }
+            } else if class == 1 {
+                // Offsets only grow from here
+                break;
             }
+            from = self.prev_match[from];
         }
-        list
+        let [mut short, long] = classes;
+        short.extend(long);
+        short
     }
     fn get_step_cost(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
         0
@@ -130,6 +191,25 @@ impl GraphFuncs for LZSSGraphFuncs {
             false => 2,
         }
     }
+    fn get_cost_period(&self) -> usize {
+        LZSS_MAX_LEN
+    }
+    fn get_cost_class(&self, off: usize) -> usize {
+        (off >= 128) as usize
+    }
+    fn get_entry_cycles(&self, _data: &[u8], _pos: usize, off: usize) -> isize {
+        // Same as LZSSInstr's cycles, split up per byte
+        (55 + 40 + match off >= 128 {
+            false => 20,
+            true => 50,
+        } + 58 + 20 - 5) + 21
+    }
+    fn get_cont_cycles(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
+        match rel_pos % LZSS_MAX_LEN {
+            0 => self.get_entry_cycles(data, pos, uid),
+            _ => 21
+        }
+    }
     fn get_cont_cost(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
         match rel_pos % LZSS_MAX_LEN {
             0 => self.get_entry_cost(data, pos, uid),
@@ -145,6 +225,423 @@ impl GraphFuncs for LZSSGraphFuncs {
 
 
 
+/*          Temporal Instruction        */
+
+// Copies a run from the previously decoded frame (the image buffer currently being displayed by
+// the player). Shares the LZSS header byte, the offset byte then has the two-byte flag set and an
+// upper value of $40-$7E, which gives the displacement from the same position in the previous
+// frame in steps of 2 bytes (one row) so both bit-planes stay aligned
+
+const TEMPORAL_MAX_LEN: usize = LZSS_MAX_LEN;
+const TEMPORAL_MAX_DISP: usize = 31;        // In rows
+const TEMPORAL_DISP_BASE: usize = 0x5F;     // Offset byte value for a displacement of 0
+const TEMPORAL_MAX_CANDIDATES: usize = 8;
+
+const TEMPORAL_INSTRGEN: TemporalInstrGen = TemporalInstrGen{};
+struct TemporalInstrGen {
+}
+impl InstrGen<dyn CycleInstr> for TemporalInstrGen {
+    fn gen_instr(&self, data: &[u8], pos: usize, disp: usize, len: usize) -> Box<dyn CycleInstr> {
+        if len < 2 {
+            // A length of 1 would be read as the end of the image, just use a stream
+            return STREAM_INSTRGEN.gen_instr(data, pos, 0, len);
+        }
+        let decomp: Vec<u8> = data[pos..pos+len].to_vec();
+        let numsplit = num::Integer::div_ceil(&len, &TEMPORAL_MAX_LEN);
+        Box::new(TemporalInstr {decomp, disp, len, numsplit})
+    }
+}
+
+struct TemporalInstr {
+    decomp: Vec<u8>,
+    disp: usize,    // Displacement as it is stored in the offset byte
+    len: usize,
+    numsplit: usize,
+}
+impl Instr for TemporalInstr {
+    fn gen_bytecode(&self, last: bool) -> Vec<u8> {
+        // Generate temporal bytecode
+        let mut bytecode: Vec<u8> = Vec::new();
+        let max_put_len = num::Integer::div_ceil(&self.len, &self.numsplit);
+        let mut len = self.len;
+        for _i in 0..self.numsplit {
+            let put_len = cmp::min(len, max_put_len);
+            bytecode.push(((put_len - 1) as u8) << 3);
+            bytecode.push(((self.disp as u8) << 1) + 1);
+            len -= put_len;
+        }
+        if last {
+            bytecode.push(0x00);
+        }
+        bytecode
+    }
+    fn get_comp_size(&self) -> usize {
+        self.numsplit * 2
+    }
+    fn get_decomp_size(&self) -> usize {
+        self.len
+    }
+    fn get_decomp(&self) -> Vec<u8> {
+        self.decomp.clone()
+    }
+}
+impl CycleInstr for TemporalInstr {
+    fn get_cycles(&self) -> usize {
+        // Assumes the previous buffer has to be wrapped around, which is the slower path
+        (55 + 40 + 26 + 21 + 27 + 19 + 11 + 11 + 10 + 11 + 4 + 25 + 10 + 20) * self.numsplit + (21 * self.len - 5 * self.numsplit)
+    }
+    fn is_minimum(&self) -> bool {
+        false
+    }
+    fn to_minimum(&self) -> Box<dyn CycleInstr> {
+        // Generate stream instruction from our data
+        STREAM_INSTRGEN.gen_instr(&self.decomp, 0, 0, self.len)
+    }
+    fn combine_with_right(&mut self, _other: &dyn CycleInstr) {}
+    fn combine_with_left(&mut self, _other: &dyn CycleInstr) {}
+}
+
+struct TemporalGraphFuncs {
+    runs: Vec<Vec<usize>>,  // By displacement, how long the data matches the previous frame from each position
+}
+impl TemporalGraphFuncs {
+    fn new(data: &[u8], prev: &[u8]) -> TemporalGraphFuncs {
+        // Runs are counted back from the end so each position only gets compared once
+        let runs = (0..(TEMPORAL_MAX_DISP*2 + 1)).map(|row| {
+            let disp = (row as isize - TEMPORAL_MAX_DISP as isize) * 2;
+            let mut lens = vec![0; data.len() + 1];
+            for pos in (0..data.len()).rev() {
+                let from = pos as isize + disp;
+                if from >= 0 && (from as usize) < prev.len() && data[pos] == prev[from as usize] {
+                    lens[pos] = lens[pos+1] + 1;
+                }
+            }
+            lens
+        }).collect();
+        TemporalGraphFuncs {runs}
+    }
+}
+impl GraphFuncs for TemporalGraphFuncs {
+    fn get_instr_info(&self, _data: &[u8], pos: usize) -> Vec<(usize, usize)> {   // Returns Vec<len, id>
+        // Check each displacement into the previous frame for a run matching our current position
+        let mut list: Vec<(usize, usize)> = Vec::new();
+        for (row, lens) in self.runs.iter().enumerate() {
+            let len = lens[pos];
+            if len >= 2 {
+                list.push((len, TEMPORAL_DISP_BASE - TEMPORAL_MAX_DISP + row));
+            }
+        }
+        // Only keep the longest runs so the graph doesn't get overloaded, ties staying in order
+        if list.len() > TEMPORAL_MAX_CANDIDATES {
+            list.select_nth_unstable_by_key(TEMPORAL_MAX_CANDIDATES, |&(len, disp)| (cmp::Reverse(len), disp));
+            list.truncate(TEMPORAL_MAX_CANDIDATES);
+        }
+        list.sort_unstable_by_key(|&(len, disp)| (cmp::Reverse(len), disp));
+        list
+    }
+    fn get_step_cost(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
+        0
+    }
+    fn get_entry_cost(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
+        2
+    }
+    fn get_cost_period(&self) -> usize {
+        TEMPORAL_MAX_LEN
+    }
+    fn get_cost_class(&self, _uid: usize) -> usize {
+        0
+    }
+    fn get_entry_cycles(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
+        (55 + 40 + 26 + 21 + 27 + 19 + 11 + 11 + 10 + 11 + 4 + 25 + 10 + 20 - 5) + 21
+    }
+    fn get_cont_cycles(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
+        match rel_pos % TEMPORAL_MAX_LEN {
+            0 => self.get_entry_cycles(data, pos, uid),
+            _ => 21
+        }
+    }
+    fn get_cont_cost(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
+        match rel_pos % TEMPORAL_MAX_LEN {
+            0 => self.get_entry_cost(data, pos, uid),
+            _ => 0
+        }
+    }
+}
+
+
+
+
+
+
+
+
+/*          Skip Instruction        */
+
+// Leaves a run of bytes as they already are in the image buffer being decompressed into. The player
+// rotates between three image buffers, so this is whatever was decoded three frames ago. Shares the
+// LZSS header byte with an offset byte of $FF
+
+const SKIP_MAX_LEN: usize = LZSS_MAX_LEN;
+
+const SKIP_INSTRGEN: SkipInstrGen = SkipInstrGen{};
+struct SkipInstrGen {
+}
+impl InstrGen<dyn CycleInstr> for SkipInstrGen {
+    fn gen_instr(&self, data: &[u8], pos: usize, _: usize, len: usize) -> Box<dyn CycleInstr> {
+        if len < 2 {
+            // A length of 1 would be read as the end of the image, just use a stream
+            return STREAM_INSTRGEN.gen_instr(data, pos, 0, len);
+        }
+        let decomp: Vec<u8> = data[pos..pos+len].to_vec();
+        let numsplit = num::Integer::div_ceil(&len, &SKIP_MAX_LEN);
+        Box::new(SkipInstr {decomp, len, numsplit})
+    }
+}
+
+struct SkipInstr {
+    decomp: Vec<u8>,
+    len: usize,
+    numsplit: usize,
+}
+impl Instr for SkipInstr {
+    fn gen_bytecode(&self, last: bool) -> Vec<u8> {
+        // Generate skip bytecode
+        let mut bytecode: Vec<u8> = Vec::new();
+        let max_put_len = num::Integer::div_ceil(&self.len, &self.numsplit);
+        let mut len = self.len;
+        for _i in 0..self.numsplit {
+            let put_len = cmp::min(len, max_put_len);
+            bytecode.push(((put_len - 1) as u8) << 3);
+            bytecode.push(0xFF);
+            len -= put_len;
+        }
+        if last {
+            bytecode.push(0x00);
+        }
+        bytecode
+    }
+    fn get_comp_size(&self) -> usize {
+        self.numsplit * 2
+    }
+    fn get_decomp_size(&self) -> usize {
+        self.len
+    }
+    fn get_decomp(&self) -> Vec<u8> {
+        self.decomp.clone()
+    }
+}
+impl CycleInstr for SkipInstr {
+    fn get_cycles(&self) -> usize {
+        // Nothing gets copied, so the cost doesn't depend on the length
+        (55 + 40 + 26 + 26 + 19 + 11 + 4 + 20) * self.numsplit
+    }
+    fn is_minimum(&self) -> bool {
+        false
+    }
+    fn to_minimum(&self) -> Box<dyn CycleInstr> {
+        // Only cheaper for very short runs
+        STREAM_INSTRGEN.gen_instr(&self.decomp, 0, 0, self.len)
+    }
+    fn combine_with_right(&mut self, _other: &dyn CycleInstr) {}
+    fn combine_with_left(&mut self, _other: &dyn CycleInstr) {}
+}
+
+struct SkipGraphFuncs {
+    runs: Vec<usize>,   // How long the data stays unchanged from what's already in the buffer from each position
+}
+impl SkipGraphFuncs {
+    fn new(data: &[u8], target: &[u8]) -> SkipGraphFuncs {
+        let mut runs = vec![0; data.len() + 1];
+        for pos in (0..data.len()).rev() {
+            if data[pos] == target[pos] {
+                runs[pos] = runs[pos+1] + 1;
+            }
+        }
+        SkipGraphFuncs {runs}
+    }
+}
+impl GraphFuncs for SkipGraphFuncs {
+    fn get_instr_info(&self, _data: &[u8], pos: usize) -> Vec<(usize, usize)> {   // Returns Vec<len, id>
+        let len = self.runs[pos];
+        match len >= 2 {
+            true => vec![(len, 0)],
+            false => Vec::new(),
+        }
+    }
+    fn get_step_cost(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
+        0
+    }
+    fn get_entry_cost(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
+        2
+    }
+    fn get_cost_period(&self) -> usize {
+        SKIP_MAX_LEN
+    }
+    fn get_cost_class(&self, _uid: usize) -> usize {
+        0
+    }
+    fn get_entry_cycles(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
+        55 + 40 + 26 + 26 + 19 + 11 + 4 + 20
+    }
+    fn get_cont_cycles(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
+        match rel_pos % SKIP_MAX_LEN {
+            0 => self.get_entry_cycles(data, pos, uid),
+            _ => 0
+        }
+    }
+    fn get_cont_cost(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
+        match rel_pos % SKIP_MAX_LEN {
+            0 => self.get_entry_cost(data, pos, uid),
+            _ => 0
+        }
+    }
+}
+
+
+
+
+
+
+
+
+/*          Fill Instruction        */
+
+// Fills a run with a repeating pair of bytes, one for each bit-plane, so it covers solid shades as
+// well as any pattern repeating every row. Shares the LZSS header byte with an offset byte of $7F,
+// followed by the pair. The player writes the pair once then copies the rest from it
+
+const FILL_MAX_LEN: usize = LZSS_MAX_LEN;
+const FILL_ID: u8 = 0x7F;       // Offset byte
+
+const FILL_INSTRGEN: FillInstrGen = FillInstrGen{};
+struct FillInstrGen {
+}
+impl InstrGen<dyn CycleInstr> for FillInstrGen {
+    fn gen_instr(&self, data: &[u8], pos: usize, _: usize, len: usize) -> Box<dyn CycleInstr> {
+        if len < 2 {
+            // A length of 1 would be read as the end of the image, just use a stream
+            return STREAM_INSTRGEN.gen_instr(data, pos, 0, len);
+        }
+        let decomp: Vec<u8> = data[pos..pos+len].to_vec();
+        let numsplit = num::Integer::div_ceil(&len, &FILL_MAX_LEN);
+        Box::new(FillInstr {decomp, len, numsplit})
+    }
+}
+
+struct FillInstr {
+    decomp: Vec<u8>,
+    len: usize,
+    numsplit: usize,
+}
+impl Instr for FillInstr {
+    fn gen_bytecode(&self, last: bool) -> Vec<u8> {
+        // Generate fill bytecode
+        let mut bytecode: Vec<u8> = Vec::new();
+        let max_put_len = num::Integer::div_ceil(&self.len, &self.numsplit);
+        let mut pos = 0;
+        for _i in 0..self.numsplit {
+            let put_len = cmp::min(self.len - pos, max_put_len);
+            bytecode.push(((put_len - 1) as u8) << 3);
+            bytecode.push(FILL_ID);
+            // Each split starts with the pair in the order it lands in
+            bytecode.push(self.decomp[pos]);
+            bytecode.push(self.decomp[pos+1]);
+            pos += put_len;
+        }
+        if last {
+            bytecode.push(0x00);
+        }
+        bytecode
+    }
+    fn get_comp_size(&self) -> usize {
+        self.numsplit * 4
+    }
+    fn get_decomp_size(&self) -> usize {
+        self.len
+    }
+    fn get_decomp(&self) -> Vec<u8> {
+        self.decomp.clone()
+    }
+}
+impl CycleInstr for FillInstr {
+    fn get_cycles(&self) -> usize {
+        // A length of 2 is done after writing the pair
+        let max_put_len = num::Integer::div_ceil(&self.len, &self.numsplit);
+        let mut cycles = 0;
+        let mut len = self.len;
+        for _i in 0..self.numsplit {
+            let put_len = cmp::min(len, max_put_len);
+            cycles += 55 + 40 + 26 + 12 + 52 + match put_len > 2 {
+                true => 31 + 21 * (put_len - 2) - 5 + 20,
+                false => 0,
+            };
+            len -= put_len;
+        }
+        cycles
+    }
+    fn is_minimum(&self) -> bool {
+        false
+    }
+    fn to_minimum(&self) -> Box<dyn CycleInstr> {
+        // Generate stream instruction from our data
+        STREAM_INSTRGEN.gen_instr(&self.decomp, 0, 0, self.len)
+    }
+    fn combine_with_right(&mut self, _other: &dyn CycleInstr) {}
+    fn combine_with_left(&mut self, _other: &dyn CycleInstr) {}
+}
+
+const FILL_GRAPHFUNCS: FillGraphFuncs = FillGraphFuncs{};
+struct FillGraphFuncs {
+}
+impl GraphFuncs for FillGraphFuncs {
+    fn get_instr_info(&self, data: &[u8], pos: usize) -> Vec<(usize, usize)> {   // Returns Vec<len, id>
+        // Find how long the pair starting here keeps repeating, shorter runs are cheaper streamed
+        let mut len = 0;
+        while pos + len < data.len() && data[pos+len] == data[pos + len%2] {
+            len += 1;
+        }
+        match len >= 3 {
+            true => vec![(len, 0)],
+            false => Vec::new(),
+        }
+    }
+    fn get_step_cost(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
+        0
+    }
+    fn get_entry_cost(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
+        4
+    }
+    fn get_cost_period(&self) -> usize {
+        FILL_MAX_LEN
+    }
+    fn get_cost_class(&self, _uid: usize) -> usize {
+        0
+    }
+    fn get_entry_cycles(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
+        // Same as FillInstr's cycles for runs over 2, split up per byte
+        (55 + 40 + 26 + 12 + 52 + 31 - 42 - 5 + 20) + 21
+    }
+    fn get_cont_cycles(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
+        match rel_pos % FILL_MAX_LEN {
+            0 => self.get_entry_cycles(data, pos, uid),
+            _ => 21
+        }
+    }
+    fn get_cont_cost(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
+        match rel_pos % FILL_MAX_LEN {
+            0 => self.get_entry_cost(data, pos, uid),
+            _ => 0
+        }
+    }
+}
+
+
+
+
+
+
+
+
 /*          Stream Instruction      */
 
 const STREAM_MAX_LEN: usize = 64;
@@ -157,7 +654,7 @@ impl InstrGen<dyn CycleInstr> for StreamInstrGen {
         // Copy data straight with no compression
         let stream: Vec<u8> = data[pos..pos+len].to_vec();
         let numsplit = num::Integer::div_ceil(&len, &STREAM_MAX_LEN);
-        Box::new(StreamInstr {stream: stream, len: len, numsplit: numsplit})
+        Box::new(StreamInstr {stream, len, numsplit})
     }
 }
 
@@ -240,6 +737,21 @@ impl GraphFuncs for StreamGraphFuncs {
     fn get_entry_cost(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
         1
     }
+    fn get_cost_period(&self) -> usize {
+        STREAM_MAX_LEN
+    }
+    fn get_cost_class(&self, _uid: usize) -> usize {
+        0
+    }
+    fn get_entry_cycles(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
+        (69 - 5) + 21
+    }
+    fn get_cont_cycles(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
+        match rel_pos % STREAM_MAX_LEN {
+            0 => self.get_entry_cycles(data, pos, uid),
+            _ => 21
+        }
+    }
     fn get_cont_cost(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
         match rel_pos % STREAM_MAX_LEN {
             0 => self.get_entry_cost(data, pos, uid),
@@ -266,7 +778,7 @@ impl InstrGen<dyn CycleInstr> for AltFlipInstrGen {
     fn gen_instr(&self, data: &[u8], pos: usize, parity: usize, len: usize) -> Box<dyn CycleInstr> {
         let decomp: Vec<u8> = data[pos..pos+len].to_vec();
         let numsplit = num::Integer::div_ceil(&len, &ALT_FLIP_MAX_LEN);
-        Box::new(AltFlipInstr {decomp: decomp, parity: parity, pos: pos, len: len, numsplit: numsplit})
+        Box::new(AltFlipInstr {decomp, parity, pos, len, numsplit})
     }
 }
 
@@ -322,7 +834,7 @@ impl CycleInstr for AltFlipInstr {
         let mut len = self.len;
         for _i in 0..self.numsplit {
             let put_len = cmp::min(len, ALT_FLIP_MAX_LEN);
-            cycles += 70 + match put_len % 2 == 0 {
+            cycles += 80 + match put_len.is_multiple_of(2) {
                 true => 67 * (put_len / 2) - 5,
                 false => 42 + 67 * (put_len / 2),
             };
@@ -347,10 +859,9 @@ struct AltFlipGraphFuncs {
 impl GraphFuncs for AltFlipGraphFuncs {
     fn get_instr_info(&self, data: &[u8], pos: usize) -> Vec<(usize, usize)> {   // Returns Vec<len, id>
         let mut list: Vec<(usize, usize)> = Vec::new();
-        match search_alt(data, pos, 'f', false) {
-            Some(len) => list.push((len, pos % 2)),
-            None => {}
-        };
+        if let Some(len) = search_alt(data, pos, 'f', false) {
+            list.push((len, pos % 2));
+        }
         list
     }
     fn get_step_cost(&self, _data: &[u8], pos: usize, uid: usize) -> isize {
@@ -365,6 +876,23 @@ impl GraphFuncs for AltFlipGraphFuncs {
             false => 999999,
         }
     }
+    fn get_cost_period(&self) -> usize {
+        ALT_FLIP_MAX_LEN
+    }
+    fn get_cost_class(&self, uid: usize) -> usize {
+        uid
+    }
+    fn get_entry_cycles(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
+        80 + 42
+    }
+    fn get_cont_cycles(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
+        // Every second byte finishes a pair
+        match rel_pos % ALT_FLIP_MAX_LEN {
+            0 => self.get_entry_cycles(data, pos, uid),
+            rel_pos if rel_pos % 2 == 1 => 67 - 5 - 42,
+            _ => 42 + 5,
+        }
+    }
     fn get_cont_cost(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
         match rel_pos % ALT_FLIP_MAX_LEN {
             0 => self.get_entry_cost(data, pos, uid),
@@ -391,7 +919,7 @@ impl InstrGen<dyn CycleInstr> for AltWhiteInstrGen {
     fn gen_instr(&self, data: &[u8], pos: usize, parity: usize, len: usize) -> Box<dyn CycleInstr> {
         let decomp: Vec<u8> = data[pos..pos+len].to_vec();
         let numsplit = num::Integer::div_ceil(&len, &ALT_WHITE_MAX_LEN);
-        Box::new(AltWhiteInstr {decomp: decomp, parity: parity, pos: pos, len: len, numsplit: numsplit})
+        Box::new(AltWhiteInstr {decomp, parity, pos, len, numsplit})
     }
 }
 
@@ -447,14 +975,15 @@ impl CycleInstr for AltWhiteInstr {
         let mut len = self.len;
         for _i in 0..self.numsplit {
             let put_len = cmp::min(len, ALT_WHITE_MAX_LEN);
-            cycles += 68 + 56 * (put_len / 2) + ((match self.pos + self.parity % 2 {
+            // Starts on a data byte when it lines up with the parity
+            cycles += 77 + 63 * (put_len / 2) + ((match (self.pos + self.parity) % 2 {
                 0 => (match put_len % 2 {
                     1 => 42,
                     _ => -5,
                 }) + 7,
                 _ => (match put_len % 2 {
                     1 => 21,
-                    _ => 12,
+                    _ => 5,
                 }) + 12,
             } + 14) as usize);
             len -= put_len;
@@ -478,14 +1007,12 @@ struct AltWhiteGraphFuncs {
 impl GraphFuncs for AltWhiteGraphFuncs {
     fn get_instr_info(&self, data: &[u8], pos: usize) -> Vec<(usize, usize)> {   // Returns Vec<len, id>
         let mut list: Vec<(usize, usize)> = Vec::new();
-        match search_alt(data, pos, 'w', false) {
-            Some(len) => list.push((len, pos % 2)),
-            None => {}
-        };
-        match search_alt(data, pos, 'w', true) {
-            Some(len) => list.push((len, (pos + 1) % 2)),
-            None => {}
-        };
+        if let Some(len) = search_alt(data, pos, 'w', false) {
+            list.push((len, pos % 2));
+        }
+        if let Some(len) = search_alt(data, pos, 'w', true) {
+            list.push((len, (pos + 1) % 2));
+        }
         list
     }
     fn get_step_cost(&self, _data: &[u8], pos: usize, uid: usize) -> isize {
@@ -497,6 +1024,26 @@ impl GraphFuncs for AltWhiteGraphFuncs {
     fn get_entry_cost(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
         1
     }
+    fn get_cost_period(&self) -> usize {
+        ALT_WHITE_MAX_LEN
+    }
+    fn get_cost_class(&self, uid: usize) -> usize {
+        uid
+    }
+    fn get_entry_cycles(&self, _data: &[u8], pos: usize, uid: usize) -> isize {
+        77 + 14 + match pos % 2 == uid {
+            true => 7 + 42,
+            false => 12 + 21,
+        }
+    }
+    fn get_cont_cycles(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
+        // Data bytes take longer than the constant bytes between them
+        match rel_pos % ALT_WHITE_MAX_LEN {
+            0 => self.get_entry_cycles(data, pos, uid),
+            _ if pos % 2 == uid => 47,
+            _ => 16,
+        }
+    }
     fn get_cont_cost(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
         match rel_pos % ALT_WHITE_MAX_LEN {
             0 => self.get_entry_cost(data, pos, uid),
@@ -523,7 +1070,7 @@ impl InstrGen<dyn CycleInstr> for AltBlackInstrGen {
     fn gen_instr(&self, data: &[u8], pos: usize, parity: usize, len: usize) -> Box<dyn CycleInstr> {
         let decomp: Vec<u8> = data[pos..pos+len].to_vec();
         let numsplit = num::Integer::div_ceil(&len, &ALT_BLACK_MAX_LEN);
-        Box::new(AltBlackInstr {decomp: decomp, parity: parity, pos: pos, len: len, numsplit: numsplit})
+        Box::new(AltBlackInstr {decomp, parity, pos, len, numsplit})
     }
 }
 
@@ -579,14 +1126,15 @@ impl CycleInstr for AltBlackInstr {
         let mut len = self.len;
         for _i in 0..self.numsplit {
             let put_len = cmp::min(len, ALT_BLACK_MAX_LEN);
-            cycles += 73 + 56 * (put_len / 2) + ((match self.pos + self.parity % 2 {
+            // Starts on a data byte when it lines up with the parity
+            cycles += 76 + 63 * (put_len / 2) + ((match (self.pos + self.parity) % 2 {
                 0 => (match put_len % 2 {
                     1 => 42,
                     _ => -5,
                 }) + 7,
                 _ => (match put_len % 2 {
                     1 => 21,
-                    _ => 12,
+                    _ => 5,
                 }) + 12,
             } + 14) as usize);
             len -= put_len;
@@ -610,14 +1158,12 @@ struct AltBlackGraphFuncs {
 impl GraphFuncs for AltBlackGraphFuncs {
     fn get_instr_info(&self, data: &[u8], pos: usize) -> Vec<(usize, usize)> {   // Returns Vec<len, id>
         let mut list: Vec<(usize, usize)> = Vec::new();
-        match search_alt(data, pos, 'b', false) {
-            Some(len) => list.push((len, pos % 2)),
-            None => ()
-        };
-        match search_alt(data, pos, 'b', true) {
-            Some(len) => list.push((len, (pos + 1) % 2)),
-            None => {}
-        };
+        if let Some(len) = search_alt(data, pos, 'b', false) {
+            list.push((len, pos % 2));
+        }
+        if let Some(len) = search_alt(data, pos, 'b', true) {
+            list.push((len, (pos + 1) % 2));
+        }
         list
     }
     fn get_step_cost(&self, _data: &[u8], pos: usize, uid: usize) -> isize {
@@ -629,6 +1175,26 @@ impl GraphFuncs for AltBlackGraphFuncs {
     fn get_entry_cost(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
         1
     }
+    fn get_cost_period(&self) -> usize {
+        ALT_BLACK_MAX_LEN
+    }
+    fn get_cost_class(&self, uid: usize) -> usize {
+        uid
+    }
+    fn get_entry_cycles(&self, _data: &[u8], pos: usize, uid: usize) -> isize {
+        76 + 14 + match pos % 2 == uid {
+            true => 7 + 42,
+            false => 12 + 21,
+        }
+    }
+    fn get_cont_cycles(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
+        // Data bytes take longer than the constant bytes between them
+        match rel_pos % ALT_BLACK_MAX_LEN {
+            0 => self.get_entry_cycles(data, pos, uid),
+            _ if pos % 2 == uid => 47,
+            _ => 16,
+        }
+    }
     fn get_cont_cost(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
         match rel_pos % ALT_BLACK_MAX_LEN {
             0 => self.get_entry_cost(data, pos, uid),
@@ -674,3 +1240,125 @@ fn search_alt(data: &[u8], mut pos: usize, typ: char, start_const: bool) -> Opti
 }
 
 
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompress;
+    
+    fn random(len: usize, seed: u32) -> Vec<u8> {
+        // Few enough distinct bytes that every instruction gets a chance to be used
+        const BYTES: [u8; 4] = [0x00, 0xFF, 0x5A, 0xA5];
+        let mut x = seed | 1;
+        (0..len).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            BYTES[(x >> 30) as usize]
+        }).collect()
+    }
+    
+    fn decodes(data: &[u8], prev: Option<&[u8]>, target: Option<&[u8]>, inst: &dyn CycleInstr, pos: usize) -> bool {
+        // Whether the player decodes the instruction to the data it was made from, when
+        // everything before it was streamed
+        let mut comp = match pos {
+            0 => Vec::new(),
+            _ => STREAM_INSTRGEN.gen_instr(data, 0, 0, pos).gen_bytecode(false),
+        };
+        comp.extend(inst.gen_bytecode(true));
+        let end = pos + inst.get_decomp_size();
+        let mut img: Vec<u8> = match target {
+            Some(target) => target.to_vec(),
+            None => data.iter().map(|b| !b).collect(),
+        };
+        decompress::lzss_alt::dec_img(&comp, &mut img, prev).is_ok() && img[..end] == data[..end]
+    }
+    
+    fn runs(len: usize, seed: u32) -> Vec<u8> {
+        // Long alternating runs broken up by random bytes, so instructions need splitting and where
+        // they start matters
+        let bytes = random(len, seed);
+        let mut data: Vec<u8> = Vec::with_capacity(len);
+        let mut pos = 0;
+        while data.len() < len {
+            let run = 1 + bytes[pos] as usize % 48;
+            let kind = bytes[(pos + 1) % len];
+            for i in 0..run {
+                data.push(match (kind, i % 2) {
+                    (0x00 | 0xFF, 0) => kind,
+                    (0x5A, 1) => !data[data.len()-1],
+                    _ => bytes[(pos + i) % len],
+                });
+            }
+            pos = (pos + 2) % len;
+        }
+        data.truncate(len);
+        data
+    }
+    
+    fn exhaustive(data: &[u8], prev: Option<&[u8]>, target: Option<&[u8]>, fill: bool) -> usize {
+        // Size of the smallest encoding made from every instruction the solver is offered, started
+        // anywhere along the run it's offered for and cut to every length that still decodes.
+        // A run costs the same whatever comes before it, so the cheapest encoding of what follows
+        // each position only needs finding once
+        let (instr_types, graph_types) = get_types(data, prev, target, fill);
+        let mut runs: Vec<Vec<(usize, usize, usize)>> = vec![Vec::new(); data.len()];    // Type, id & end by position
+        for pos in 0..data.len() {
+            for (itype, gen) in graph_types.iter().enumerate() {
+                for (len, id) in gen.get_instr_info(data, pos) {
+                    let end = cmp::min(pos + len, data.len());
+                    for run in runs[pos..end].iter_mut() {
+                        run.push((itype, id, end));
+                    }
+                }
+            }
+        }
+        let mut best = vec![0; data.len() + 1];
+        for pos in (0..data.len()).rev() {
+            let mut cheapest = usize::MAX;
+            for &(itype, id, end) in runs[pos].iter() {
+                for len in 1..=end - pos {
+                    let inst = instr_types[itype].gen_instr(data, pos, id, len);
+                    if best[pos + len] != usize::MAX && decodes(data, prev, target, &*inst, pos) {
+                        cheapest = cmp::min(cheapest, inst.get_comp_size() + best[pos + len]);
+                    }
+                }
+            }
+            best[pos] = cheapest;
+        }
+        best[0]
+    }
+    
+    fn solved(data: &[u8], prev: Option<&[u8]>, target: Option<&[u8]>, fill: bool) -> usize {
+        compress(data, prev, target, fill, usize::MAX, &|_| 0).iter().map(|inst| inst.get_comp_size()).sum()
+    }
+    
+    #[test]
+    fn smallest_encoding() {
+        for seed in 0..200 {
+            let data = random(1 + seed as usize % 40, seed);
+            assert_eq!(solved(&data, None, None, false), exhaustive(&data, None, None, false), "data {:02X?}", data);
+        }
+    }
+    
+    #[test]
+    fn smallest_encoding_long_runs() {
+        for seed in 0..20 {
+            let data = runs(48 + seed as usize % 32, seed);
+            assert_eq!(solved(&data, None, None, false), exhaustive(&data, None, None, false), "data {:02X?}", data);
+        }
+    }
+    
+    #[test]
+    fn smallest_encoding_all_instructions() {
+        for seed in 0..200 {
+            let len = 1 + seed as usize % 40;
+            let data = random(len, seed);
+            let prev = random(len, seed + 1000);
+            let target = random(len, seed + 2000);
+            assert_eq!(solved(&data, Some(&prev), Some(&target), true), exhaustive(&data, Some(&prev), Some(&target), true), "data {:02X?}", data);
+        }
+    }
+    
+}
@@ -1,10 +1,13 @@
 
 
+pub mod codec;
 pub mod cycle_limit;
 pub mod graph_solve;
 pub mod instr;
 pub mod lzss_alt;
 pub mod nib_diff;
 pub mod none;
+pub mod old;
+pub mod rate;
 
 
@@ -84,7 +84,7 @@ impl Instr for ByteInstr {
 }
 impl CycleInstr for ByteInstr {
     fn get_cycles(&self) -> usize {
-        35 * self.diffs.len() + 5
+        54 * self.diffs.len() + 5
     }
     fn is_minimum(&self) -> bool {
         true
@@ -1,12 +1,14 @@
 
 // This compression routine assumes valid numbers range from 0-127
-// Compression data will look as follows:
-// Starts in absolute mode, in which each value is just the read byte without the msb
-// If the msb is set, then after this sample we switch to relative mode
-// In relative mode each nybble represents an offset from the previous vale to be applied
-// Most significant nybble is read first. A nybble of 0 mreans no change, 8 switch back to absolute
-// 
-// Used for the audio compression
+// Compression data is a stream of bits, most significant bit first, with each sample starting with
+// a 2 bit type:
+// 00 - The difference from the previous sample stays the same
+// 10 - The difference changes by the 4 bit signed acceleration that follows
+// 11 - The difference changes by the 4 bit acceleration that follows and then flips sign
+// 01 - The upper 6 bits of the sample follow, losing the lowest bit
+// The last byte is padded with 0s
+//
+// Used for the audio compression before nib_diff, kept around to benchmark against
 
 
 
@@ -19,10 +21,15 @@ fn putbit(comp: &mut Vec<u8>, curbyte: &mut u8, bitpos: &mut usize, bit: u8) {
         *bitpos = 0;
     }
 }
+fn putbits(comp: &mut Vec<u8>, curbyte: &mut u8, bitpos: &mut usize, bits: u8, num: usize) {
+    for i in (0..num).rev() {
+        putbit(comp, curbyte, bitpos, bits >> i);
+    }
+}
 fn finishbyte(comp: &mut Vec<u8>, curbyte: &mut u8, bitpos: &mut usize) {
     if *bitpos > 0 {
         while *bitpos < 8 {
-            *curbyte = *curbyte * 2;
+            *curbyte *= 2;
             *bitpos += 1;
         }
         comp.push(*curbyte);
@@ -30,7 +37,7 @@ fn finishbyte(comp: &mut Vec<u8>, curbyte: &mut u8, bitpos: &mut usize) {
 }
 
 
-pub fn compress(data: Vec<u8>) -> Vec<u8> {
+pub fn compress(data: &[u8]) -> Vec<u8> {
 
     // Compress audio samples
     let mut prev_samp: u8 = 196;    // Guarenteed out of range
@@ -38,55 +45,37 @@ pub fn compress(data: Vec<u8>) -> Vec<u8> {
     let mut comp: Vec<u8> = Vec::new();
     let mut byte = 0;
     let mut bpos = 0;
-    
-    for samp in data.iter() {
-        let samp = *samp;
+
+    for &samp in data.iter() {
         // Check difference in samples
         let diff: i16 = (samp as i16) - (prev_samp as i16);
         let accel = diff - prev_diff;
         let neg_accel = -diff - prev_diff;
+        // Full samples lose their lowest bit, keep going from what gets decoded
+        let mut decoded = samp;
         if accel == 0 {
             // No change in acceleration
-            putbit(&mut comp, &mut byte, &mut bpos, 0);
-            putbit(&mut comp, &mut byte, &mut bpos, 0);
+            putbits(&mut comp, &mut byte, &mut bpos, 0b00, 2);
         } else if (accel > -8) && (accel < 8) {
             // Normal acceleration
-            let aput = accel as u8;
-            putbit(&mut comp, &mut byte, &mut bpos, 1);
-            putbit(&mut comp, &mut byte, &mut bpos, 0);
-            putbit(&mut comp, &mut byte, &mut bpos, (aput / 8) % 2);
-            putbit(&mut comp, &mut byte, &mut bpos, (aput / 4) % 2);
-            putbit(&mut comp, &mut byte, &mut bpos, (aput / 2) % 2);
-            putbit(&mut comp, &mut byte, &mut bpos, aput % 2);
+            putbits(&mut comp, &mut byte, &mut bpos, 0b10, 2);
+            putbits(&mut comp, &mut byte, &mut bpos, accel as u8, 4);
         } else if (neg_accel > -8) && (neg_accel < 8) {
             // Aacceleration off of negative difference
-            let aput = neg_accel as u8;
-            putbit(&mut comp, &mut byte, &mut bpos, 1);
-            putbit(&mut comp, &mut byte, &mut bpos, 1);
-            putbit(&mut comp, &mut byte, &mut bpos, (aput / 8) % 2);
-            putbit(&mut comp, &mut byte, &mut bpos, (aput / 4) % 2);
-            putbit(&mut comp, &mut byte, &mut bpos, (aput / 2) % 2);
-            putbit(&mut comp, &mut byte, &mut bpos, aput % 2);
+            putbits(&mut comp, &mut byte, &mut bpos, 0b11, 2);
+            putbits(&mut comp, &mut byte, &mut bpos, neg_accel as u8, 4);
         } else {
             // Put full sample
-            let samp = samp / 2;
-            putbit(&mut comp, &mut byte, &mut bpos, 0);
-            putbit(&mut comp, &mut byte, &mut bpos, 1);
-            putbit(&mut comp, &mut byte, &mut bpos, (samp / 32) % 2);
-            putbit(&mut comp, &mut byte, &mut bpos, (samp / 16) % 2);
-            putbit(&mut comp, &mut byte, &mut bpos, (samp / 8) % 2);
-            putbit(&mut comp, &mut byte, &mut bpos, (samp / 4) % 2);
-            putbit(&mut comp, &mut byte, &mut bpos, (samp / 2) % 2);
-            putbit(&mut comp, &mut byte, &mut bpos, samp % 2);
+            putbits(&mut comp, &mut byte, &mut bpos, 0b01, 2);
+            putbits(&mut comp, &mut byte, &mut bpos, samp / 2, 6);
+            decoded = samp & 0x7E;
         }
-        
-        prev_diff = diff;
-        prev_samp = samp;
+
+        prev_diff = (decoded as i16) - (prev_samp as i16);
+        prev_samp = decoded;
     }
     finishbyte(&mut comp, &mut byte, &mut bpos);
-    
+
     comp
 
 }
-
-
@@ -6,11 +6,11 @@
 // In relative mode each nybble represents an offset from the previous vale to be applied
 // Most significant nybble is read first. A nybble of 0 mreans no change, 8 switch back to absolute
 // 
-// Used for the audio compression
+// Used for the audio compression before nib_diff, kept around to benchmark against
 
 
 
-fn putoffsets(comp: &mut Vec<u8>, offsets: &mut Vec<i8>) {
+fn putoffsets(comp: &mut Vec<u8>, offsets: &[i8]) {
     let ub = (((offsets[0] as i16) * 16) as u8) & 0xF0;
     let lb = (offsets[1] as u8) & 0x0F;
     comp.push(ub | lb);
@@ -26,9 +26,6 @@ pub fn compress(data: &[u8]) -> Vec<u8> {
     let mut comp: Vec<u8> = Vec::new();
     
     for samp in data.iter() {
-        //if *samp > 127 {
-        //    println!("{}", *samp);
-        //}
         // Check difference in samples
         let diff: i16 = (*samp as i16) - (prev_samp as i16);
         if -8 < diff && diff < 8 {
@@ -42,7 +39,7 @@ pub fn compress(data: &[u8]) -> Vec<u8> {
             offsets.push(diff as i8);
             // And if it's 2 large add a new instruction
             if offsets.len() >= 2 {
-                putoffsets(&mut comp, &mut offsets);
+                putoffsets(&mut comp, &offsets);
                 offsets = Vec::new();
             }
         } else {
@@ -51,7 +48,7 @@ pub fn compress(data: &[u8]) -> Vec<u8> {
                 while offsets.len() < 2 {
                     offsets.push(8);
                 }
-                putoffsets(&mut comp, &mut offsets);
+                putoffsets(&mut comp, &offsets);
                 offsets = Vec::new();
                 prev_rel = false;
             }
@@ -61,11 +58,11 @@ pub fn compress(data: &[u8]) -> Vec<u8> {
         prev_samp = *samp;
     }
     // Put dangling offsets
-    if offsets.len() > 0 {
+    if !offsets.is_empty() {
         while offsets.len() < 2 {
             offsets.push(0);
         }
-        putoffsets(&mut comp, &mut offsets);
+        putoffsets(&mut comp, &offsets);
     }
     
     comp
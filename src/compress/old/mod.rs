@@ -0,0 +1,6 @@
+
+
+// Compression schemes the player no longer uses, only there to benchmark against
+pub mod accel;
+pub mod diff_abs;
+pub mod rle_lzss;
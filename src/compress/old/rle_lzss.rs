@@ -1,215 +1,262 @@
 use std::cmp;
 use crate::compress::graph_solve;
-//use num::integer::Integer;
+use crate::compress::graph_solve::{GraphFuncs};
+use crate::compress::instr::{Instr, InstrGen};
+use crate::compress::cycle_limit::{CycleInstr};
+
+// The image compression the player used before lzss_alt, kept around to benchmark against
+// Every instruction starts with a byte holding its length - 1 in the upper 6 bits and its type in
+// the lower 2: 0 LZSS (followed by the offset), 1 stream (followed by the bytes), 2 a run of $00s
+// and 3 a run of $FFs. There's no end marker, the image ends once the buffer is full
+// The player for it is long gone, so cycles are only an estimate of what it would have taken
 
-/*
-pub fn compress(nt: usize, d: &[u8], ins: &[&dyn Fn(&[u8], usize) -> Vec<(usize, usize)>], stcs: &[&dyn Fn(&[u8], usize, usize) -> isize], encs: &[&dyn Fn(&[u8], usize, usize) -> isize], ctcs: &[&dyn Fn(&[u8], usize, usize, usize) -> isize], bcs: &[&dyn Fn(&[u8], usize, usize, usize)->Vec<u8>]) -> Vec<u8> {
-        let gs = GraphSolve { numt: nt, data: d, get_instrs: ins, get_step_costs: stcs, get_entry_costs: encs, get_cont_costs: ctcs, gen_bytecodes: bcs};
-        gs.gen_bytecode(gs.make_graph())
-    }
-*/
 
+pub fn compress(data: &[u8]) -> Vec<Box<dyn CycleInstr>> {
 
-pub fn compress(data: Vec<u8>) -> Vec<u8> {
-    
-    // Compress
-    graph_solve::GraphSolve::compress(5, &data, &[&stream_get_instrs, &lzss_get_instrs, &const0_get_instrs, &const1_get_instrs],
-                                                &[&stream_get_step_cost, &lzss_get_step_cost, &const0_get_step_cost, &const1_get_step_cost],
-                                                &[&stream_get_entry_cost, &lzss_get_entry_cost, &const0_get_entry_cost, &const1_get_entry_cost],
-                                                &[&stream_get_cont_cost, &lzss_get_cont_cost, &const0_get_cont_cost, &const1_get_cont_cost],
-                                                &[&stream_gen_bytecode, &lzss_gen_bytecode, &const0_gen_bytecode, &const1_gen_bytecode] )
+    let instr_types: Vec<Box<dyn InstrGen<dyn CycleInstr>>> = vec![Box::new(InstrType(STREAM)), Box::new(InstrType(LZSS)), Box::new(InstrType(CONST_00)), Box::new(InstrType(CONST_FF))];
+    let graph_types: Vec<Box<dyn GraphFuncs>> = vec![Box::new(StreamGraphFuncs{}), Box::new(LZSSGraphFuncs{}), Box::new(ConstGraphFuncs(0x00)), Box::new(ConstGraphFuncs(0xFF))];
+    // Without a player there's no cycle limit to keep to
+    graph_solve::compress(data, &instr_types, &graph_types, usize::MAX, &|_| 0)
 
-    
 }
 
 
 
-// LZSS Functions
-const LZSS_MAX_LEN: usize = 64;
-fn lzss_get_instrs(data: &[u8], pos: usize) -> Vec<(usize, usize)> {
-    // Search through data stream for all occurances that match our current position (over a
-    // certain length)
-    let mut list: Vec<(usize, usize)> = Vec::new();
-    for from_start in 0..pos {
-        let mut len = 0;
-        for to in pos..data.len() {
-            let from = (to - pos) + from_start;
-            if data[to] == data[from] {
-                len += 1;
-            } else {
-                if len >= 2 {
-                    let offset = pos - from_start;  // offset will be the unique identifier
-                    list.push((len, offset));
-                }
-                break;
-            }
-        }
+const MAX_LEN: usize = 64;      // Same for every instruction
+
+const LZSS: u8 = 0;
+const STREAM: u8 = 1;
+const CONST_00: u8 = 2;
+const CONST_FF: u8 = 3;
+
+// Cycles an instruction would take on top of 21 per byte
+const LZSS_CYCLES: usize = 150;
+const LZSS_LONG_CYCLES: usize = 30;     // Extra for an offset taking 2 bytes
+const STREAM_CYCLES: usize = 64;
+const CONST_CYCLES: usize = 60;
+
+
+struct InstrType(u8);
+impl InstrGen<dyn CycleInstr> for InstrType {
+    fn gen_instr(&self, data: &[u8], pos: usize, offset: usize, len: usize) -> Box<dyn CycleInstr> {
+        let decomp: Vec<u8> = data[pos..pos+len].to_vec();
+        let numsplit = num::Integer::div_ceil(&len, &MAX_LEN);
+        Box::new(RLELZSSInstr {kind: self.0, decomp, offset, numsplit})
     }
-    list
 }
-fn lzss_get_step_cost(_data: &[u8], _pos: usize, _off: usize) -> isize {
-    0
+
+#[derive(Clone)]
+struct RLELZSSInstr {
+    kind: u8,
+    decomp: Vec<u8>,
+    offset: usize,      // Only for LZSS
+    numsplit: usize,
 }
-fn lzss_get_entry_cost(_data: &[u8], _pos: usize, off: usize) -> isize {
-    // Entry cost is 3 if offset is greater than or equal to 128, else 2
-    match off >= 128 {
-        true => 3,
-        false => 2
+impl RLELZSSInstr {
+    fn head_size(&self) -> usize {
+        // Bytes each of the split up instructions takes, not counting streamed bytes
+        match self.kind {
+            LZSS => match self.offset >= 128 {
+                false => 2,
+                true => 3,
+            },
+            _ => 1,
+        }
     }
 }
-fn lzss_get_cont_cost(data: &[u8], pos: usize, off: usize, rel_pos: usize) -> isize{
-    // Same as entry cost if length is 64 (or some multiple of that)
-    match rel_pos % LZSS_MAX_LEN {
-        0 => lzss_get_entry_cost(data, pos, off),
-        _ => 0
+impl Instr for RLELZSSInstr {
+    fn gen_bytecode(&self, _last: bool) -> Vec<u8> {
+        let mut bytecode: Vec<u8> = Vec::new();
+        // Figure out how many instructions we'll need to use and the lengths of each one
+        let max_put_len = num::Integer::div_ceil(&self.decomp.len(), &self.numsplit);
+        let mut pos = 0;
+        for _i in 0..self.numsplit {
+            let put_len = cmp::min(self.decomp.len() - pos, max_put_len);
+            bytecode.push((((put_len - 1) as u8) << 2) + self.kind);
+            match self.kind {
+                LZSS => {
+                    // Offset stored in 1 or 2 bytes depending on size
+                    if self.offset >= 128 {
+                        bytecode.push(((self.offset & 0x7F00) >> 7) as u8 + 1);
+                        bytecode.push(self.offset as u8);
+                    } else {
+                        bytecode.push((self.offset as u8) << 1);
+                    }
+                },
+                STREAM => bytecode.extend_from_slice(&self.decomp[pos..pos+put_len]),
+                _ => {},
+            }
+            pos += put_len;
+        }
+        bytecode
     }
-}
-fn lzss_gen_bytecode(_data: &[u8], _pos: usize, off: usize, mut len: usize) -> Vec<u8> {
-    // Generate lzss bytecode
-    let mut bytecode: Vec<u8> = Vec::new();
-    // Figure out how many instructions we'll need to use and the lengths of each one
-    let num = num::Integer::div_ceil(&len, &LZSS_MAX_LEN);
-    let max_put_len = num::Integer::div_ceil(&len, &num);
-    for _i in 0..num {
-        let put_len = cmp::min(len, max_put_len) - 1;
-        bytecode.push(((put_len as u8) << 2) + 0x00);
-        // Offset stored in 1 or 2 bytes depending on size
-        if off >= 128 {
-            bytecode.push(((off & 0x7F00) >> 7) as u8 + 1);
-            bytecode.push(off as u8);
-        } else {
-            bytecode.push((off as u8) << 1);
+    fn get_comp_size(&self) -> usize {
+        self.numsplit * self.head_size() + match self.kind {
+            STREAM => self.decomp.len(),
+            _ => 0,
         }
-        len -= LZSS_MAX_LEN;
     }
-    bytecode
+    fn get_decomp_size(&self) -> usize {
+        self.decomp.len()
+    }
+    fn get_decomp(&self) -> Vec<u8> {
+        self.decomp.clone()
+    }
+}
+impl CycleInstr for RLELZSSInstr {
+    fn get_cycles(&self) -> usize {
+        let each = match self.kind {
+            LZSS => LZSS_CYCLES + match self.offset >= 128 {
+                false => 0,
+                true => LZSS_LONG_CYCLES,
+            },
+            STREAM => STREAM_CYCLES,
+            _ => CONST_CYCLES,
+        };
+        each * self.numsplit + 21 * self.decomp.len()
+    }
+    fn is_minimum(&self) -> bool {
+        // Nothing's faster than the smallest encoding when there's no limit
+        true
+    }
+    fn to_minimum(&self) -> Box<dyn CycleInstr> {
+        Box::new(self.clone())
+    }
+    fn combine_with_right(&mut self, _other: &dyn CycleInstr) {}
+    fn combine_with_left(&mut self, _other: &dyn CycleInstr) {}
 }
 
 
-// Stream Functions
-const STREAM_MAX_LEN: usize = 64;
-fn stream_get_instrs(data: &[u8], _pos: usize) -> Vec<(usize, usize)> {
-    // Because the stream instruction is always available and arbitrarily long, this function
-    // should only get called once at the start of the graph generation
-    vec![(data.len(), 0)]
-}
-fn stream_get_step_cost(_data: &[u8], _pos: usize, _id: usize) -> isize {
-    1
-}
-fn stream_get_entry_cost(_data: &[u8], _pos: usize, _id: usize) -> isize {
-    1
+
+struct LZSSGraphFuncs {
 }
-fn stream_get_cont_cost(data: &[u8], pos: usize, id: usize, rel_pos: usize) -> isize{
-    // Same as entry cost if length is a multiple of the max length
-    match rel_pos % STREAM_MAX_LEN {
-        0 => stream_get_entry_cost(data, pos, id),
-        _ => 0
+impl GraphFuncs for LZSSGraphFuncs {
+    fn get_instr_info(&self, data: &[u8], pos: usize) -> Vec<(usize, usize)> {   // Returns Vec<len, id>
+        // Search through data stream for all occurances that match our current position (over a
+        // certain length)
+        let mut list: Vec<(usize, usize)> = Vec::new();
+        for from in 0..pos {
+            let mut len = 0;
+            while pos + len < data.len() && data[from+len] == data[pos+len] {
+                len += 1;
+            }
+            if len >= 2 {
+                let offset = pos - from;  // offset will be the unique identifier
+                list.push((len, offset));
+            }
+        }
+        list
     }
-}
-fn stream_gen_bytecode(data: &[u8], mut pos: usize, _id: usize, mut len: usize) -> Vec<u8> {
-    // Generate tream bytecode
-    let mut bytecode: Vec<u8> = Vec::new();
-    // Figure out how many instructions we'll need to use and the lengths of each one
-    let num = num::Integer::div_ceil(&len, &STREAM_MAX_LEN);
-    let max_put_len = num::Integer::div_ceil(&len, &num);
-    for _i in 0..num {
-        let put_len = cmp::min(len, max_put_len) - 1;
-        bytecode.push(((put_len as u8) << 2) + 0x01);
-        for byte in &data[pos..pos+put_len] {
-            bytecode.push(*byte);
-            pos += 1;
+    fn get_step_cost(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
+        0
+    }
+    fn get_entry_cost(&self, _data: &[u8], _pos: usize, off: usize) -> isize {
+        // Entry cost is 3 if offset is greater than or equal to 128, else 2
+        match off >= 128 {
+            true => 3,
+            false => 2,
         }
-        len -= STREAM_MAX_LEN;
     }
-    bytecode
-}
-
-
-// Const 00 Functions
-const CONST_00_MAX_LEN: usize = 64;
-fn const0_get_instrs(data: &[u8], pos: usize) -> Vec<(usize, usize)> {
-    let mut instrs: Vec<(usize, usize)> = Vec::new();
-    // Get length of data which is just 00
-    let mut len = 0;
-    for i in pos..data.len() {
-        if data[i] != 0x00 {
-            break;
+    fn get_cost_period(&self) -> usize {
+        MAX_LEN
+    }
+    fn get_cost_class(&self, uid: usize) -> usize {
+        uid
+    }
+    fn get_entry_cycles(&self, _data: &[u8], _pos: usize, off: usize) -> isize {
+        (LZSS_CYCLES + match off >= 128 {
+            false => 0,
+            true => LZSS_LONG_CYCLES,
+        } + 21) as isize
+    }
+    fn get_cont_cycles(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
+        match rel_pos % MAX_LEN {
+            0 => self.get_entry_cycles(data, pos, uid),
+            _ => 21
         }
-        len += 1;
     }
-    if len > 0 {
-        instrs.push((len, 0));
+    fn get_cont_cost(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
+        // Same as entry cost if length is 64 (or some multiple of that)
+        match rel_pos % MAX_LEN {
+            0 => self.get_entry_cost(data, pos, uid),
+            _ => 0
+        }
     }
-    instrs
 }
-fn const0_get_step_cost(_data: &[u8], _pos: usize, _id: usize) -> isize {
-    0
-}
-fn const0_get_entry_cost(_data: &[u8], _pos: usize, _id: usize) -> isize {
-    1
+
+
+struct StreamGraphFuncs {
 }
-fn const0_get_cont_cost(data: &[u8], pos: usize, id: usize, rel_pos: usize) -> isize {
-    // Same as entry cost if length is a multiple of the max length
-    match rel_pos % CONST_00_MAX_LEN {
-        0 => stream_get_entry_cost(data, pos, id),
-        _ => 0
+impl GraphFuncs for StreamGraphFuncs {
+    fn get_instr_info(&self, data: &[u8], _pos: usize) -> Vec<(usize, usize)> {   // Returns Vec<len, id>
+        // Because the stream instruction is always available and arbitrarily long, this function
+        // should only get called once at the start of the graph generation
+        vec![(data.len(), 0)]
+    }
+    fn get_step_cost(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
+        1
+    }
+    fn get_entry_cost(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
+        1
+    }
+    fn get_cost_period(&self) -> usize {
+        MAX_LEN
+    }
+    fn get_cost_class(&self, uid: usize) -> usize {
+        uid
+    }
+    fn get_entry_cycles(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
+        (STREAM_CYCLES + 21) as isize
+    }
+    fn get_cont_cycles(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
+        match rel_pos % MAX_LEN {
+            0 => self.get_entry_cycles(data, pos, uid),
+            _ => 21
+        }
+    }
+    fn get_cont_cost(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
+        match rel_pos % MAX_LEN {
+            0 => self.get_entry_cost(data, pos, uid),
+            _ => 0
+        }
     }
-}
-fn const0_gen_bytecode(_data: &[u8], _pos: usize, _id: usize, mut len: usize) -> Vec<u8> {
-    // Generate bytecode
-    let mut bytecode: Vec<u8> = Vec::new();
-    // Figure out how many instructions we'll need to use and the lengths of each one
-    let num = num::Integer::div_ceil(&len, &CONST_00_MAX_LEN);
-    let max_put_len = num::Integer::div_ceil(&len, &num);
-    for _i in 0..num {
-        let put_len = cmp::min(len, max_put_len) - 1;
-        bytecode.push(((put_len as u8) << 2) + 0x02);
-        len -= CONST_00_MAX_LEN;
-    }
-    bytecode
 }
 
 
-// Const FF Functions
-const CONST_FF_MAX_LEN: usize = 64;
-fn const1_get_instrs(data: &[u8], pos: usize) -> Vec<(usize, usize)> {
-    let mut instrs: Vec<(usize, usize)> = Vec::new();
-    // Get length of data which is just FF
-    let mut len = 0;
-    for i in pos..data.len() {
-        if data[i] != 0xFF {
-            break;
+struct ConstGraphFuncs(u8);     // Runs of the given byte
+impl GraphFuncs for ConstGraphFuncs {
+    fn get_instr_info(&self, data: &[u8], pos: usize) -> Vec<(usize, usize)> {   // Returns Vec<len, id>
+        let len = data[pos..].iter().take_while(|&&byte| byte == self.0).count();
+        match len > 0 {
+            true => vec![(len, self.0 as usize)],
+            false => Vec::new(),
         }
-        len += 1;
     }
-    if len > 0 {
-        instrs.push((len, 0xFF));
+    fn get_step_cost(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
+        0
     }
-    instrs
-}
-fn const1_get_step_cost(_data: &[u8], _pos: usize, _id: usize) -> isize {
-    0
-}
-fn const1_get_entry_cost(_data: &[u8], _pos: usize, _id: usize) -> isize {
-    1
-}
-fn const1_get_cont_cost(data: &[u8], pos: usize, id: usize, rel_pos: usize) -> isize {
-    // Same as entry cost if length is a multiple of the max length
-    match rel_pos % CONST_FF_MAX_LEN {
-        0 => stream_get_entry_cost(data, pos, id),
-        _ => 0
+    fn get_entry_cost(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
+        1
+    }
+    fn get_cost_period(&self) -> usize {
+        MAX_LEN
+    }
+    fn get_cost_class(&self, uid: usize) -> usize {
+        uid
+    }
+    fn get_entry_cycles(&self, _data: &[u8], _pos: usize, _uid: usize) -> isize {
+        (CONST_CYCLES + 21) as isize
+    }
+    fn get_cont_cycles(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
+        match rel_pos % MAX_LEN {
+            0 => self.get_entry_cycles(data, pos, uid),
+            _ => 21
+        }
+    }
+    fn get_cont_cost(&self, data: &[u8], pos: usize, uid: usize, rel_pos: usize) -> isize {
+        match rel_pos % MAX_LEN {
+            0 => self.get_entry_cost(data, pos, uid),
+            _ => 0
+        }
     }
-}
-fn const1_gen_bytecode(_data: &[u8], _pos: usize, _id: usize, mut len: usize) -> Vec<u8> {
-    // Generate bytecode
-    let mut bytecode: Vec<u8> = Vec::new();
-    // Figure out how many instructions we'll need to use and the lengths of each one
-    let num = num::Integer::div_ceil(&len, &CONST_FF_MAX_LEN);
-    let max_put_len = num::Integer::div_ceil(&len, &num);
-    for _i in 0..num {
-        let put_len = cmp::min(len, max_put_len) - 1;
-        bytecode.push(((put_len as u8) << 2) + 0x03);
-        len -= CONST_FF_MAX_LEN;
-    }
-    bytecode
 }
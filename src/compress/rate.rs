@@ -0,0 +1,96 @@
+
+
+// Rate control for image frames
+// Frames that compress to more than their share of a byte budget get simplified a little at a time
+// until they fit, so quality drops gradually instead of the video not fitting at all
+// Simplifying works on pairs of bytes, the two planes of 8 pixels in a row. A pair that only
+// differs from one the player already has by a few pixels gets snapped to it, either from the
+// images left in the player's buffers or from the rows just above, which evens out dither noise in
+// flat regions
+
+
+
+pub const MAX_LOSS: usize = 4;          // Highest loss level, each level allows one more pixel per pair to change
+const MAX_BANK_FRAMES: isize = 8;       // How many frames' worth of budget can be saved up or owed
+const SNAP_ROWS: [usize; 4] = [1, 2, 4, 8];     // Rows above to try snapping to, matching the ordered dither's pattern
+
+
+pub struct RateControl {
+    budgets: Vec<usize>,    // Each frame's budget, the last one carries on for any frames after
+    frame: usize,
+    bank: isize,
+    limit: isize,
+}
+impl RateControl {
+
+    pub fn new(budget: usize) -> RateControl {
+        RateControl::scheduled(vec![budget])
+    }
+
+    pub fn scheduled(budgets: Vec<usize>) -> RateControl {
+        // Budgets that follow how big each frame is expected to be, so frames only get simplified
+        // once the video runs bigger than expected
+        let limit = overdraft(&budgets) as isize;
+        RateControl {budgets, frame: 0, bank: 0, limit}
+    }
+
+    fn budget(&self) -> usize {
+        self.budgets[self.frame.min(self.budgets.len() - 1)]
+    }
+
+    pub fn allowed(&self) -> usize {
+        // Bytes the next frame can take, frames under budget let later frames go over
+        (self.budget() as isize + self.bank).max(0) as usize
+    }
+
+    pub fn spend(&mut self, size: usize) {
+        self.bank = (self.bank + self.budget() as isize - size as isize).clamp(-self.limit, self.limit);
+        self.frame += 1;
+    }
+
+}
+
+
+pub fn overdraft(budgets: &[usize]) -> usize {
+    // Most bytes frames can take over their budgets in total, or save up
+    budgets.iter().sum::<usize>() / budgets.len().max(1) * MAX_BANK_FRAMES as usize
+}
+
+
+fn pair_diff(img: &[u8], pos: usize, other: &[u8], other_pos: usize) -> u32 {
+    // Number of pixels that differ between two byte pairs
+    ((img[pos] ^ other[other_pos]) | (img[pos+1] ^ other[other_pos+1])).count_ones()
+}
+
+
+pub fn degrade(img: &[u8], refs: &[&[u8]], level: usize) -> Vec<u8> {
+    // Simplify an interleaved image, refs are the images the player has in its buffers
+    let mut out = img.to_vec();
+    if level == 0 {
+        return out;
+    }
+    let temporal_max = level as u32;
+    let spatial_max = level as u32 - 1;
+    for pos in (0..out.len()).step_by(2) {
+        // Prefer copying from a buffer, that can be skipped over entirely
+        let best_ref = refs.iter().map(|r| (pair_diff(&out, pos, r, pos), *r)).min_by_key(|(diff, _)| *diff);
+        if let Some((diff, r)) = best_ref {
+            if diff <= temporal_max {
+                out[pos] = r[pos];
+                out[pos+1] = r[pos+1];
+                continue;
+            }
+        }
+        // Otherwise copy from a row above in the same column
+        let y = (pos / 2) % 64;
+        let best_row = SNAP_ROWS.iter().filter(|&&rows| rows <= y).map(|&rows| (pair_diff(&out, pos, &out, pos - rows * 2), pos - rows * 2))
+                                .min_by_key(|(diff, _)| *diff);
+        if let Some((diff, from)) = best_row {
+            if diff <= spatial_max {
+                out[pos] = out[from];
+                out[pos+1] = out[from+1];
+            }
+        }
+    }
+    out
+}
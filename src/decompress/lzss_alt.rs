@@ -0,0 +1,379 @@
+// Reference decompressor for the image bytecode produced by compress::lzss_alt
+// Mirrors dec_img and the routines it branches to in audVid.z80, so anything this decodes the same
+// as the source image should decode the same on the calculator
+
+
+
+const TEMPORAL_DISP_BASE: u8 = 0x5F;
+const SKIP_ID: u8 = 0x7F;
+const FILL_ID: u8 = 0x3F;
+
+
+struct ImgDecoder<'a> {
+    comp: &'a [u8],
+    hl: usize,          // Position in compressed data
+    img: &'a mut [u8],
+    de: usize,          // Position in decompressed image
+    prev: Option<&'a [u8]>,
+}
+
+
+pub fn dec_img(comp: &[u8], img: &mut [u8], prev: Option<&[u8]>) -> Result<usize, String> {
+    // Decompresses an image into the given buffer, which should already hold whatever the player
+    // would have left in it (for skip instructions), prev is the previously decoded image
+    // Returns the number of compressed bytes read
+    let mut dec = ImgDecoder {comp, hl: 0, img, de: 0, prev};
+    dec.dec_img()?;
+    Ok(dec.hl)
+}
+
+
+impl ImgDecoder<'_> {
+    
+    fn dec_img(&mut self) -> Result<(), String> {
+        loop {
+            // Load type & length byte
+            let a = self.read()?;
+            // Branch based on type
+            if a & 0x01 != 0 {
+                self.dec_alt(a)?;
+            } else if a & 0x02 != 0 {
+                self.dec_stream(a)?;
+            } else if a & 0x04 != 0 {
+                self.dec_alt_flip(a)?;
+            } else if a == 0 {
+                // Done
+                return Ok(());
+            } else {
+                self.dec_lzss(a)?;
+            }
+        }
+    }
+    
+    fn dec_lzss(&mut self, a: u8) -> Result<(), String> {
+        let len = (a >> 3) as usize + 1;
+        let off = self.read()?;
+        let offset = if off & 0x01 != 0 {
+            let d = off >> 1;
+            if d >= 0x40 {
+                // Extended instructions
+                return match d {
+                    SKIP_ID => self.dec_skip(len),
+                    _ => self.dec_temporal(d, len),
+                };
+            }
+            if d == FILL_ID {
+                return self.dec_fill(len);
+            }
+            // Two-byte offset
+            ((d as usize) << 8) + self.read()? as usize
+        } else {
+            // One-byte offset
+            (off >> 1) as usize
+        };
+        if offset == 0 || offset > self.de {
+            return Err(format!("LZSS offset {} out of range at image byte {}", offset, self.de));
+        }
+        // Copy data one byte at a time like ldir, so overlapping copies repeat
+        for _i in 0..len {
+            let byte = self.img[self.de - offset];
+            self.write(byte)?;
+        }
+        Ok(())
+    }
+    
+    fn dec_temporal(&mut self, d: u8, len: usize) -> Result<(), String> {
+        // Copy from the previously decoded image
+        let prev = match self.prev {
+            Some(prev) => prev,
+            None => return Err(format!("Temporal reference at image byte {} with no previous image", self.de)),
+        };
+        let disp = (d as isize - TEMPORAL_DISP_BASE as isize) * 2;
+        let from = self.de as isize + disp;
+        if from < 0 || from as usize + len > prev.len() {
+            return Err(format!("Temporal reference out of range at image byte {}", self.de));
+        }
+        if self.de + len > self.img.len() {
+            return Err(format!("Decompressed image longer than {} bytes", self.img.len()));
+        }
+        let from = from as usize;
+        self.img[self.de..self.de+len].copy_from_slice(&prev[from..from+len]);
+        self.de += len;
+        Ok(())
+    }
+    
+    fn dec_skip(&mut self, len: usize) -> Result<(), String> {
+        // Leave bytes as they already are in the image buffer
+        if self.de + len > self.img.len() {
+            return Err(format!("Decompressed image longer than {} bytes", self.img.len()));
+        }
+        self.de += len;
+        Ok(())
+    }
+    
+    fn dec_fill(&mut self, len: usize) -> Result<(), String> {
+        // Repeat the pair of bytes that follows
+        let pair = [self.read()?, self.read()?];
+        for i in 0..len {
+            self.write(pair[i % 2])?;
+        }
+        Ok(())
+    }
+    
+    fn dec_stream(&mut self, a: u8) -> Result<(), String> {
+        // Data stream
+        let len = ((a >> 2) & 0x3F) as usize + 1;
+        for _i in 0..len {
+            let byte = self.read()?;
+            self.write(byte)?;
+        }
+        Ok(())
+    }
+    
+    fn dec_alt_flip(&mut self, a: u8) -> Result<(), String> {
+        // Alternate between byte from data stream and the same byte flipped
+        let len = ((a >> 3) & 0x1F) as usize + 1;
+        let mut byte = 0;
+        for i in 0..len {
+            if i % 2 == 0 {
+                byte = self.read()?;
+                self.write(byte)?;
+            } else {
+                self.write(!byte)?;
+            }
+        }
+        Ok(())
+    }
+    
+    fn dec_alt(&mut self, a: u8) -> Result<(), String> {
+        // Alternate between byte from data stream and constant value
+        let constant = match a & 0x02 != 0 {
+            true => 0xFF,
+            false => 0x00,
+        };
+        let start_const = a & 0x04 != 0;
+        let len = (a >> 3) as usize + 1;
+        for i in 0..len {
+            if (i % 2 == 0) != start_const {
+                let byte = self.read()?;
+                self.write(byte)?;
+            } else {
+                self.write(constant)?;
+            }
+        }
+        Ok(())
+    }
+    
+    fn read(&mut self) -> Result<u8, String> {
+        match self.comp.get(self.hl) {
+            Some(&byte) => {
+                self.hl += 1;
+                Ok(byte)
+            },
+            None => Err("Ran past the end of the compressed image".to_string()),
+        }
+    }
+    
+    fn write(&mut self, byte: u8) -> Result<(), String> {
+        match self.img.get_mut(self.de) {
+            Some(put) => {
+                *put = byte;
+                self.de += 1;
+                Ok(())
+            },
+            None => Err(format!("Decompressed image longer than {} bytes", self.img.len())),
+        }
+    }
+    
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::{lzss_alt, instr};
+    
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    enum Kind {
+        Stream,
+        Alt,
+        AltFlip,
+        LzssShort,
+        LzssLong,
+        Temporal,
+        Skip,
+        Fill,
+    }
+    
+    fn noise(len: usize, seed: u32) -> Vec<u8> {
+        // Bytes that don't repeat or alternate, so only the instruction being tested compresses them
+        let mut x = seed | 1;
+        (0..len).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            (x >> 24) as u8
+        }).collect()
+    }
+    
+    fn round_trip(data: &[u8], prev: Option<&[u8]>, target: Option<&[u8]>, fill: bool) -> Vec<Kind> {
+        // Compresses without a cycle limit, decompresses into the target (or bytes that can't match
+        // it) and returns which instructions it was decompressed with
+        let comp = instr::gen_bytecode(&lzss_alt::compress(data, prev, target, fill, usize::MAX, &|_| 0));
+        let mut img: Vec<u8> = match target {
+            Some(target) => target.to_vec(),
+            None => data.iter().map(|b| !b).collect(),
+        };
+        let mut dec = ImgDecoder {comp: &comp, hl: 0, img: &mut img, de: 0, prev};
+        let mut kinds = Vec::new();
+        loop {
+            // Same branches as dec_img, looking at the offset byte to tell LZSS instructions apart
+            let a = dec.read().unwrap();
+            let kind = if a & 0x01 != 0 {
+                dec.dec_alt(a).unwrap();
+                Kind::Alt
+            } else if a & 0x02 != 0 {
+                dec.dec_stream(a).unwrap();
+                Kind::Stream
+            } else if a & 0x04 != 0 {
+                dec.dec_alt_flip(a).unwrap();
+                Kind::AltFlip
+            } else if a == 0 {
+                break;
+            } else {
+                let off = dec.comp[dec.hl];
+                let kind = match (off & 0x01 != 0, off >> 1) {
+                    (false, _) => Kind::LzssShort,
+                    (true, SKIP_ID) => Kind::Skip,
+                    (true, FILL_ID) => Kind::Fill,
+                    (true, d) if d >= 0x40 => Kind::Temporal,
+                    (true, _) => Kind::LzssLong,
+                };
+                dec.dec_lzss(a).unwrap();
+                kind
+            };
+            kinds.push(kind);
+        }
+        assert_eq!(dec.hl, comp.len(), "decompression stopped early");
+        assert_eq!(dec.de, data.len(), "decompressed the wrong number of bytes");
+        assert_eq!(img, data, "decompressed image differs");
+        kinds
+    }
+    
+    #[test]
+    fn stream() {
+        let kinds = round_trip(&noise(200, 1), None, None, false);
+        assert!(kinds.iter().all(|&kind| kind == Kind::Stream));
+    }
+    
+    #[test]
+    fn alt() {
+        let mut data = noise(64, 2);
+        for (i, byte) in noise(64, 3).into_iter().enumerate() {
+            data.push(match i % 2 {
+                0 => byte,
+                _ => 0xFF,
+            });
+        }
+        for (i, byte) in noise(63, 4).into_iter().enumerate() {
+            data.push(match i % 2 {
+                0 => 0x00,
+                _ => byte,
+            });
+        }
+        assert!(round_trip(&data, None, None, false).contains(&Kind::Alt));
+    }
+    
+    #[test]
+    fn alt_flip() {
+        let mut data = noise(64, 5);
+        for byte in noise(32, 6) {
+            data.extend([byte, !byte]);
+        }
+        assert!(round_trip(&data, None, None, false).contains(&Kind::AltFlip));
+    }
+    
+    #[test]
+    fn lzss_short_offset() {
+        let mut data = noise(100, 7);
+        data.extend_from_within(..100);
+        let kinds = round_trip(&data, None, None, false);
+        assert!(kinds.contains(&Kind::LzssShort));
+        assert!(!kinds.contains(&Kind::LzssLong));
+    }
+    
+    #[test]
+    fn lzss_long_offset() {
+        let mut data = noise(600, 8);
+        data.extend_from_within(..100);
+        assert!(round_trip(&data, None, None, false).contains(&Kind::LzssLong));
+    }
+    
+    #[test]
+    fn lzss_overlapping() {
+        // Copies from just behind themselves repeat like ldir does
+        let mut data = noise(40, 9);
+        data.extend([0x5A, 0xC3, 0x5A].repeat(30));
+        data.extend(noise(40, 10));
+        assert!(round_trip(&data, None, None, false).contains(&Kind::LzssShort));
+    }
+    
+    #[test]
+    fn temporal() {
+        // The previous image shifted by an even number of bytes either way
+        let prev = noise(400, 11);
+        let mut data = noise(100, 12);
+        data.extend_from_slice(&prev[110..200]);
+        data.extend_from_slice(&prev[180..280]);
+        data.extend(noise(110, 13));
+        assert!(round_trip(&data, Some(&prev), None, false).contains(&Kind::Temporal));
+    }
+    
+    #[test]
+    fn skip() {
+        let target = noise(400, 14);
+        let mut data = noise(100, 15);
+        data.extend_from_slice(&target[100..300]);
+        data.extend(noise(100, 16));
+        assert!(round_trip(&data, None, Some(&target), false).contains(&Kind::Skip));
+    }
+    
+    #[test]
+    fn skip_after_rotation() {
+        // Frames go round the player's 3 image buffers, so the first one back in a buffer can skip
+        // what the frame 3 before it left there
+        let first = noise(400, 19);
+        let mut frames = vec![first.clone(), noise(400, 20), noise(400, 21)];
+        let mut fourth = first[..250].to_vec();
+        fourth.extend(noise(150, 22));
+        frames.push(fourth);
+        let mut buffers: Vec<Option<Vec<u8>>> = vec![None; 3];
+        for (i, frame) in frames.iter().enumerate() {
+            let prev = match i {
+                0 => None,
+                _ => Some(frames[i - 1].as_slice()),
+            };
+            let kinds = round_trip(frame, prev, buffers[i % 3].as_deref(), false);
+            assert_eq!(kinds.contains(&Kind::Skip), i == 3);
+            buffers[i % 3] = Some(frame.clone());
+        }
+        // Decompressed into any other buffer, like one the player hadn't rotated to, the skipped
+        // bytes come out wrong
+        let comp = instr::gen_bytecode(&lzss_alt::compress(&frames[3], Some(&frames[2]), Some(&frames[0]), false, usize::MAX, &|_| 0));
+        let mut img = frames[2].clone();
+        dec_img(&comp, &mut img, Some(&frames[2])).unwrap();
+        assert_ne!(img[..250], frames[3][..250]);
+    }
+    
+    #[test]
+    fn fill() {
+        let mut data = noise(64, 17);
+        for byte in noise(4, 18).chunks(2) {
+            data.extend(byte.repeat(16));
+            data.extend(noise(64, byte[0] as u32));
+        }
+        assert!(round_trip(&data, None, None, true).contains(&Kind::Fill));
+        assert!(!round_trip(&data, None, None, false).contains(&Kind::Fill));
+    }
+    
+}
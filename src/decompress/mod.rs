@@ -0,0 +1,6 @@
+
+
+
+pub mod lzss_alt;
+pub mod nib_diff;
+pub mod old;
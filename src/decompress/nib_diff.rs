@@ -0,0 +1,108 @@
+
+
+// Reference decompressor for the audio bytecode produced by compress::nib_diff
+// Mirrors dec_aud in audVid.z80, which decompresses the differences between samples and leaves the
+// start sample for the next frame right after them
+
+
+
+const SAMPLES: usize = 512;
+
+
+pub fn dec_aud(comp: &[u8]) -> Result<(Vec<i8>, u8, usize), String> {
+    // Returns the sample differences, the start sample read after them and the number of compressed
+    // bytes read (including the start sample)
+    let mut diffs: Vec<i8> = Vec::with_capacity(SAMPLES);
+    let mut hl = 0;
+    let read = |hl: &mut usize| -> Result<u8, String> {
+        match comp.get(*hl) {
+            Some(&byte) => {
+                *hl += 1;
+                Ok(byte)
+            },
+            None => Err("Ran past the end of the compressed audio".to_string()),
+        }
+    };
+    let mut nibbles = false;
+    while diffs.len() < SAMPLES {
+        let byte = read(&mut hl)?;
+        if !nibbles {
+            // Byte-wide offset, bit 0 flags switching to nibbles
+            diffs.push((byte as i8) >> 1);
+            nibbles = byte & 0x01 != 0;
+        } else {
+            // Two nibble-wide offsets, a value of 8 switches back to bytes
+            let lower = ((byte << 4) as i8) >> 4;
+            if lower == -8 {
+                nibbles = false;
+                continue;
+            }
+            diffs.push(lower);
+            if diffs.len() >= SAMPLES {
+                break;
+            }
+            let upper = (byte as i8) >> 4;
+            if upper == -8 {
+                nibbles = false;
+                continue;
+            }
+            diffs.push(upper);
+        }
+    }
+    let start = read(&mut hl)?;
+    Ok((diffs, start, hl))
+}
+
+
+pub fn apply_diffs(diffs: &[i8], start: u8) -> Vec<u8> {
+    // Gets the samples output by the player, each difference gets added to the current sample twice
+    let mut samp = start;
+    diffs.iter().map(|&diff| {
+        samp = samp.wrapping_add((diff as u8).wrapping_mul(2));
+        samp
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::{nib_diff, instr};
+    
+    fn round_trip(samps: &[u8], start: u8) {
+        // Samples only keep their upper 7 bits, the start sample goes after the differences
+        let mut comp = instr::gen_bytecode(&nib_diff::compress(samps, start).0);
+        comp.push(start);
+        let (diffs, read_start, read) = dec_aud(&comp).unwrap();
+        assert_eq!(read, comp.len());
+        assert_eq!(read_start, start);
+        let expected: Vec<u8> = samps.iter().map(|samp| samp & 0xFE).collect();
+        assert_eq!(apply_diffs(&diffs, start), expected);
+    }
+    
+    #[test]
+    fn nibbles() {
+        // Small steps are nibble-wide after the first sample
+        let samps: Vec<u8> = (0..SAMPLES).map(|i| (128.0 + 100.0 * (i as f32 / 20.0).sin()) as u8).collect();
+        round_trip(&samps, 128);
+    }
+    
+    #[test]
+    fn bytes() {
+        let samps: Vec<u8> = (0..SAMPLES).map(|i| (i * 97 % 256) as u8).collect();
+        round_trip(&samps, 0);
+    }
+    
+    #[test]
+    fn switching() {
+        // Runs of small steps broken up by jumps, including a switch landing on the last sample
+        let mut samps: Vec<u8> = (0..SAMPLES).map(|i| match i % 50 < 40 {
+            true => 60 + (i % 50) as u8 * 2,
+            false => (i * 53 % 256) as u8,
+        }).collect();
+        samps[SAMPLES - 2] = samps[SAMPLES - 3].wrapping_add(120);
+        samps[SAMPLES - 1] = samps[SAMPLES - 2].wrapping_add(6);
+        round_trip(&samps, 200);
+    }
+    
+}
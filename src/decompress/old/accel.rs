@@ -0,0 +1,39 @@
+// Reference decompressor for the audio produced by compress::old::accel
+
+
+
+pub fn dec_aud(comp: &[u8], samps: &mut [u8]) -> Result<usize, String> {
+    // Decompresses 7 bit samples until the buffer is full, returns the number of compressed bytes
+    // read
+    let mut bitpos = 0;
+    let mut read = |num: usize| -> Result<u8, String> {
+        let mut bits = 0;
+        for _i in 0..num {
+            let byte = match comp.get(bitpos / 8) {
+                Some(&byte) => byte,
+                None => return Err("Ran past the end of the compressed audio".to_string()),
+            };
+            bits = (bits << 1) + ((byte >> (7 - bitpos % 8)) & 0x01);
+            bitpos += 1;
+        }
+        Ok(bits)
+    };
+    let mut prev_samp: i16 = 196;
+    let mut prev_diff: i16 = 0;
+    for samp in samps.iter_mut() {
+        let accel = |bits: u8| (((bits << 4) as i8) >> 4) as i16;
+        let next = match read(2)? {
+            0b00 => prev_samp + prev_diff,
+            0b10 => prev_samp + prev_diff + accel(read(4)?),
+            0b11 => prev_samp - prev_diff - accel(read(4)?),
+            _ => (read(6)? * 2) as i16,
+        };
+        if !(0..128).contains(&next) {
+            return Err(format!("Sample {} out of range", next));
+        }
+        *samp = next as u8;
+        prev_diff = next - prev_samp;
+        prev_samp = next;
+    }
+    Ok(bitpos.div_ceil(8))
+}
@@ -0,0 +1,41 @@
+// Reference decompressor for the audio produced by compress::old::diff_abs
+
+
+
+pub fn dec_aud(comp: &[u8], samps: &mut [u8]) -> Result<usize, String> {
+    // Decompresses 7 bit samples until the buffer is full, returns the number of compressed bytes
+    // read
+    let mut hl = 0;
+    let mut pos = 0;
+    let mut samp: u8 = 0;
+    let mut relative = false;
+    while pos < samps.len() {
+        let byte = match comp.get(hl) {
+            Some(&byte) => byte,
+            None => return Err("Ran past the end of the compressed audio".to_string()),
+        };
+        hl += 1;
+        if !relative {
+            // Absolute sample, the msb flags switching to relative
+            samp = byte & 0x7F;
+            samps[pos] = samp;
+            pos += 1;
+            relative = byte & 0x80 != 0;
+        } else {
+            // Two nibble-wide offsets, upper first, a value of 8 switches back to absolute
+            for nib in [(byte as i8) >> 4, ((byte << 4) as i8) >> 4] {
+                if nib == -8 {
+                    relative = false;
+                    break;
+                }
+                if pos >= samps.len() {
+                    break;
+                }
+                samp = (samp as i8 + nib) as u8;
+                samps[pos] = samp;
+                pos += 1;
+            }
+        }
+    }
+    Ok(hl)
+}
@@ -0,0 +1,6 @@
+
+
+// Reference decompressors for the schemes in compress::old
+pub mod accel;
+pub mod diff_abs;
+pub mod rle_lzss;
@@ -0,0 +1,59 @@
+// Reference decompressor for the image bytecode produced by compress::old::rle_lzss
+
+
+
+pub fn dec_img(comp: &[u8], img: &mut [u8]) -> Result<usize, String> {
+    // Decompresses until the image is full, returns the number of compressed bytes read
+    let mut hl = 0;
+    let mut de = 0;
+    let read = |hl: &mut usize| -> Result<u8, String> {
+        match comp.get(*hl) {
+            Some(&byte) => {
+                *hl += 1;
+                Ok(byte)
+            },
+            None => Err("Ran past the end of the compressed image".to_string()),
+        }
+    };
+    while de < img.len() {
+        let a = read(&mut hl)?;
+        let len = (a >> 2) as usize + 1;
+        if de + len > img.len() {
+            return Err(format!("Decompressed image longer than {} bytes", img.len()));
+        }
+        match a & 0x03 {
+            0 => {
+                // LZSS, offset stored in 1 or 2 bytes
+                let off = read(&mut hl)?;
+                let offset = match off & 0x01 != 0 {
+                    true => (((off >> 1) as usize) << 8) + read(&mut hl)? as usize,
+                    false => (off >> 1) as usize,
+                };
+                if offset == 0 || offset > de {
+                    return Err(format!("LZSS offset {} out of range at image byte {}", offset, de));
+                }
+                // Overlapping copies repeat
+                for _i in 0..len {
+                    img[de] = img[de - offset];
+                    de += 1;
+                }
+            },
+            1 => {
+                // Data stream
+                for _i in 0..len {
+                    img[de] = read(&mut hl)?;
+                    de += 1;
+                }
+            },
+            2 => {
+                img[de..de+len].fill(0x00);
+                de += len;
+            },
+            _ => {
+                img[de..de+len].fill(0xFF);
+                de += len;
+            },
+        }
+    }
+    Ok(hl)
+}
@@ -0,0 +1,89 @@
+use crate::emulator::z80::Bus;
+
+
+
+// TI-83+SE memory map & paging
+// $0000-$3FFF  Flash page 0 (OS, not loaded so reads as erased flash)
+// $4000-$7FFF  Page in port 6, the app's first page while it's running
+// $8000-$BFFF  Page in port 7, the video data pages get swapped in here
+// $C000-$FFFF  RAM page in port 5, normally RAM page 0
+// Flash pages only exist for the app being tested, which is installed from APP_PAGE downwards
+
+
+pub const PAGE_SIZE: usize = 16384;
+pub const APP_PAGE: u8 = 0x69;      // First app page on a TI-83+SE
+const RAM_PAGES: usize = 8;
+const RAM_FLAG: u8 = 0x80;          // Set in a page number to select a RAM page
+
+
+pub struct Calc {
+    app: Vec<u8>,
+    ram: Vec<u8>,
+    ports: [u8; 256],
+}
+impl Calc {
+
+    pub fn new(app: Vec<u8>) -> Calc {
+        let mut ports = [0xFF; 256];
+        ports[5] = 0;
+        ports[6] = APP_PAGE;
+        ports[7] = RAM_FLAG | 1;
+        Calc {
+            app,
+            ram: vec![0; PAGE_SIZE * RAM_PAGES],
+            ports,
+        }
+    }
+
+    pub fn num_app_pages(&self) -> usize {
+        self.app.len().div_ceil(PAGE_SIZE)
+    }
+
+    fn map(&self, addr: u16) -> Option<(bool, usize)> {
+        // Get whether an address is in RAM along with its position in that RAM or app
+        let page = match addr >> 14 {
+            0 => return None,
+            1 => self.ports[6],
+            2 => self.ports[7],
+            _ => self.ports[5] | RAM_FLAG,
+        };
+        let offset = addr as usize % PAGE_SIZE;
+        if page & RAM_FLAG != 0 {
+            Some((true, (page & !RAM_FLAG) as usize % RAM_PAGES * PAGE_SIZE + offset))
+        } else if page <= APP_PAGE && ((APP_PAGE - page) as usize) < self.num_app_pages() {
+            Some((false, (APP_PAGE - page) as usize * PAGE_SIZE + offset))
+        } else {
+            None
+        }
+    }
+
+}
+impl Bus for Calc {
+
+    fn read(&mut self, addr: u16) -> u8 {
+        match self.map(addr) {
+            Some((true, pos)) => self.ram[pos],
+            Some((false, pos)) => match self.app.get(pos) {
+                Some(&b) => b,
+                None => 0xFF,
+            },
+            None => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        // Flash can't be written to without the unlock sequence, which the player never uses
+        if let Some((true, pos)) = self.map(addr) {
+            self.ram[pos] = val;
+        }
+    }
+
+    fn port_in(&mut self, port: u8) -> u8 {
+        self.ports[port as usize]
+    }
+
+    fn port_out(&mut self, port: u8, val: u8) {
+        self.ports[port as usize] = val;
+    }
+
+}
@@ -0,0 +1,3 @@
+pub mod calc;
+pub mod player;
+pub mod z80;
@@ -0,0 +1,279 @@
+use crate::emulator::z80::{Z80, Bus};
+use crate::emulator::calc::{Calc, PAGE_SIZE};
+use crate::decompress;
+use crate::load::layout::Layout;
+use crate::helper::macros::passerr;
+use crate::helper::funcs::print_ln_if;
+use crate::VArgs;
+use crate::video::schedule::Schedule;
+use std::fs;
+
+
+
+// Player variables & buffers, these have to match audVid.z80
+const CODE_RAM: u16 = 0xC400;
+const IS_PAUSED: u16 = 0xCA00;
+const DEC_STATE: u16 = 0xCA01;
+const CUR_IMG_BUFF: u16 = 0xCA02;
+const IN_VID: u16 = 0xCA06;
+const CUR_AUD_BUFF: u16 = 0xCA0A;
+const START_SAMP: u16 = 0xCA0C;
+const FRAME_LIST_CUR: u16 = 0xCA18;
+const AHEAD_IMG_START: u16 = 0xCA1A;
+const COMP_IMG_START: u16 = 0xCA1C;
+const COMP_AUD_START: u16 = 0xCA1E;
+const IMG_A: u16 = 0xE000;
+const IMG_C: u16 = 0xEC00;
+const IMG_STEP: u16 = 0x0600;
+const IMG_BUFFERS: usize = 3;
+const AUD_A: u16 = 0xFA00;
+const AUD_B: u16 = 0xFC00;
+const STACK: u16 = 0xFFF0;
+// Video data page header
+const PAGE_TYPE: u16 = 0x8000;
+const LAST_FRAME: u16 = 0x8002;
+const FIRST_FRAME: u16 = 0x8004;
+
+const LAYOUT_FLAG: u16 = 0x4000;        // Bit flipped in the pointers of images stored in other layouts
+const IMG_SIZE: usize = 1536;
+const AUD_SIZE: usize = 512;
+const COMP_READ_MAX: u16 = 0x1000;      // Most compressed bytes handed to the reference decompressor
+const MAX_FRAME_CYCLES: u64 = 10_000_000;
+
+// ld hl, src \ ld de, codeRam \ ld bc, size \ ldir
+const RAM_COPY_PATTERN: [Option<u8>; 11] = [Some(0x21), None, None, Some(0x11), Some(CODE_RAM as u8), Some((CODE_RAM >> 8) as u8), Some(0x01), None, None, Some(0xED), Some(0xB0)];
+// The last thing in the RAM routines, copying any less leaves the error screens printing garbage
+const RAM_ROUTINES_END: &[u8] = b"Press ON to exit\0";
+// ld hl, decState \ ld a, (hl) \ or a \ jr nz, $-2
+const DEC_LOOP_PATTERN: [Option<u8>; 7] = [Some(0x21), Some(DEC_STATE as u8), Some((DEC_STATE >> 8) as u8), Some(0x7E), Some(0xB7), Some(0x20), Some(0xFC)];
+// ld hl, (compAudStart), where the loop moves on to the audio
+const DEC_AUD_PATTERN: [Option<u8>; 3] = [Some(0x2A), Some(COMP_AUD_START as u8), Some((COMP_AUD_START >> 8) as u8)];
+
+
+
+pub fn check_app(path: &str, est_cycles: &[usize], args: &VArgs) -> Result<(), String> {
+    // Runs the player's decompression loop over every frame in the app in the order it would
+    // play them, checking each frame against the reference decompressor and timing it
+    if !args.codecs.playable() {
+        return Err(format!("The player can't decompress {} images & {} audio", args.codecs.img.name(), args.codecs.aud.name()));
+    }
+    let app = passerr!(fs::read(path), "Error reading app to emulate: {}");
+    let mut calc = Calc::new(app);
+    let mut cpu = Z80::new();
+    let (dec_loop, dec_aud) = load_player(&mut calc)?;
+    // Setup pages like the player does when it starts
+    let page = calc.port_in(6);
+    calc.port_out(7, page.wrapping_sub(1));
+    // Frame 0 is decompressed into the last buffer, the player's RAM starts cleared
+    let mut img_buff = IMG_C;
+    let mut aud_buff = AUD_A;
+    let mut ref_imgs = vec![vec![0; IMG_SIZE]; IMG_BUFFERS];
+    let mut scratch_img = vec![0; IMG_SIZE];
+    let mut frame_ptr = FIRST_FRAME;
+    let mut frame_cycles: Vec<usize> = Vec::with_capacity(est_cycles.len());
+    let mut schedule = Schedule::new(args);
+    let mut behind: Option<String> = None;
+    let mut ahead_cycles = 0;
+    loop {
+        let frame = frame_cycles.len();
+        let img_ptr = read_word(&mut calc, frame_ptr);
+        let aud_ptr = read_word(&mut calc, frame_ptr + 2);
+        // Decompress with the reference decompressor
+        let buff_ind = ((img_buff - IMG_A) / IMG_STEP) as usize;
+        let prev_img = ref_imgs[(buff_ind + IMG_BUFFERS - 1) % IMG_BUFFERS].clone();
+        // Images in other layouts go through the scratch buffer, their pointer has bits 14 & 15
+        // matching and they start with which layout they're in
+        let flagged = (img_ptr >> 15) == (img_ptr >> 14) & 1;
+        let comp_img = read_bytes(&mut calc, img_ptr ^ (flagged as u16 * LAYOUT_FLAG), COMP_READ_MAX);
+        let dec = match flagged {
+            true => match Layout::from_id(comp_img[0]) {
+                Some(layout) => decompress::lzss_alt::dec_img(&comp_img[1..], &mut scratch_img, None).map(|_| ref_imgs[buff_ind] = layout.restore(&scratch_img)),
+                None => Err(format!("Unknown image layout {}", comp_img[0])),
+            },
+            false => decompress::lzss_alt::dec_img(&comp_img, &mut ref_imgs[buff_ind], Some(&prev_img)).map(|_| ()),
+        };
+        if let Err(err) = dec {
+            return Err(format!("Frame {} image failed to decompress: {}", frame, err));
+        }
+        let comp_aud = read_bytes(&mut calc, aud_ptr, COMP_READ_MAX);
+        let (ref_diffs, ref_start, _) = match decompress::nib_diff::dec_aud(&comp_aud) {
+            Ok(dec) => dec,
+            Err(err) => return Err(format!("Frame {} audio failed to decompress: {}", frame, err)),
+        };
+        // Decompress with the player
+        write_word(&mut calc, COMP_IMG_START, img_ptr);
+        write_word(&mut calc, COMP_AUD_START, aud_ptr);
+        write_word(&mut calc, CUR_IMG_BUFF, img_buff);
+        write_word(&mut calc, CUR_AUD_BUFF, aud_buff);
+        let (img_cycles, aud_cycles) = match run_dec_loop(&mut cpu, &mut calc, dec_loop, dec_aud) {
+            Ok(cycles) => cycles,
+            Err(err) => return Err(format!("Frame {}: {}", frame, err)),
+        };
+        // Any of the image the player got through ahead of time counts towards this frame
+        let img_cycles = ahead_cycles + img_cycles;
+        frame_cycles.push(img_cycles + aud_cycles);
+        // The player starts on a frame early unless it's the first on its page
+        if let Err(err) = schedule.add_frame(img_cycles, aud_cycles, frame_ptr != FIRST_FRAME) {
+            behind.get_or_insert(format!("Frame {}: {}", frame, err));
+        }
+        // Check both agree
+        let img = read_bytes(&mut calc, img_buff, IMG_SIZE as u16);
+        if let Some(pos) = (0..IMG_SIZE).find(|&i| img[i] != ref_imgs[buff_ind][i]) {
+            return Err(format!("Frame {} image decompressed by the player differs at byte {}", frame, pos));
+        }
+        let diffs = read_bytes(&mut calc, aud_buff, AUD_SIZE as u16);
+        if let Some(pos) = (0..AUD_SIZE).find(|&i| diffs[i] as i8 != ref_diffs[i]) {
+            return Err(format!("Frame {} audio decompressed by the player differs at sample {}", frame, pos));
+        }
+        if calc.read(START_SAMP) != ref_start {
+            return Err(format!("Frame {} audio decompressed by the player has the wrong start sample", frame));
+        }
+        // The player starts on the next image early when it can
+        ahead_cycles = match run_ahead(&mut cpu, &mut calc, dec_loop, frame_ptr) {
+            Ok(cycles) => cycles,
+            Err(err) => return Err(format!("Frame {}: {}", frame + 1, err)),
+        };
+        // Move to next frame
+        if frame_ptr >= read_word(&mut calc, LAST_FRAME) {
+            if calc.read(PAGE_TYPE) & 0x02 != 0 {
+                break;
+            }
+            let page = calc.port_in(7);
+            calc.port_out(7, page.wrapping_sub(1));
+            frame_ptr = FIRST_FRAME;
+        } else {
+            frame_ptr += 4;
+        }
+        // Rotate buffers
+        img_buff = match img_buff >= IMG_C {
+            true => IMG_A,
+            false => img_buff + IMG_STEP,
+        };
+        aud_buff ^= AUD_A ^ AUD_B;
+    }
+    report(&frame_cycles, est_cycles, behind, args)
+}
+
+
+fn report(frame_cycles: &[usize], est_cycles: &[usize], behind: Option<String>, args: &VArgs) -> Result<(), String> {
+    if frame_cycles.len() != est_cycles.len() {
+        return Err(format!("App contains {} frames but {} were converted", frame_cycles.len(), est_cycles.len()));
+    }
+    // Compare cycle counts with the estimates they were limited by
+    let mut max = (0, 0);
+    let mut under = (0, 0);
+    let mut total_err = 0;
+    for (frame, (&real, &est)) in frame_cycles.iter().zip(est_cycles).enumerate() {
+        if real > max.0 {
+            max = (real, frame);
+        }
+        if real > est && real - est > under.0 {
+            under = (real - est, frame);
+        }
+        total_err += real as isize - est as isize;
+    }
+    print_ln_if(format!("\nEmulated {} frames", frame_cycles.len()), !args.mute);
+    print_ln_if(format!("Max. Frame Cycles: {} (frame {})", max.0, max.1), !args.mute);
+    print_ln_if(format!("Avg. Cycle Estimate Error: {}", total_err / frame_cycles.len().max(1) as isize), !args.mute);
+    print_ln_if(format!("Worst Cycle Underestimate: {} (frame {})", under.0, under.1), !args.mute);
+    // Frames borrowing cycles only need to be finished by the time they're shown
+    if args.borrow {
+        return match behind {
+            Some(err) => Err(err),
+            None => Ok(()),
+        };
+    }
+    // Any frame over the limit risks not being finished before the player needs it
+    let over: Vec<usize> = (0..frame_cycles.len()).filter(|&i| frame_cycles[i] > args.cycle_limit).collect();
+    match over.first() {
+        Some(&first) => Err(format!("{} frames take more than the cycle limit of {} to decompress, starting with frame {} at {} cycles. The player would stop on a decompression error", over.len(), args.cycle_limit, first, frame_cycles[first])),
+        None => Ok(()),
+    }
+}
+
+
+fn load_player(calc: &mut Calc) -> Result<(u16, u16), String> {   // Decompression loop, where it starts on the audio
+    // Copy the RAM routines into place the same way the player's startup code does and find the
+    // decompression loop in them
+    let first_page = read_bytes(calc, 0x4000, PAGE_SIZE as u16);
+    let pos = match find_pattern(&first_page, &RAM_COPY_PATTERN) {
+        Some(pos) => pos,
+        None => return Err("Could not find where the player copies its RAM routines".to_string()),
+    };
+    let src = first_page[pos+1] as u16 | ((first_page[pos+2] as u16) << 8);
+    let size = first_page[pos+7] as u16 | ((first_page[pos+8] as u16) << 8);
+    let routines = read_bytes(calc, src, size);
+    if !routines.ends_with(RAM_ROUTINES_END) {
+        return Err(format!("The player only copies ${:04X} bytes of its RAM routines, they don't end with the exit prompt", size));
+    }
+    for (i, &b) in routines.iter().enumerate() {
+        calc.write(CODE_RAM + i as u16, b);
+    }
+    let dec_loop = match find_pattern(&routines, &DEC_LOOP_PATTERN) {
+        Some(pos) => pos,
+        None => return Err("Could not find the player's decompression loop".to_string()),
+    };
+    match find_pattern(&routines[dec_loop..], &DEC_AUD_PATTERN) {
+        Some(pos) => Ok((CODE_RAM + dec_loop as u16, CODE_RAM + (dec_loop + pos) as u16)),
+        None => Err("Could not find where the player's decompression loop starts on the audio".to_string()),
+    }
+}
+
+
+fn run_dec_loop(cpu: &mut Z80, calc: &mut Calc, dec_loop: u16, dec_aud: u16) -> Result<(usize, usize), String> {    // Image cycles, audio cycles
+    // Runs one pass of the decompression loop, finishing once it marks the frame as done
+    calc.write(DEC_STATE, 0);
+    cpu.pc = dec_loop;
+    cpu.sp = STACK;
+    let start = cpu.cycles;
+    let mut aud_start = None;
+    while calc.read(DEC_STATE) == 0 {
+        if cpu.pc == dec_aud {
+            aud_start.get_or_insert(cpu.cycles);
+        }
+        cpu.step(calc);
+        if cpu.cycles - start > MAX_FRAME_CYCLES {
+            return Err(format!("player did not finish decompressing after {} cycles, stuck at ${:04X}", MAX_FRAME_CYCLES, cpu.pc));
+        }
+    }
+    let aud_start = aud_start.unwrap_or(start);
+    Ok(((aud_start - start) as usize, (cpu.cycles - aud_start) as usize))
+}
+
+
+fn run_ahead(cpu: &mut Z80, calc: &mut Calc, dec_loop: u16, frame_ptr: u16) -> Result<usize, String> {  // Cycles spent on the next image, 0 if it wasn't started
+    // Carries on from where the decompression loop marked the frame as done the way it does while
+    // the video is playing, finishing once it's back waiting for the next frame
+    write_word(calc, FRAME_LIST_CUR, frame_ptr);
+    calc.write(IN_VID, 1);
+    calc.write(IS_PAUSED, 0);
+    let start = cpu.cycles;
+    while cpu.pc != dec_loop {
+        cpu.step(calc);
+        if cpu.cycles - start > MAX_FRAME_CYCLES {
+            return Err(format!("player did not finish decompressing ahead after {} cycles, stuck at ${:04X}", MAX_FRAME_CYCLES, cpu.pc));
+        }
+    }
+    match read_word(calc, AHEAD_IMG_START) {
+        0 => Ok(0),
+        _ => Ok((cpu.cycles - start) as usize),
+    }
+}
+
+
+fn find_pattern(data: &[u8], pattern: &[Option<u8>]) -> Option<usize> {
+    data.windows(pattern.len()).position(|w| w.iter().zip(pattern).all(|(&b, p)| p.is_none_or(|p| p == b)))
+}
+
+fn read_bytes(calc: &mut Calc, addr: u16, len: u16) -> Vec<u8> {
+    (0..len).map(|i| calc.read(addr.wrapping_add(i))).collect()
+}
+
+fn read_word(calc: &mut Calc, addr: u16) -> u16 {
+    calc.read(addr) as u16 | ((calc.read(addr.wrapping_add(1)) as u16) << 8)
+}
+
+fn write_word(calc: &mut Calc, addr: u16, val: u16) {
+    calc.write(addr, val as u8);
+    calc.write(addr.wrapping_add(1), (val >> 8) as u8);
+}
@@ -0,0 +1,853 @@
+
+
+// Cycle-counting Z80 core
+// Covers the documented instruction set along with the undocumented instructions audVid.z80 uses
+// (SLI, IN (C), OUT (C),0 and the IXH/IXL/IYH/IYL registers). Interrupts are not modelled, the
+// player routines are called directly instead
+
+
+
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, val: u8);
+    fn port_in(&mut self, port: u8) -> u8;
+    fn port_out(&mut self, port: u8, val: u8);
+}
+
+
+const FLAG_C: u8 = 0x01;
+const FLAG_N: u8 = 0x02;
+const FLAG_PV: u8 = 0x04;
+const FLAG_X: u8 = 0x08;
+const FLAG_H: u8 = 0x10;
+const FLAG_Y: u8 = 0x20;
+const FLAG_Z: u8 = 0x40;
+const FLAG_S: u8 = 0x80;
+
+
+#[derive(Clone, Copy, PartialEq)]
+enum Index {
+    HL,
+    IX,
+    IY,
+}
+
+
+#[derive(Default)]
+pub struct Z80 {
+    pub a: u8, pub f: u8,
+    pub b: u8, pub c: u8,
+    pub d: u8, pub e: u8,
+    pub h: u8, pub l: u8,
+    pub a_: u8, pub f_: u8,
+    pub b_: u8, pub c_: u8,
+    pub d_: u8, pub e_: u8,
+    pub h_: u8, pub l_: u8,
+    pub ix: u16,
+    pub iy: u16,
+    pub sp: u16,
+    pub pc: u16,
+    pub i: u8,
+    pub r: u8,
+    pub iff1: bool,
+    pub iff2: bool,
+    pub im: u8,
+    pub halted: bool,
+    pub cycles: u64,
+}
+
+
+fn parity(val: u8) -> bool {
+    val.count_ones().is_multiple_of(2)
+}
+
+fn szxy(val: u8) -> u8 {
+    // Sign, zero and the two undocumented flags for a result
+    let mut f = val & (FLAG_S | FLAG_Y | FLAG_X);
+    if val == 0 {
+        f |= FLAG_Z;
+    }
+    f
+}
+
+fn szxyp(val: u8) -> u8 {
+    szxy(val) | match parity(val) {
+        true => FLAG_PV,
+        false => 0,
+    }
+}
+
+
+impl Z80 {
+
+    pub fn new() -> Z80 {
+        Z80 {a: 0xFF, f: 0xFF, sp: 0xFFFF, ..Default::default()}
+    }
+
+
+    /*          Register helpers        */
+
+    pub fn bc(&self) -> u16 { ((self.b as u16) << 8) | self.c as u16 }
+    pub fn de(&self) -> u16 { ((self.d as u16) << 8) | self.e as u16 }
+    pub fn hl(&self) -> u16 { ((self.h as u16) << 8) | self.l as u16 }
+    pub fn af(&self) -> u16 { ((self.a as u16) << 8) | self.f as u16 }
+    pub fn set_bc(&mut self, val: u16) { self.b = (val >> 8) as u8; self.c = val as u8; }
+    pub fn set_de(&mut self, val: u16) { self.d = (val >> 8) as u8; self.e = val as u8; }
+    pub fn set_hl(&mut self, val: u16) { self.h = (val >> 8) as u8; self.l = val as u8; }
+    pub fn set_af(&mut self, val: u16) { self.a = (val >> 8) as u8; self.f = val as u8; }
+
+    fn idx(&self, idx: Index) -> u16 {
+        match idx {
+            Index::HL => self.hl(),
+            Index::IX => self.ix,
+            Index::IY => self.iy,
+        }
+    }
+    fn set_idx(&mut self, idx: Index, val: u16) {
+        match idx {
+            Index::HL => self.set_hl(val),
+            Index::IX => self.ix = val,
+            Index::IY => self.iy = val,
+        }
+    }
+
+    fn get_rp(&self, p: u8, idx: Index) -> u16 {
+        // Register pairs with SP
+        match p {
+            0 => self.bc(),
+            1 => self.de(),
+            2 => self.idx(idx),
+            _ => self.sp,
+        }
+    }
+    fn set_rp(&mut self, p: u8, idx: Index, val: u16) {
+        match p {
+            0 => self.set_bc(val),
+            1 => self.set_de(val),
+            2 => self.set_idx(idx, val),
+            _ => self.sp = val,
+        }
+    }
+    fn get_rp2(&self, p: u8, idx: Index) -> u16 {
+        // Register pairs with AF
+        match p {
+            3 => self.af(),
+            _ => self.get_rp(p, idx),
+        }
+    }
+    fn set_rp2(&mut self, p: u8, idx: Index, val: u16) {
+        match p {
+            3 => self.set_af(val),
+            _ => self.set_rp(p, idx, val),
+        }
+    }
+
+    fn get_reg(&self, r: u8, idx: Index) -> u8 {
+        // 8-bit registers other than (HL), H & L are replaced by the index halves when prefixed
+        match r {
+            0 => self.b,
+            1 => self.c,
+            2 => self.d,
+            3 => self.e,
+            4 => (self.idx(idx) >> 8) as u8,
+            5 => self.idx(idx) as u8,
+            _ => self.a,
+        }
+    }
+    fn set_reg(&mut self, r: u8, idx: Index, val: u8) {
+        match r {
+            0 => self.b = val,
+            1 => self.c = val,
+            2 => self.d = val,
+            3 => self.e = val,
+            4 => { let v = (self.idx(idx) & 0x00FF) | ((val as u16) << 8); self.set_idx(idx, v); },
+            5 => { let v = (self.idx(idx) & 0xFF00) | val as u16; self.set_idx(idx, v); },
+            _ => self.a = val,
+        }
+    }
+
+    fn cond(&self, y: u8) -> bool {
+        match y {
+            0 => self.f & FLAG_Z == 0,
+            1 => self.f & FLAG_Z != 0,
+            2 => self.f & FLAG_C == 0,
+            3 => self.f & FLAG_C != 0,
+            4 => self.f & FLAG_PV == 0,
+            5 => self.f & FLAG_PV != 0,
+            6 => self.f & FLAG_S == 0,
+            _ => self.f & FLAG_S != 0,
+        }
+    }
+
+
+    /*          Memory helpers        */
+
+    fn fetch(&mut self, bus: &mut dyn Bus) -> u8 {
+        let val = bus.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        val
+    }
+    fn fetch16(&mut self, bus: &mut dyn Bus) -> u16 {
+        let lo = self.fetch(bus) as u16;
+        let hi = self.fetch(bus) as u16;
+        (hi << 8) | lo
+    }
+    fn read16(&mut self, bus: &mut dyn Bus, addr: u16) -> u16 {
+        let lo = bus.read(addr) as u16;
+        let hi = bus.read(addr.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+    fn write16(&mut self, bus: &mut dyn Bus, addr: u16, val: u16) {
+        bus.write(addr, val as u8);
+        bus.write(addr.wrapping_add(1), (val >> 8) as u8);
+    }
+    pub fn push(&mut self, bus: &mut dyn Bus, val: u16) {
+        self.sp = self.sp.wrapping_sub(2);
+        let sp = self.sp;
+        self.write16(bus, sp, val);
+    }
+    pub fn pop(&mut self, bus: &mut dyn Bus) -> u16 {
+        let sp = self.sp;
+        let val = self.read16(bus, sp);
+        self.sp = self.sp.wrapping_add(2);
+        val
+    }
+    fn inc_r(&mut self) {
+        self.r = (self.r & 0x80) | (self.r.wrapping_add(1) & 0x7F);
+    }
+
+
+    /*          ALU        */
+
+    fn add8(&mut self, val: u8, carry: bool) {
+        let c = (carry && self.f & FLAG_C != 0) as u16;
+        let res = self.a as u16 + val as u16 + c;
+        let r8 = res as u8;
+        let mut f = szxy(r8);
+        if (self.a & 0x0F) as u16 + (val & 0x0F) as u16 + c > 0x0F { f |= FLAG_H; }
+        if (self.a ^ val) & 0x80 == 0 && (self.a ^ r8) & 0x80 != 0 { f |= FLAG_PV; }
+        if res > 0xFF { f |= FLAG_C; }
+        self.a = r8;
+        self.f = f;
+    }
+    fn sub8(&mut self, val: u8, carry: bool, store: bool) -> u8 {
+        let c = (carry && self.f & FLAG_C != 0) as i16;
+        let res = self.a as i16 - val as i16 - c;
+        let r8 = res as u8;
+        let mut f = szxy(r8) | FLAG_N;
+        if ((self.a & 0x0F) as i16) - ((val & 0x0F) as i16) - c < 0 { f |= FLAG_H; }
+        if (self.a ^ val) & 0x80 != 0 && (self.a ^ r8) & 0x80 != 0 { f |= FLAG_PV; }
+        if res < 0 { f |= FLAG_C; }
+        if store {
+            self.a = r8;
+        } else {
+            // CP takes the undocumented flags from the operand
+            f = (f & !(FLAG_Y | FLAG_X)) | (val & (FLAG_Y | FLAG_X));
+        }
+        self.f = f;
+        r8
+    }
+    fn alu(&mut self, op: u8, val: u8) {
+        match op {
+            0 => self.add8(val, false),
+            1 => self.add8(val, true),
+            2 => { self.sub8(val, false, true); },
+            3 => { self.sub8(val, true, true); },
+            4 => { self.a &= val; self.f = szxyp(self.a) | FLAG_H; },
+            5 => { self.a ^= val; self.f = szxyp(self.a); },
+            6 => { self.a |= val; self.f = szxyp(self.a); },
+            _ => { self.sub8(val, false, false); },
+        }
+    }
+    fn inc8(&mut self, val: u8) -> u8 {
+        let res = val.wrapping_add(1);
+        let mut f = (self.f & FLAG_C) | szxy(res);
+        if val & 0x0F == 0x0F { f |= FLAG_H; }
+        if val == 0x7F { f |= FLAG_PV; }
+        self.f = f;
+        res
+    }
+    fn dec8(&mut self, val: u8) -> u8 {
+        let res = val.wrapping_sub(1);
+        let mut f = (self.f & FLAG_C) | szxy(res) | FLAG_N;
+        if val & 0x0F == 0x00 { f |= FLAG_H; }
+        if val == 0x80 { f |= FLAG_PV; }
+        self.f = f;
+        res
+    }
+    fn add16(&mut self, a: u16, b: u16) -> u16 {
+        let res = a as u32 + b as u32;
+        let mut f = self.f & (FLAG_S | FLAG_Z | FLAG_PV);
+        if (a & 0x0FFF) + (b & 0x0FFF) > 0x0FFF { f |= FLAG_H; }
+        if res > 0xFFFF { f |= FLAG_C; }
+        f |= ((res >> 8) as u8) & (FLAG_Y | FLAG_X);
+        self.f = f;
+        res as u16
+    }
+    fn adc16(&mut self, a: u16, b: u16) -> u16 {
+        let c = (self.f & FLAG_C) as u32;
+        let res = a as u32 + b as u32 + c;
+        let r16 = res as u16;
+        let mut f = ((r16 >> 8) as u8) & (FLAG_S | FLAG_Y | FLAG_X);
+        if r16 == 0 { f |= FLAG_Z; }
+        if (a & 0x0FFF) as u32 + (b & 0x0FFF) as u32 + c > 0x0FFF { f |= FLAG_H; }
+        if (a ^ b) & 0x8000 == 0 && (a ^ r16) & 0x8000 != 0 { f |= FLAG_PV; }
+        if res > 0xFFFF { f |= FLAG_C; }
+        self.f = f;
+        r16
+    }
+    fn sbc16(&mut self, a: u16, b: u16) -> u16 {
+        let c = (self.f & FLAG_C) as i32;
+        let res = a as i32 - b as i32 - c;
+        let r16 = res as u16;
+        let mut f = (((r16 >> 8) as u8) & (FLAG_S | FLAG_Y | FLAG_X)) | FLAG_N;
+        if r16 == 0 { f |= FLAG_Z; }
+        if ((a & 0x0FFF) as i32) - ((b & 0x0FFF) as i32) - c < 0 { f |= FLAG_H; }
+        if (a ^ b) & 0x8000 != 0 && (a ^ r16) & 0x8000 != 0 { f |= FLAG_PV; }
+        if res < 0 { f |= FLAG_C; }
+        self.f = f;
+        r16
+    }
+    fn rot(&mut self, op: u8, val: u8) -> u8 {
+        // CB-prefixed rotates & shifts
+        let carry_in = self.f & FLAG_C;
+        let (res, carry) = match op {
+            0 => (val.rotate_left(1), val >> 7),
+            1 => (val.rotate_right(1), val & 1),
+            2 => ((val << 1) | carry_in, val >> 7),
+            3 => ((val >> 1) | (carry_in << 7), val & 1),
+            4 => (val << 1, val >> 7),
+            5 => ((val >> 1) | (val & 0x80), val & 1),
+            6 => ((val << 1) | 1, val >> 7),
+            _ => (val >> 1, val & 1),
+        };
+        self.f = szxyp(res) | carry;
+        res
+    }
+    fn rot_a(&mut self, op: u8) {
+        // RLCA, RRCA, RLA & RRA only affect the carry and undocumented flags
+        let keep = self.f & (FLAG_S | FLAG_Z | FLAG_PV);
+        let res = self.rot(op, self.a);
+        self.f = keep | (res & (FLAG_Y | FLAG_X)) | (self.f & FLAG_C);
+        self.a = res;
+    }
+    fn bit(&mut self, bit: u8, val: u8, xy: u8) {
+        let res = val & (1 << bit);
+        let mut f = (self.f & FLAG_C) | FLAG_H | (xy & (FLAG_Y | FLAG_X));
+        if res == 0 { f |= FLAG_Z | FLAG_PV; }
+        if bit == 7 && res != 0 { f |= FLAG_S; }
+        self.f = f;
+    }
+    fn daa(&mut self) {
+        let mut corr = 0;
+        let mut carry = self.f & FLAG_C;
+        if self.f & FLAG_H != 0 || self.a & 0x0F > 9 { corr |= 0x06; }
+        if carry != 0 || self.a > 0x99 { corr |= 0x60; carry = FLAG_C; }
+        let n = self.f & FLAG_N;
+        let res = match n != 0 {
+            true => self.a.wrapping_sub(corr),
+            false => self.a.wrapping_add(corr),
+        };
+        let h = match n != 0 {
+            true => self.f & FLAG_H != 0 && self.a & 0x0F < 6,
+            false => self.a & 0x0F > 9,
+        };
+        self.f = szxyp(res) | n | carry | match h { true => FLAG_H, false => 0 };
+        self.a = res;
+    }
+
+
+    /*          Execution        */
+
+    pub fn step(&mut self, bus: &mut dyn Bus) -> u32 {
+        // Executes a single instruction and returns the number of cycles it took
+        if self.halted {
+            self.cycles += 4;
+            return 4;
+        }
+        let mut idx = Index::HL;
+        let mut cycles = 0;
+        let mut op = self.fetch(bus);
+        self.inc_r();
+        // Index prefixes, only the last one counts
+        while op == 0xDD || op == 0xFD {
+            idx = match op {
+                0xDD => Index::IX,
+                _ => Index::IY,
+            };
+            cycles += 4;
+            op = self.fetch(bus);
+            self.inc_r();
+        }
+        cycles += match op {
+            0xCB => self.exec_cb(bus, idx),
+            0xED => self.exec_ed(bus),
+            _ => self.exec_main(bus, op, idx),
+        };
+        self.cycles += cycles as u64;
+        cycles
+    }
+
+    fn index_addr(&mut self, bus: &mut dyn Bus, idx: Index) -> u16 {
+        // Get (HL) or (IX+d) address
+        match idx {
+            Index::HL => self.hl(),
+            _ => {
+                let d = self.fetch(bus) as i8;
+                self.idx(idx).wrapping_add(d as u16)
+            },
+        }
+    }
+
+    fn exec_main(&mut self, bus: &mut dyn Bus, op: u8, idx: Index) -> u32 {
+        let x = op >> 6;
+        let y = (op >> 3) & 7;
+        let z = op & 7;
+        let p = y >> 1;
+        let q = y & 1;
+        let indexed = idx != Index::HL;
+        match x {
+            0 => match z {
+                0 => match y {
+                    0 => 4,
+                    1 => {
+                        std::mem::swap(&mut self.a, &mut self.a_);
+                        std::mem::swap(&mut self.f, &mut self.f_);
+                        4
+                    },
+                    2 => {
+                        let d = self.fetch(bus) as i8;
+                        self.b = self.b.wrapping_sub(1);
+                        if self.b != 0 {
+                            self.pc = self.pc.wrapping_add(d as u16);
+                            13
+                        } else {
+                            8
+                        }
+                    },
+                    3 => {
+                        let d = self.fetch(bus) as i8;
+                        self.pc = self.pc.wrapping_add(d as u16);
+                        12
+                    },
+                    _ => {
+                        let d = self.fetch(bus) as i8;
+                        if self.cond(y - 4) {
+                            self.pc = self.pc.wrapping_add(d as u16);
+                            12
+                        } else {
+                            7
+                        }
+                    },
+                },
+                1 => match q {
+                    0 => {
+                        let nn = self.fetch16(bus);
+                        self.set_rp(p, idx, nn);
+                        10
+                    },
+                    _ => {
+                        let res = self.add16(self.idx(idx), self.get_rp(p, idx));
+                        self.set_idx(idx, res);
+                        11
+                    },
+                },
+                2 => match (q, p) {
+                    (0, 0) => { bus.write(self.bc(), self.a); 7 },
+                    (0, 1) => { bus.write(self.de(), self.a); 7 },
+                    (0, 2) => { let nn = self.fetch16(bus); let v = self.idx(idx); self.write16(bus, nn, v); 16 },
+                    (0, _) => { let nn = self.fetch16(bus); bus.write(nn, self.a); 13 },
+                    (_, 0) => { self.a = bus.read(self.bc()); 7 },
+                    (_, 1) => { self.a = bus.read(self.de()); 7 },
+                    (_, 2) => { let nn = self.fetch16(bus); let v = self.read16(bus, nn); self.set_idx(idx, v); 16 },
+                    (_, _) => { let nn = self.fetch16(bus); self.a = bus.read(nn); 13 },
+                },
+                3 => {
+                    let val = self.get_rp(p, idx);
+                    let val = match q {
+                        0 => val.wrapping_add(1),
+                        _ => val.wrapping_sub(1),
+                    };
+                    self.set_rp(p, idx, val);
+                    6
+                },
+                4 | 5 => {
+                    if y == 6 {
+                        let addr = self.index_addr(bus, idx);
+                        let val = bus.read(addr);
+                        let res = match z {
+                            4 => self.inc8(val),
+                            _ => self.dec8(val),
+                        };
+                        bus.write(addr, res);
+                        match indexed { true => 19, false => 11 }
+                    } else {
+                        let val = self.get_reg(y, idx);
+                        let res = match z {
+                            4 => self.inc8(val),
+                            _ => self.dec8(val),
+                        };
+                        self.set_reg(y, idx, res);
+                        4
+                    }
+                },
+                6 => {
+                    if y == 6 {
+                        let addr = self.index_addr(bus, idx);
+                        let n = self.fetch(bus);
+                        bus.write(addr, n);
+                        match indexed { true => 15, false => 10 }
+                    } else {
+                        let n = self.fetch(bus);
+                        self.set_reg(y, idx, n);
+                        7
+                    }
+                },
+                _ => {
+                    match y {
+                        0..=3 => self.rot_a(y),
+                        4 => self.daa(),
+                        5 => {
+                            self.a = !self.a;
+                            self.f = (self.f & (FLAG_S | FLAG_Z | FLAG_PV | FLAG_C)) | FLAG_H | FLAG_N | (self.a & (FLAG_Y | FLAG_X));
+                        },
+                        6 => {
+                            self.f = (self.f & (FLAG_S | FLAG_Z | FLAG_PV)) | FLAG_C | (self.a & (FLAG_Y | FLAG_X));
+                        },
+                        _ => {
+                            let h = match self.f & FLAG_C != 0 { true => FLAG_H, false => 0 };
+                            self.f = ((self.f & (FLAG_S | FLAG_Z | FLAG_PV | FLAG_C)) | h | (self.a & (FLAG_Y | FLAG_X))) ^ FLAG_C;
+                        },
+                    };
+                    4
+                },
+            },
+            1 => {
+                if z == 6 && y == 6 {
+                    self.halted = true;
+                    4
+                } else if y == 6 {
+                    // LD (HL),r uses the real H & L even when prefixed
+                    let addr = self.index_addr(bus, idx);
+                    let val = self.get_reg(z, Index::HL);
+                    bus.write(addr, val);
+                    match indexed { true => 15, false => 7 }
+                } else if z == 6 {
+                    let addr = self.index_addr(bus, idx);
+                    let val = bus.read(addr);
+                    self.set_reg(y, Index::HL, val);
+                    match indexed { true => 15, false => 7 }
+                } else {
+                    let val = self.get_reg(z, idx);
+                    self.set_reg(y, idx, val);
+                    4
+                }
+            },
+            2 => {
+                if z == 6 {
+                    let addr = self.index_addr(bus, idx);
+                    let val = bus.read(addr);
+                    self.alu(y, val);
+                    match indexed { true => 15, false => 7 }
+                } else {
+                    let val = self.get_reg(z, idx);
+                    self.alu(y, val);
+                    4
+                }
+            },
+            _ => match z {
+                0 => {
+                    if self.cond(y) {
+                        self.pc = self.pop(bus);
+                        11
+                    } else {
+                        5
+                    }
+                },
+                1 => match (q, p) {
+                    (0, _) => { let val = self.pop(bus); self.set_rp2(p, idx, val); 10 },
+                    (_, 0) => { self.pc = self.pop(bus); 10 },
+                    (_, 1) => {
+                        std::mem::swap(&mut self.b, &mut self.b_);
+                        std::mem::swap(&mut self.c, &mut self.c_);
+                        std::mem::swap(&mut self.d, &mut self.d_);
+                        std::mem::swap(&mut self.e, &mut self.e_);
+                        std::mem::swap(&mut self.h, &mut self.h_);
+                        std::mem::swap(&mut self.l, &mut self.l_);
+                        4
+                    },
+                    (_, 2) => { self.pc = self.idx(idx); 4 },
+                    (_, _) => { self.sp = self.idx(idx); 6 },
+                },
+                2 => {
+                    let nn = self.fetch16(bus);
+                    if self.cond(y) {
+                        self.pc = nn;
+                    }
+                    10
+                },
+                3 => match y {
+                    0 => { self.pc = self.fetch16(bus); 10 },
+                    2 => { let n = self.fetch(bus); bus.port_out(n, self.a); 11 },
+                    3 => { let n = self.fetch(bus); self.a = bus.port_in(n); 11 },
+                    4 => {
+                        let sp = self.sp;
+                        let val = self.read16(bus, sp);
+                        let old = self.idx(idx);
+                        self.write16(bus, sp, old);
+                        self.set_idx(idx, val);
+                        19
+                    },
+                    5 => {
+                        // Never affected by the index prefixes
+                        let de = self.de();
+                        let hl = self.hl();
+                        self.set_de(hl);
+                        self.set_hl(de);
+                        4
+                    },
+                    6 => { self.iff1 = false; self.iff2 = false; 4 },
+                    _ => { self.iff1 = true; self.iff2 = true; 4 },
+                },
+                4 => {
+                    let nn = self.fetch16(bus);
+                    if self.cond(y) {
+                        let pc = self.pc;
+                        self.push(bus, pc);
+                        self.pc = nn;
+                        17
+                    } else {
+                        10
+                    }
+                },
+                5 => match (q, p) {
+                    (0, _) => { let val = self.get_rp2(p, idx); self.push(bus, val); 11 },
+                    _ => {
+                        let nn = self.fetch16(bus);
+                        let pc = self.pc;
+                        self.push(bus, pc);
+                        self.pc = nn;
+                        17
+                    },
+                },
+                6 => {
+                    let n = self.fetch(bus);
+                    self.alu(y, n);
+                    7
+                },
+                _ => {
+                    let pc = self.pc;
+                    self.push(bus, pc);
+                    self.pc = (y as u16) * 8;
+                    11
+                },
+            },
+        }
+    }
+
+    fn exec_cb(&mut self, bus: &mut dyn Bus, idx: Index) -> u32 {
+        // With an index prefix the displacement comes before the opcode and the result is always
+        // written back to memory (and copied to a register for the undocumented forms)
+        let addr = match idx {
+            Index::HL => None,
+            _ => Some(self.index_addr(bus, idx)),
+        };
+        let op = self.fetch(bus);
+        if addr.is_none() {
+            self.inc_r();
+        }
+        let x = op >> 6;
+        let y = (op >> 3) & 7;
+        let z = op & 7;
+        let (val, mem_addr) = match addr {
+            Some(addr) => (bus.read(addr), Some(addr)),
+            None => match z {
+                6 => { let hl = self.hl(); (bus.read(hl), Some(hl)) },
+                _ => (self.get_reg(z, Index::HL), None),
+            },
+        };
+        if x == 1 {
+            let xy = match (addr, mem_addr) {
+                (Some(addr), _) => (addr >> 8) as u8,
+                _ => val,
+            };
+            self.bit(y, val, xy);
+            return match (addr, mem_addr) {
+                (Some(_), _) => 16,
+                (None, Some(_)) => 12,
+                _ => 8,
+            };
+        }
+        let res = match x {
+            0 => self.rot(y, val),
+            2 => val & !(1 << y),
+            _ => val | (1 << y),
+        };
+        if let Some(mem_addr) = mem_addr {
+            bus.write(mem_addr, res);
+        }
+        if z != 6 {
+            self.set_reg(z, Index::HL, res);
+        }
+        match (addr, mem_addr) {
+            (Some(_), _) => 19,
+            (None, Some(_)) => 15,
+            _ => 8,
+        }
+    }
+
+    fn exec_ed(&mut self, bus: &mut dyn Bus) -> u32 {
+        let op = self.fetch(bus);
+        self.inc_r();
+        let x = op >> 6;
+        let y = (op >> 3) & 7;
+        let z = op & 7;
+        let p = y >> 1;
+        let q = y & 1;
+        match x {
+            1 => match z {
+                0 => {
+                    let val = bus.port_in(self.c);
+                    if y != 6 {
+                        self.set_reg(y, Index::HL, val);
+                    }
+                    self.f = (self.f & FLAG_C) | szxyp(val);
+                    12
+                },
+                1 => {
+                    let val = match y {
+                        6 => 0,
+                        _ => self.get_reg(y, Index::HL),
+                    };
+                    bus.port_out(self.c, val);
+                    12
+                },
+                2 => {
+                    let hl = self.hl();
+                    let rp = self.get_rp(p, Index::HL);
+                    let res = match q {
+                        0 => self.sbc16(hl, rp),
+                        _ => self.adc16(hl, rp),
+                    };
+                    self.set_hl(res);
+                    15
+                },
+                3 => {
+                    let nn = self.fetch16(bus);
+                    match q {
+                        0 => { let val = self.get_rp(p, Index::HL); self.write16(bus, nn, val); },
+                        _ => { let val = self.read16(bus, nn); self.set_rp(p, Index::HL, val); },
+                    }
+                    20
+                },
+                4 => {
+                    let val = self.a;
+                    self.a = 0;
+                    self.sub8(val, false, true);
+                    8
+                },
+                5 => {
+                    self.pc = self.pop(bus);
+                    self.iff1 = self.iff2;
+                    14
+                },
+                6 => {
+                    self.im = match y & 3 {
+                        2 => 1,
+                        3 => 2,
+                        _ => 0,
+                    };
+                    8
+                },
+                _ => match y {
+                    0 => { self.i = self.a; 9 },
+                    1 => { self.r = self.a; 9 },
+                    2 | 3 => {
+                        self.a = match y {
+                            2 => self.i,
+                            _ => self.r,
+                        };
+                        let pv = match self.iff2 { true => FLAG_PV, false => 0 };
+                        self.f = (self.f & FLAG_C) | szxy(self.a) | pv;
+                        9
+                    },
+                    4 | 5 => {
+                        let hl = self.hl();
+                        let val = bus.read(hl);
+                        let (mem, a) = match y {
+                            4 => ((self.a << 4) | (val >> 4), (self.a & 0xF0) | (val & 0x0F)),
+                            _ => ((val << 4) | (self.a & 0x0F), (self.a & 0xF0) | (val >> 4)),
+                        };
+                        bus.write(hl, mem);
+                        self.a = a;
+                        self.f = (self.f & FLAG_C) | szxyp(a);
+                        18
+                    },
+                    _ => 8,
+                },
+            },
+            2 if z <= 3 && y >= 4 => self.exec_block(bus, y, z),
+            _ => 8,
+        }
+    }
+
+    fn exec_block(&mut self, bus: &mut dyn Bus, y: u8, z: u8) -> u32 {
+        // LDI, CPI, INI, OUTI and their decrementing & repeating forms
+        let dec = y & 1 != 0;
+        let repeat = y >= 6;
+        let step = |val: u16| match dec {
+            true => val.wrapping_sub(1),
+            false => val.wrapping_add(1),
+        };
+        let bc = self.bc().wrapping_sub(1);
+        let again;
+        match z {
+            0 => {
+                let val = bus.read(self.hl());
+                bus.write(self.de(), val);
+                let (hl, de) = (step(self.hl()), step(self.de()));
+                self.set_hl(hl);
+                self.set_de(de);
+                self.set_bc(bc);
+                let n = val.wrapping_add(self.a);
+                self.f = (self.f & (FLAG_S | FLAG_Z | FLAG_C)) | (n & FLAG_X) | ((n << 4) & FLAG_Y) | match bc != 0 { true => FLAG_PV, false => 0 };
+                again = bc != 0;
+            },
+            1 => {
+                let val = bus.read(self.hl());
+                let carry = self.f & FLAG_C;
+                self.sub8(val, false, false);
+                let hl = step(self.hl());
+                self.set_hl(hl);
+                self.set_bc(bc);
+                self.f = (self.f & (FLAG_S | FLAG_Z | FLAG_H)) | FLAG_N | carry | match bc != 0 { true => FLAG_PV, false => 0 };
+                again = bc != 0 && self.f & FLAG_Z == 0;
+            },
+            2 => {
+                let val = bus.port_in(self.c);
+                bus.write(self.hl(), val);
+                let hl = step(self.hl());
+                self.set_hl(hl);
+                self.b = self.b.wrapping_sub(1);
+                self.f = szxy(self.b) | FLAG_N | (self.f & FLAG_C);
+                again = self.b != 0;
+            },
+            _ => {
+                let val = bus.read(self.hl());
+                self.b = self.b.wrapping_sub(1);
+                bus.port_out(self.c, val);
+                let hl = step(self.hl());
+                self.set_hl(hl);
+                self.f = szxy(self.b) | FLAG_N | (self.f & FLAG_C);
+                again = self.b != 0;
+            },
+        }
+        if repeat && again {
+            self.pc = self.pc.wrapping_sub(2);
+            21
+        } else {
+            16
+        }
+    }
+
+}
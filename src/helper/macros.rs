@@ -1,18 +1,5 @@
 
 
-#[macro_export]
-macro_rules! ex_variant {
-    ($variant:path, $enum:expr) => {
-        if let $variant(var) = $enum {
-            var
-        } else {
-            panic!()
-        }
-    };
-}
-pub(crate) use ex_variant;
-
-
 #[macro_export]
 macro_rules! passerr {
     ($result:expr, $message:literal) => {
@@ -1,67 +1,167 @@
-use std::io::{Read, BufReader, Bytes};
+use std::io::{Read, BufReader};
 use std::fs::File;
 use crate::helper::macros::{passerr, bound};
 use crate::helper::funcs::redist_range;
 
 
 // Simple iterator over all the samples in a wave file
+// Samples are read as 16-bit values whatever format they are stored in, multiple channels get
+// mixed together
 
 
 
+const FRAME_SAMPLES: usize = 512;
+const FORMAT_PCM: u16 = 0x0001;
+const FORMAT_FLOAT: u16 = 0x0003;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+
+#[derive(Clone, Copy, PartialEq)]
+enum SampleFormat {
+    Int(usize),     // Bytes per sample
+    Float(usize),
+}
+
+
 pub struct AudIter {
-    bytes: Bytes<BufReader<File>>,
+    reader: BufReader<File>,
+    format: SampleFormat,
+    channels: usize,
+    remaining: usize,
     range_low: i16,
     range_high: i16,
 }
 impl Iterator for AudIter {
-    type Item = Vec<u8>;
-    
+    type Item = Result<Vec<u8>, String>;
+
     fn next(&mut self) -> Option<Self::Item> {
-        // Read 512 samples
-        let mut samps: Vec<u8> = Vec::with_capacity(512);
-        for _i in 0..512 {
-            // Read sample from bytestream
-            self.bytes.next();      // Ignore least-significant byte
-            let mut signed_samp = match self.bytes.next() {
-                Some(s) => s.unwrap() as i8 as i16,
-                None => return Some(vec![((self.range_low + self.range_high) / 2) as u8; 512]),
+        // Read 512 samples, once out of samples the rest are filled with silence
+        let mut samps: Vec<u8> = Vec::with_capacity(FRAME_SAMPLES);
+        for _i in 0..FRAME_SAMPLES {
+            let s16 = match self.read_sample() {
+                Ok(Some(s)) => s,
+                Ok(None) => {
+                    samps.push(((self.range_low + self.range_high) / 2) as u8);
+                    continue;
+                },
+                Err(err) => return Some(Err(err)),
             };
-            //println!("{}", signed_samp);
+            // Only the most significant byte is used
+            let mut signed_samp = s16 >> 8;
             signed_samp *= 2;
             signed_samp = bound!(signed_samp, -128, 128);
             let samp = redist_range(signed_samp as f64, -128.0, 128.0, self.range_low as f64, self.range_high as f64) as u8;
             samps.push(samp);
         }
-        Some(samps)
+        Some(Ok(samps))
     }
 }
 
 impl AudIter {
-    pub fn new(fname: &str, range_low: i16, range_high: i16) -> Result<AudIter, String> {
+    pub fn new(fname: &str, range_low: i16, range_high: i16, sample_rate: usize) -> Result<AudIter, String> {
         // Load wave file provided
-        let f = passerr!(File::open(fname));
-        let mut bytes = BufReader::new(f).bytes();
-        // Look for start of data
-        loop {
-            if bytes.next().unwrap().unwrap() == 0x64 {
-                if bytes.next().unwrap().unwrap() == 0x61 {
-                    if bytes.next().unwrap().unwrap() == 0x74 {
-                        if bytes.next().unwrap().unwrap() == 0x61 {
-                            break;
-                        }
+        let f = passerr!(File::open(fname), "Error opening audio file: {}");
+        let mut reader = BufReader::new(f);
+        // Check RIFF header
+        let mut header = [0; 12];
+        passerr!(reader.read_exact(&mut header), "Audio file is too short to be a wave file: {}");
+        if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+            return Err("Audio file is not a wave file".to_string());
+        }
+        // Go through chunks until the data chunk, the format chunk has to come before it
+        let mut fmt: Option<(SampleFormat, usize)> = None;
+        let remaining = loop {
+            let mut chunk = [0; 8];
+            passerr!(reader.read_exact(&mut chunk), "Audio file ended before any audio data: {}");
+            let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as usize;
+            match &chunk[0..4] {
+                b"fmt " => fmt = Some(read_fmt(&mut reader, size, sample_rate)?),
+                b"data" => break size,
+                _ => {
+                    // Skip chunk, chunks are padded to an even length
+                    let skip = (size + size % 2) as u64;
+                    let skipped = passerr!(std::io::copy(&mut (&mut reader).take(skip), &mut std::io::sink()), "Error reading audio file: {}");
+                    if skipped != skip {
+                        return Err("Audio file ended before any audio data".to_string());
                     }
-                }
+                },
             }
+        };
+        let (format, channels) = match fmt {
+            Some(fmt) => fmt,
+            None => return Err("Audio file has no format chunk before its data".to_string()),
+        };
+        Ok(AudIter {reader, format, channels, remaining, range_low, range_high})
+    }
+
+    fn read_sample(&mut self) -> Result<Option<i16>, String> {
+        // Read the next sample, mixing all channels together
+        let size = match self.format {
+            SampleFormat::Int(size) | SampleFormat::Float(size) => size,
+        };
+        let block = size * self.channels;
+        if self.remaining < block {
+            return Ok(None);
         }
-        // Skip next 4 bytes
-        for _ in 0..4 {
-            bytes.next();
+        let mut buf = vec![0; block];
+        if let Err(err) = self.reader.read_exact(&mut buf) {
+            return match err.kind() {
+                std::io::ErrorKind::UnexpectedEof => {
+                    // Data chunk was shorter than it claimed
+                    self.remaining = 0;
+                    Ok(None)
+                },
+                _ => Err(format!("Error reading audio file: {}", err)),
+            };
         }
-        Ok(AudIter {bytes: bytes, range_low: range_low, range_high: range_high})
+        self.remaining -= block;
+        let total: i32 = buf.chunks(size).map(|b| match self.format {
+            SampleFormat::Int(1) => (b[0] as i32 - 128) << 8,
+            SampleFormat::Int(_) => i16::from_le_bytes([b[size-2], b[size-1]]) as i32,
+            SampleFormat::Float(4) => float_to_int(f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64),
+            SampleFormat::Float(_) => float_to_int(f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])),
+        }).sum();
+        Ok(Some((total / self.channels as i32) as i16))
     }
 }
 
 
+fn read_fmt(reader: &mut BufReader<File>, size: usize, sample_rate: usize) -> Result<(SampleFormat, usize), String> {
+    // Read & validate the format chunk
+    if size < 16 {
+        return Err("Audio file's format chunk is too short".to_string());
+    }
+    let mut buf = vec![0; size + size % 2];
+    passerr!(reader.read_exact(&mut buf), "Audio file's format chunk is cut off: {}");
+    let get16 = |pos: usize| u16::from_le_bytes([buf[pos], buf[pos+1]]);
+    let get32 = |pos: usize| u32::from_le_bytes([buf[pos], buf[pos+1], buf[pos+2], buf[pos+3]]);
+    let mut tag = get16(0);
+    let channels = get16(2) as usize;
+    let rate = get32(4) as usize;
+    let bits = get16(14) as usize;
+    if tag == FORMAT_EXTENSIBLE {
+        // Actual format is the start of the sub-format GUID
+        if size < 26 {
+            return Err("Audio file's extensible format chunk is too short".to_string());
+        }
+        tag = get16(24);
+    }
+    let format = match (tag, bits) {
+        (FORMAT_PCM, 8) | (FORMAT_PCM, 16) | (FORMAT_PCM, 24) | (FORMAT_PCM, 32) => SampleFormat::Int(bits / 8),
+        (FORMAT_FLOAT, 32) | (FORMAT_FLOAT, 64) => SampleFormat::Float(bits / 8),
+        (FORMAT_PCM, _) | (FORMAT_FLOAT, _) => return Err(format!("Audio file uses {}-bit samples, only 8, 16, 24 & 32-bit PCM or 32 & 64-bit float are supported", bits)),
+        _ => return Err(format!("Audio file uses format {:#06X}, only PCM & float are supported", tag)),
+    };
+    if channels == 0 {
+        return Err("Audio file has no channels".to_string());
+    }
+    if rate != sample_rate {
+        return Err(format!("Audio file has a sample rate of {}Hz but {}Hz is needed for the calculator's framerate, try extracting the video again", rate, sample_rate));
+    }
+    Ok((format, channels))
+}
 
 
-
+fn float_to_int(samp: f64) -> i32 {
+    (samp * 32768.0).round().clamp(-32768.0, 32767.0) as i32
+}
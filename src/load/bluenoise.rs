@@ -0,0 +1,120 @@
+use std::sync::OnceLock;
+
+
+// Blue noise threshold mask, generated once with Ulichney's void-and-cluster method
+// R. Ulichney, "The void-and-cluster method for dither array generation", Proc. SPIE 1913 (1993)
+// Every pixel gets a rank, pixels with neighbouring ranks are spread as far apart as possible so
+// thresholding against it leaves no visible pattern, unlike the Bayer mask's cross-hatching
+
+
+
+const SIZE: usize = 64;
+const SIGMA: f64 = 1.5;
+const INITIAL_FILL: usize = SIZE * SIZE / 10;
+const SEED: u32 = 0x2545F491;
+
+
+static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+
+
+pub fn mask(x: u32, y: u32) -> f64 {
+    // Threshold offset for a pixel, between -0.5 & 0.5 like the ordered dither's mask
+    let mask = MASK.get_or_init(generate);
+    mask[(y as usize % SIZE) * SIZE + (x as usize % SIZE)]
+}
+
+
+fn generate() -> Vec<f64> {
+    let mut gen = Generator::new();
+    // Start from random points, then move the point in the tightest cluster into the largest void
+    // until that puts it back where it was
+    let mut rng = SEED;
+    let mut placed = 0;
+    while placed < INITIAL_FILL {
+        rng ^= rng << 13;
+        rng ^= rng >> 17;
+        rng ^= rng << 5;
+        let pos = rng as usize % (SIZE * SIZE);
+        if !gen.ones[pos] {
+            gen.set(pos, true);
+            placed += 1;
+        }
+    }
+    loop {
+        let cluster = gen.tightest_cluster();
+        gen.set(cluster, false);
+        let void = gen.largest_void();
+        gen.set(void, true);
+        if void == cluster {
+            break;
+        }
+    }
+    let prototype = gen.clone();
+    // Rank the initial points by taking away the tightest cluster each time
+    let mut ranks = vec![0; SIZE * SIZE];
+    for rank in (0..INITIAL_FILL).rev() {
+        let cluster = gen.tightest_cluster();
+        gen.set(cluster, false);
+        ranks[cluster] = rank;
+    }
+    // Then the rest by filling the largest void each time
+    let mut gen = prototype;
+    for rank in INITIAL_FILL..SIZE * SIZE {
+        let void = gen.largest_void();
+        gen.set(void, true);
+        ranks[void] = rank;
+    }
+    ranks.iter().map(|&rank| (rank as f64 + 0.5) / (SIZE * SIZE) as f64 - 0.5).collect()
+}
+
+
+#[derive(Clone)]
+struct Generator {
+    ones: Vec<bool>,
+    energy: Vec<f64>,       // Sum of a gaussian centred on every one, wrapping around the edges
+    gaussian: Vec<f64>,     // Gaussian for each x & y distance
+}
+impl Generator {
+
+    fn new() -> Generator {
+        let gaussian = (0..SIZE * SIZE).map(|i| {
+            let dist = |d: usize| d.min(SIZE - d) as f64;
+            let (dx, dy) = (dist(i % SIZE), dist(i / SIZE));
+            (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+        }).collect();
+        Generator {ones: vec![false; SIZE * SIZE], energy: vec![0.0; SIZE * SIZE], gaussian}
+    }
+
+    fn set(&mut self, pos: usize, one: bool) {
+        self.ones[pos] = one;
+        let sign = match one {
+            true => 1.0,
+            false => -1.0,
+        };
+        let (px, py) = (pos % SIZE, pos / SIZE);
+        for y in 0..SIZE {
+            let dy = (y + SIZE - py) % SIZE;
+            for x in 0..SIZE {
+                let dx = (x + SIZE - px) % SIZE;
+                self.energy[y * SIZE + x] += sign * self.gaussian[dy * SIZE + dx];
+            }
+        }
+    }
+
+    fn tightest_cluster(&self) -> usize {
+        // The one with the most energy
+        (0..SIZE * SIZE).filter(|&i| self.ones[i]).fold(None, |best: Option<usize>, i| match best {
+            Some(best) if self.energy[best] >= self.energy[i] => Some(best),
+            _ => Some(i),
+        }).unwrap()
+    }
+
+    fn largest_void(&self) -> usize {
+        // The zero with the least energy
+        (0..SIZE * SIZE).filter(|&i| !self.ones[i]).fold(None, |best: Option<usize>, i| match best {
+            Some(best) if self.energy[best] <= self.energy[i] => Some(best),
+            _ => Some(i),
+        }).unwrap()
+    }
+
+}
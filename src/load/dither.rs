@@ -1,6 +1,9 @@
 use image::GrayImage;
 use crate::helper::funcs::redist_range;
 use crate::helper::macros::bound;
+use crate::load::bluenoise;
+use crate::load::profile::Profile;
+use crate::load::preprocess::{Preprocess, Step};
 
 
 // Warning,
@@ -29,49 +32,86 @@ fn nearest_shade(shade: u8, allowed: &Vec<u8>) -> u8 {
 
 
 
+// Error diffusion kernels, each pixel's error is spread to the pixels at the given x & y offsets from it
+// by weight / divisor. Atkinson's only spreads 3/4 of the error, which keeps more contrast
+// https://en.wikipedia.org/wiki/Floyd-Steinberg_dithering (accessed on 12 Sept)
+// https://tannerhelland.com/2012/12/28/dithering-eleven-algorithms-source-code.html
+
+struct Kernel {
+    divisor: f32,
+    taps: &'static [(i32, i32, f32)],
+}
+
+const FLOYD_STEINBERG: Kernel = Kernel {divisor: 16.0, taps: &[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)]};
+const ATKINSON: Kernel = Kernel {divisor: 8.0, taps: &[(1, 0, 1.0), (2, 0, 1.0), (-1, 1, 1.0), (0, 1, 1.0), (1, 1, 1.0), (0, 2, 1.0)]};
+const SIERRA: Kernel = Kernel {divisor: 32.0, taps: &[(1, 0, 5.0), (2, 0, 3.0),
+                                                      (-2, 1, 2.0), (-1, 1, 4.0), (0, 1, 5.0), (1, 1, 4.0), (2, 1, 2.0),
+                                                      (-1, 2, 2.0), (0, 2, 3.0), (1, 2, 2.0)]};
+const JARVIS_JUDICE_NINKE: Kernel = Kernel {divisor: 48.0, taps: &[(1, 0, 7.0), (2, 0, 5.0),
+                                                                   (-2, 1, 3.0), (-1, 1, 5.0), (0, 1, 7.0), (1, 1, 5.0), (2, 1, 3.0),
+                                                                   (-2, 2, 1.0), (-1, 2, 3.0), (0, 2, 5.0), (1, 2, 3.0), (2, 2, 1.0)]};
+
+
+fn add_error(img: &mut GrayImage, error: i16, scale: f32, x: i32, y: i32) {
+    let (w, h) = img.dimensions();
+    if x >= 0 && y >= 0 && (x as u32) < w && (y as u32) < h {
+        let pxl = img.get_pixel_mut(x as u32, y as u32);
+        let newval = (pxl[0] as i16) + (error as f32 * scale).round() as i16;
+        pxl[0] = bound!(newval, 0, 255) as u8;
+    }
+}
+
+fn diffuse(img: &mut GrayImage, shades: &Vec<u8>, kernel: &Kernel, serpentine: bool) {
+    // Round each pixel to the nearest shade and pass the difference on to the pixels after it, going
+    // right to left on odd rows when serpentine, with the kernel flipped to match
+    let (width, height) = img.dimensions();
+    for y in 0..height {
+        let flip = serpentine && y % 2 == 1;
+        for i in 0..width {
+            let x = match flip {
+                true => width - 1 - i,
+                false => i,
+            };
+            let pxl = img.get_pixel_mut(x, y);
+            let oldval = pxl[0];
+            let newval = nearest_shade(oldval, shades);
+            pxl[0] = newval;
+            let error = oldval as i16 - newval as i16;
+            for &(dx, dy, weight) in kernel.taps.iter() {
+                let dx = match flip {
+                    true => -dx,
+                    false => dx,
+                };
+                add_error(img, error, weight / kernel.divisor, x as i32 + dx, y as i32 + dy);
+            }
+        }
+    }
+}
+
+
+
+
 pub fn dither(source: &GrayImage, kind: char, shades: Vec<u8>) -> GrayImage {
     
     let (width, height) = source.dimensions();
     let mut img = source.clone();
 
-    match kind {
-        'f'|'s' => {
-            
-            // Floyd-Steinberg Dithering
-            // https://en.wikipedia.org/wiki/Floyd-Steinberg_dithering (accessed on 12 Sept)
-            
-            fn add_error(img: &mut GrayImage, error: i16, scale: f32, x: u32, y: u32, w: u32, h: u32) {
-                if x < w && y < h {
-                    let pxl = img.get_pixel_mut(x, y);
-                    let oldval = pxl[0];
-                    let newval = (oldval as i16) + (error as f32 * scale).round() as i16;
-                    let newval = if newval < 0 { 0 }
-                                else if newval > 255 { 255 }
-                                else { newval } as u8;
-                    pxl[0] = newval;
-                }
-            }
-            
-            for y in 0..height {
-                for x in 0..width {
-                    let pxl = img.get_pixel_mut(x, y);
-                    let oldval = pxl[0];
-                    let newval = nearest_shade(oldval, &shades);
-                    pxl[0] = newval;
-                    let error = oldval as i16 - newval as i16;
-                    add_error(&mut img, error, 0.4375, x+1, y, width, height);
-                    add_error(&mut img, error, 0.1875, x-1, y+1, width, height);
-                    add_error(&mut img, error, 0.3125, x, y+1, width, height);
-                    add_error(&mut img, error, 0.0625, x+1, y+1, width, height);
-                    //img.put_pixel(x, y, *pxl);
-                }
-            }
+    match kind.to_ascii_lowercase() {
+        'f'|'s'|'a'|'i'|'j' => {
             
+            // Error diffusion, upper case modes scan every other row right to left
+            let kernel = match kind.to_ascii_lowercase() {
+                'a' => &ATKINSON,
+                'i' => &SIERRA,
+                'j' => &JARVIS_JUDICE_NINKE,
+                _ => &FLOYD_STEINBERG,
+            };
+            diffuse(&mut img, &shades, kernel, kind.is_ascii_uppercase());
             img
         },
         _ => {
 
-            // Ordered Dithering
+            // Ordered Dithering, against the blue noise mask for b
             // https://en.wikipedia.org/wiki/Ordered_dithering (accessed on 12 Sept)
             
             fn apply_mask(x: u32, y: u32) -> f64 {
@@ -87,7 +127,10 @@ pub fn dither(source: &GrayImage, kind: char, shades: Vec<u8>) -> GrayImage {
                 MASK[y as usize % 8][x as usize % 8]
             }
 
-            
+            let mask: fn(u32, u32) -> f64 = match kind.to_ascii_lowercase() {
+                'b' => bluenoise::mask,
+                _ => apply_mask,
+            };
             
             // Prior to going through the algorithm normally we do one pass changing the shades
             // since as best I can tell this algorithm only works if the shades are evenly spaced
@@ -111,7 +154,7 @@ pub fn dither(source: &GrayImage, kind: char, shades: Vec<u8>) -> GrayImage {
                     // Redistribute
                     let fval = redist_range(val.try_into().unwrap(), shades[ind-1].try_into().unwrap(), shades[ind].try_into().unwrap(), (ind-1) as f64, ind as f64);
                     // Pass through mask
-                    let newval: f64 = bound!(fval + apply_mask(x, y), 0.0, 3.0);
+                    let newval: f64 = bound!(fval + mask(x, y), 0.0, (shades.len()-1) as f64);
                     // Change back into allowed shades
                     val = shades[newval.round() as usize];
                     pxl[0] = val;
@@ -126,6 +169,102 @@ pub fn dither(source: &GrayImage, kind: char, shades: Vec<u8>) -> GrayImage {
 }
 
 
+#[derive(Clone)]
+pub struct FrameDither {
+    // Dithering settings & the state carried from one frame of a video to the next
+    profile: Profile,
+    preprocess: Preprocess,
+    hold: u8,           // Source change below which pixels keep their previous shade, 0 for off
+    temporal: bool,     // Show shades between the real ones by alternating between them every frame
+    frame: usize,
+    prev: Option<(GrayImage, GrayImage)>,   // Source each pixel was last dithered from, and the shade it got
+}
+impl FrameDither {
+
+    pub fn new(profile: Profile, preprocess: &[Step], hold: u8, temporal: bool) -> FrameDither {
+        FrameDither {profile, preprocess: Preprocess::new(preprocess), hold, temporal, frame: 0, prev: None}
+    }
+
+    pub fn carries_state(&self) -> bool {
+        // Whether frames depend on the ones dithered before them, otherwise they can be dithered in
+        // any order with at_frame
+        self.hold != 0 || self.preprocess.carries_state()
+    }
+
+    pub fn at_frame(&self, frame: usize) -> FrameDither {
+        // Copy for dithering a frame on its own, only the temporal checkerboard depends on which
+        // frame it is
+        FrameDither {frame, ..self.clone()}
+    }
+
+    pub fn preprocess(&mut self, grey: &GrayImage, dbgsave: bool) -> Result<GrayImage, String> {
+        self.preprocess.apply(grey, dbgsave)
+    }
 
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
 
+    pub fn shades(&self) -> Vec<u8> {
+        // Shades to dither to, temporal dithering adds one half way between each pair of real shades
+        let shades = &self.profile.shades;
+        match self.temporal {
+            true => (0..shades.len() * 2 - 1).map(|i| match i % 2 {
+                0 => shades[i / 2],
+                _ => midpoint(shades[i / 2], shades[i / 2 + 1]),
+            }).collect(),
+            false => shades.to_vec(),
+        }
+    }
 
+    pub fn hold(&mut self, source: &GrayImage, dithered: &mut GrayImage) {
+        // Keeps pixels at the shade they were last given while their source barely changes, so still
+        // parts of a video stay the same from frame to frame instead of the dither shifting around
+        // Sources are compared against what the pixel was last dithered from rather than the last
+        // frame so slow fades still get through
+        if self.hold == 0 {
+            return;
+        }
+        let (prev_source, prev_dither) = match &mut self.prev {
+            Some(prev) => prev,
+            None => {
+                self.prev = Some((source.clone(), dithered.clone()));
+                return;
+            },
+        };
+        for (x, y, pxl) in dithered.enumerate_pixels_mut() {
+            let src = source.get_pixel(x, y)[0];
+            let prev_src = prev_source.get_pixel_mut(x, y);
+            let prev_pxl = prev_dither.get_pixel_mut(x, y);
+            if src.abs_diff(prev_src[0]) < self.hold {
+                pxl[0] = prev_pxl[0];
+            } else {
+                prev_src[0] = src;
+                prev_pxl[0] = pxl[0];
+            }
+        }
+    }
+
+    pub fn resolve(&mut self, dithered: &mut GrayImage) {
+        // Swap in-between shades for the real shades either side of them, in a checkerboard that
+        // flips every frame so both neighbouring pixels & consecutive frames average out to it
+        let shades = &self.profile.shades;
+        if self.temporal {
+            for (x, y, pxl) in dithered.enumerate_pixels_mut() {
+                if let Some(i) = (1..shades.len()).find(|&i| pxl[0] == midpoint(shades[i-1], shades[i])) {
+                    pxl[0] = match (x as usize + y as usize + self.frame) % 2 {
+                        0 => shades[i-1],
+                        _ => shades[i],
+                    };
+                }
+            }
+        }
+        self.frame += 1;
+    }
+
+}
+
+
+fn midpoint(a: u8, b: u8) -> u8 {
+    ((a as u16 + b as u16) / 2) as u8
+}
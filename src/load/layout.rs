@@ -0,0 +1,200 @@
+use crate::load::loadimg;
+
+
+// Orders an image's bytes can be compressed in besides the interleaved columns the player's image
+// buffers use. Images in any other layout are decompressed into a scratch buffer and rearranged by
+// the player, so they can't reference earlier images
+// Scan orders move pairs of bytes (both bit-planes of 8 pixels) around, pair n of the interleaved
+// image is column n / 64 & row n % 64
+
+
+
+const COLUMNS: usize = 12;
+const ROWS: usize = 64;
+const BLOCK_ROWS: usize = 8;    // Hilbert blocks are 8x8 pixels, a column of 8 pairs
+
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Layout {
+    Interleaved,    // Column by column, what the player's image buffers use
+    Split,          // One bit-plane after the other, both column by column
+    Rows,           // Row by row, left to right
+    Serpentine,     // Row by row, alternating left to right & right to left
+    Hilbert,        // 8x8 blocks along a Hilbert curve, each block top to bottom
+}
+
+// Player cycles to rearrange each layout into an image buffer, these have to match audVid.z80
+const SPLIT_CYCLES: usize = 35917;
+const ROWS_CYCLES: usize = 46854;
+const SERPENTINE_CYCLES: usize = 71022;
+const HILBERT_CYCLES: usize = 44725;
+
+const SCAN_ORDERS: [Layout; 3] = [Layout::Rows, Layout::Serpentine, Layout::Hilbert];
+
+
+impl Layout {
+
+    pub fn id(self) -> u8 {
+        // Byte the image data starts with in the player, interleaved images don't have one
+        match self {
+            Layout::Interleaved => 0,
+            Layout::Split => 1,
+            Layout::Rows => 2,
+            Layout::Serpentine => 3,
+            Layout::Hilbert => 4,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Layout> {
+        [Layout::Split, Layout::Rows, Layout::Serpentine, Layout::Hilbert].into_iter().find(|layout| layout.id() == id)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Interleaved => "interleaved",
+            Layout::Split => "split",
+            Layout::Rows => "rows",
+            Layout::Serpentine => "serpentine",
+            Layout::Hilbert => "hilbert",
+        }
+    }
+
+    pub fn cycles(self) -> usize {
+        // Extra cycles the player takes on images in this layout
+        match self {
+            Layout::Interleaved => 0,
+            Layout::Split => SPLIT_CYCLES,
+            Layout::Rows => ROWS_CYCLES,
+            Layout::Serpentine => SERPENTINE_CYCLES,
+            Layout::Hilbert => HILBERT_CYCLES,
+        }
+    }
+
+    pub fn arrange(self, img: &[u8]) -> Vec<u8> {
+        // Rearrange an interleaved image into this layout
+        match self {
+            Layout::Interleaved => img.to_vec(),
+            Layout::Split => loadimg::split_planes(img),
+            _ => self.order().iter().flat_map(|&pair| [img[pair*2], img[pair*2+1]]).collect(),
+        }
+    }
+
+    pub fn restore(self, data: &[u8]) -> Vec<u8> {
+        // Rearrange an image in this layout back into an interleaved one, the same as the player does
+        match self {
+            Layout::Interleaved => data.to_vec(),
+            Layout::Split => loadimg::join_planes(data),
+            _ => {
+                let mut img = vec![0; data.len()];
+                for (i, &pair) in self.order().iter().enumerate() {
+                    img[pair*2] = data[i*2];
+                    img[pair*2+1] = data[i*2+1];
+                }
+                img
+            },
+        }
+    }
+
+    fn order(self) -> Vec<usize> {
+        // Interleaved pair each pair of a scan order comes from
+        let pair = |col: usize, row: usize| col * ROWS + row;
+        match self {
+            Layout::Rows => (0..ROWS).flat_map(|row| (0..COLUMNS).map(move |col| pair(col, row))).collect(),
+            Layout::Serpentine => (0..ROWS).flat_map(|row| (0..COLUMNS).map(move |col| match row % 2 {
+                0 => pair(col, row),
+                _ => pair(COLUMNS - 1 - col, row),
+            })).collect(),
+            Layout::Hilbert => hilbert_blocks().into_iter().flat_map(|(col, block)| (0..BLOCK_ROWS).map(move |row| pair(col, block * BLOCK_ROWS + row))).collect(),
+            _ => (0..COLUMNS * ROWS).collect(),
+        }
+    }
+
+}
+
+
+fn hilbert_blocks() -> Vec<(usize, usize)> {
+    // Column & block row of every 8x8 block in the order a 16x16 Hilbert curve passes them, skipping
+    // the parts of the curve off the 12x8 grid of blocks
+    (0..256).map(|d| hilbert_point(16, d)).filter(|&(col, block)| col < COLUMNS && block < ROWS / BLOCK_ROWS).collect()
+}
+
+fn hilbert_point(size: usize, dist: usize) -> (usize, usize) {
+    // Point a distance along a Hilbert curve filling a size x size square
+    let (mut x, mut y) = (0, 0);
+    let mut dist = dist;
+    let mut s = 1;
+    while s < size {
+        let rx = (dist / 2) & 1;
+        let ry = (dist ^ rx) & 1;
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            (x, y) = (y, x);
+        }
+        x += s * rx;
+        y += s * ry;
+        dist /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
+
+
+pub fn parse_scan(scan: &str) -> Result<Vec<Layout>, String> {
+    // Scan orders to try on every frame, a comma separated list or all
+    match scan.to_lowercase().as_str() {
+        "" => Ok(Vec::new()),
+        "all" => Ok(SCAN_ORDERS.to_vec()),
+        scan => scan.split(',').map(|name| match SCAN_ORDERS.iter().find(|layout| layout.name() == name.trim()) {
+            Some(&layout) => Ok(layout),
+            None => Err(format!("Unknown scan order {}, choose from {} or all", name, SCAN_ORDERS.map(|layout| layout.name()).join(", "))),
+        }).collect(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const LAYOUTS: [Layout; 5] = [Layout::Interleaved, Layout::Split, Layout::Rows, Layout::Serpentine, Layout::Hilbert];
+    
+    #[test]
+    fn restore_arranged() {
+        let img: Vec<u8> = (0..COLUMNS * ROWS * 2).map(|i| (i * 31 % 251) as u8).collect();
+        for layout in LAYOUTS {
+            let arranged = layout.arrange(&img);
+            assert_eq!(arranged.len(), img.len(), "{}", layout.name());
+            assert_eq!(arranged == img, layout == Layout::Interleaved, "{}", layout.name());
+            assert_eq!(layout.restore(&arranged), img, "{}", layout.name());
+        }
+    }
+    
+    #[test]
+    fn orders_move_every_pair_once() {
+        for layout in SCAN_ORDERS {
+            let mut order = layout.order();
+            order.sort();
+            assert_eq!(order, (0..COLUMNS * ROWS).collect::<Vec<usize>>(), "{}", layout.name());
+        }
+    }
+    
+    #[test]
+    fn player_hilbert_table() {
+        // scan_hilbert in audVid.z80 holds the image buffer offset of each block in the same order
+        let src = include_str!("../../z80/audVid.z80");
+        let table = &src[src.find("\nscan_hilbert:").unwrap() + 1..];
+        let offsets: Vec<usize> = table.lines().skip(1)
+            .map(|line| line.trim())
+            .filter(|line| !line.starts_with(';'))
+            .take_while(|line| line.starts_with(".dw"))
+            .flat_map(|line| line[3..].split(',').map(|off| usize::from_str_radix(off.trim().trim_start_matches('$'), 16).unwrap()))
+            .collect();
+        let blocks: Vec<usize> = hilbert_blocks().into_iter().map(|(col, block)| (col * ROWS + block * BLOCK_ROWS) * 2).collect();
+        assert_eq!(blocks.len(), COLUMNS * ROWS / BLOCK_ROWS);
+        assert_eq!(offsets, blocks);
+    }
+    
+}
@@ -2,26 +2,23 @@ use image::Pixel;
 use image::imageops;
 use image::GrayImage;
 use image::DynamicImage;
-use image::DynamicImage::{ImageRgba8, ImageLuma8};
-use image::RgbaImage;
-use crate::helper::macros::{passerr, ex_variant};
-use crate::load::dither::dither;
+use image::DynamicImage::ImageRgba8;
+use crate::helper::macros::passerr;
+use crate::load::dither::{dither, FrameDither};
 use image::GenericImageView;
 
 
-pub fn load_imgs(path: &str, keepall: bool, savefile: bool, dither_type: char, show_full: bool)
-    -> Result<(GrayImage, Option<(RgbaImage, RgbaImage, GrayImage)>), String> {
-    
-    fn save_if(img: &DynamicImage, path: &str, save: bool) -> Result<(), String> {
-        if save {
-            passerr!(img.save(path), "Error saving image: {}");
-        }
-        Ok(())
+fn save_if(img: &DynamicImage, path: &str, save: bool) -> Result<(), String> {
+    if save {
+        passerr!(img.save(path), "Error saving image: {}");
     }
-    
-    // Read image from file
-    let mut img = passerr!(image::open(path), "Error during image frame load: {}");
-    // Resize & crop & make greyscale
+    Ok(())
+}
+
+
+pub fn scale_img(mut img: DynamicImage, savefile: bool, show_full: bool) -> Result<GrayImage, String> {
+    // Resize & crop & make greyscale, only needed for frames saved as images as ffmpeg already
+    // does this for frames it streams
     let (width, height) = img.dimensions();
     let ratio: f64 = width as f64 / height as f64;
     let crop = if !show_full {
@@ -48,27 +45,36 @@ pub fn load_imgs(path: &str, keepall: bool, savefile: bool, dither_type: char, s
     };
     let resize = imageops::resize(&crop, 96, 64, imageops::FilterType::Lanczos3);
     let grey = imageops::colorops::grayscale(&resize);
-    // Dither image
-    let dither = dither(&grey, dither_type, vec![232, 165, 68, 25]);
     // Save images
-    let img = ImageRgba8(crop);   save_if(&img, "dbg/crop.png", savefile)?;     let crop = ex_variant!(ImageRgba8, img);    // Dancing around the borrow-checker
-    let img = ImageRgba8(resize); save_if(&img, "dbg/resize.png", savefile)?;   let resize = ex_variant!(ImageRgba8, img);
-    let img = ImageLuma8(grey);   save_if(&img, "dbg/grey.png", savefile)?;     let grey = ex_variant!(ImageLuma8, img);
-    let img = ImageLuma8(dither); save_if(&img, "dbg/dither.png", savefile)?;   let dither = ex_variant!(ImageLuma8, img);
-    
-    if keepall {
-        Ok((dither, Some((crop, resize, grey))))
-    } else {
-        Ok((dither, None))
+    save_if(&ImageRgba8(crop), "dbg/crop.png", savefile)?;
+    save_if(&ImageRgba8(resize), "dbg/resize.png", savefile)?;
+    Ok(grey)
+}
+
+
+pub fn dither_img(grey: &GrayImage, savefile: bool, dither_type: char, frames: &mut FrameDither) -> Result<GrayImage, String> {
+    // Run the preprocessing steps and apply the calibration profile's gamma, then dither image
+    let mut grey = frames.preprocess(grey, savefile)?;
+    for pxl in grey.pixels_mut() {
+        pxl[0] = frames.profile().apply_gamma(pxl[0]);
     }
-    
+    let grey = &grey;
+    let mut dither = dither(grey, dither_type, frames.shades());
+    frames.hold(grey, &mut dither);
+    frames.resolve(&mut dither);
+    // Save images
+    if savefile {
+        passerr!(grey.save("dbg/grey.png"), "Error saving image: {}");
+        passerr!(dither.save("dbg/dither.png"), "Error saving image: {}");
+    }
+    Ok(dither)
 }
 
 
 
-pub fn load_interleaved(path: &str, dither: char, dbgsave: bool, show_full: bool) -> Result<Vec<u8>, String> {
+pub fn load_interleaved(grey: &GrayImage, dither: char, frames: &mut FrameDither, dbgsave: bool) -> Result<Vec<u8>, String> {
     // Get dithered image
-    let mut img = load_imgs(path, false, dbgsave, dither, show_full)?.0;
+    let mut img = dither_img(grey, dbgsave, dither, frames)?;
     // Convert to byte stream
     let mut stream: Vec<u8> = vec![0; 12*64*2];
     let mut iter = img.pixels_mut();
@@ -82,7 +88,7 @@ pub fn load_interleaved(path: &str, dither: char, dbgsave: bool, show_full: bool
                 Some(pxl) => pxl,
                 None => return Err("Number of pixels in image not as many as expected".to_string()),
             };
-            let shade = 3 - (pxl.channels()[0] / 64);
+            let shade = frames.profile().shade_index(pxl.channels()[0]);
             stream[pos] = (stream[pos] * 2) + (shade / 2);
             stream[pos+1] = (stream[pos+1] * 2) + (shade % 2);
         }
@@ -91,9 +97,9 @@ pub fn load_interleaved(path: &str, dither: char, dbgsave: bool, show_full: bool
     Ok(stream)
 }
 
-pub fn load_seperate(path: &str, dither: char, dbgsave: bool, show_full: bool) -> Result<Vec<u8>, String> {
+pub fn load_seperate(grey: &GrayImage, dither: char, frames: &mut FrameDither, dbgsave: bool) -> Result<Vec<u8>, String> {
     // Get dithered image
-    let mut img = load_imgs(path, false, dbgsave, dither, show_full)?.0;
+    let mut img = dither_img(grey, dbgsave, dither, frames)?;
     // Convert to byte stream
     let mut stream: Vec<u8> = vec![0; 12*64*2];
     let mut iter = img.pixels_mut();
@@ -107,7 +113,7 @@ pub fn load_seperate(path: &str, dither: char, dbgsave: bool, show_full: bool) -
                 Some(pxl) => pxl,
                 None => return Err("Number of pixels in image not as many as expected".to_string()),
             };
-            let shade = 3 - (pxl.channels()[0] / 64);
+            let shade = frames.profile().shade_index(pxl.channels()[0]);
             stream[pos] = (stream[pos] * 2) + (shade / 2);
             stream[pos+768] = (stream[pos+768] * 2) + (shade % 2);
         }
@@ -116,3 +122,27 @@ pub fn load_seperate(path: &str, dither: char, dbgsave: bool, show_full: bool) -
     Ok(stream)
 }
 
+
+
+pub fn split_planes(img: &[u8]) -> Vec<u8> {
+    // Rearrange an interleaved image into the layout load_seperate gives, all of one bit-plane
+    // followed by all of the other
+    let half = img.len() / 2;
+    let mut split = vec![0; img.len()];
+    for i in 0..half {
+        split[i] = img[i*2];
+        split[i+half] = img[i*2+1];
+    }
+    split
+}
+
+pub fn join_planes(split: &[u8]) -> Vec<u8> {
+    // Interleave separate bit-planes back together, the same as the player does
+    let half = split.len() / 2;
+    let mut img = vec![0; split.len()];
+    for i in 0..half {
+        img[i*2] = split[i];
+        img[i*2+1] = split[i+half];
+    }
+    img
+}
@@ -2,7 +2,12 @@
 
 pub mod audiostream;
 pub mod loadimg;
+pub mod layout;
 pub mod dither;
+pub mod bluenoise;
+pub mod profile;
+pub mod preprocess;
+pub mod videostream;
 
 
 
@@ -0,0 +1,190 @@
+use image::{GrayImage, imageops};
+use crate::helper::macros::{passerr, bound};
+
+
+// Steps frames can go through between being scaled down & dithered, chosen with --preprocess as a
+// comma separated list run in the order given. Steps that take an amount can be given one after a
+// colon, like sharpen:1.5
+// levels   - Stretch each scene's brightness out to fill black to white
+// sharpen  - Unsharp mask, adds back the difference from a blurred copy times the amount
+// edges    - Darken edges by the amount times how sharp they are, outlining shapes
+// denoise  - Average pixels with the last frame while they're within the amount of it
+
+
+
+const SCENE_CUT: f32 = 40.0;        // Change in mean brightness that starts a new scene
+const LEVELS_SMOOTHING: f32 = 0.1;  // How quickly the levels follow changes within a scene
+const LEVELS_CLIP: f32 = 0.01;      // Fraction of pixels allowed to clip at each end
+const LEVELS_MIN_RANGE: f32 = 32.0; // Scenes with less range than this only get stretched this far
+const SHARPEN_SIGMA: f32 = 1.0;
+
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Step {
+    Levels,
+    Sharpen(f32),
+    Edges(f32),
+    Denoise(u8),
+}
+
+impl Step {
+
+    fn name(self) -> &'static str {
+        match self {
+            Step::Levels => "levels",
+            Step::Sharpen(_) => "sharpen",
+            Step::Edges(_) => "edges",
+            Step::Denoise(_) => "denoise",
+        }
+    }
+
+}
+
+
+pub fn parse_steps(text: &str) -> Result<Vec<Step>, String> {
+    // Steps from a comma separated list of names, each optionally followed by :amount
+    if text.is_empty() {
+        return Ok(Vec::new());
+    }
+    text.split(',').map(|step| {
+        let (name, amount) = match step.trim().split_once(':') {
+            Some((name, amount)) => (name, Some(amount)),
+            None => (step.trim(), None),
+        };
+        let amount = |default: f32| -> Result<f32, String> {
+            match amount {
+                Some(amount) => amount.parse::<f32>().map_err(|err| format!("Bad amount for {}: {}", name, err)),
+                None => Ok(default),
+            }
+        };
+        match name.to_lowercase().as_str() {
+            "levels" => Ok(Step::Levels),
+            "sharpen" => Ok(Step::Sharpen(amount(1.0)?)),
+            "edges" => Ok(Step::Edges(amount(0.5)?)),
+            "denoise" => Ok(Step::Denoise(bound!(amount(10.0)?, 0.0, 255.0) as u8)),
+            _ => Err(format!("Unknown preprocessing step {}, choose from levels, sharpen, edges & denoise", name)),
+        }
+    }).collect()
+}
+
+
+#[derive(Clone)]
+pub struct Preprocess {
+    steps: Vec<Step>,
+    levels: Option<(f32, f32, f32)>,    // Current scene's black & white points and last mean brightness
+    denoised: Option<GrayImage>,        // Last frame out of the denoise step
+}
+impl Preprocess {
+
+    pub fn new(steps: &[Step]) -> Preprocess {
+        Preprocess {steps: steps.to_vec(), levels: None, denoised: None}
+    }
+
+    pub fn carries_state(&self) -> bool {
+        // Levels & denoise follow on from the frames before
+        self.steps.iter().any(|step| matches!(step, Step::Levels | Step::Denoise(_)))
+    }
+
+    pub fn apply(&mut self, grey: &GrayImage, dbgsave: bool) -> Result<GrayImage, String> {
+        // Run a frame through every step, saving what each one gives to the debug folder
+        let mut img = grey.clone();
+        for (i, &step) in self.steps.clone().iter().enumerate() {
+            img = match step {
+                Step::Levels => self.levels(&img),
+                Step::Sharpen(amount) => sharpen(&img, amount),
+                Step::Edges(amount) => edges(&img, amount),
+                Step::Denoise(threshold) => self.denoise(&img, threshold),
+            };
+            if dbgsave {
+                passerr!(img.save(format!("dbg/pre{}_{}.png", i, step.name())), "Error saving image: {}");
+            }
+        }
+        Ok(img)
+    }
+
+    fn levels(&mut self, img: &GrayImage) -> GrayImage {
+        // Find the brightness a small fraction of pixels are darker & lighter than
+        let mut histogram = [0usize; 256];
+        for pxl in img.pixels() {
+            histogram[pxl[0] as usize] += 1;
+        }
+        let clip = (img.pixels().len() as f32 * LEVELS_CLIP) as usize;
+        let percentile = |from_dark: bool| -> f32 {
+            let mut count = 0;
+            for i in 0..256 {
+                let level = match from_dark {
+                    true => i,
+                    false => 255 - i,
+                };
+                count += histogram[level];
+                if count > clip {
+                    return level as f32;
+                }
+            }
+            0.0
+        };
+        let (low, high) = (percentile(true), percentile(false));
+        let mean = img.pixels().map(|pxl| pxl[0] as f32).sum::<f32>() / img.pixels().len().max(1) as f32;
+        // A big jump in brightness is a new scene and takes its levels straight away, otherwise they
+        // ease towards this frame's so they don't pump
+        let (low, high) = match self.levels {
+            Some((scene_low, scene_high, scene_mean)) if (mean - scene_mean).abs() < SCENE_CUT => {
+                (scene_low + (low - scene_low) * LEVELS_SMOOTHING, scene_high + (high - scene_high) * LEVELS_SMOOTHING)
+            },
+            _ => (low, high),
+        };
+        self.levels = Some((low, high, mean));
+        // Flat scenes aren't stretched all the way, that would only bring up noise
+        let range = (high - low).max(LEVELS_MIN_RANGE);
+        let centre = (low + high) / 2.0;
+        let low = bound!(centre - range / 2.0, 0.0, 255.0 - range);
+        let mut out = img.clone();
+        for pxl in out.pixels_mut() {
+            pxl[0] = bound!((pxl[0] as f32 - low) * 255.0 / range, 0.0, 255.0).round() as u8;
+        }
+        out
+    }
+
+    fn denoise(&mut self, img: &GrayImage, threshold: u8) -> GrayImage {
+        // Pixels that only changed a little since the last frame get averaged with it, that keeps
+        // averaging over the frames they stay still for and drops out as soon as they move
+        let mut out = img.clone();
+        if let Some(prev) = &self.denoised {
+            for (x, y, pxl) in out.enumerate_pixels_mut() {
+                let prev = prev.get_pixel(x, y)[0];
+                if pxl[0].abs_diff(prev) < threshold {
+                    pxl[0] = (pxl[0] as u16 + prev as u16).div_ceil(2) as u8;
+                }
+            }
+        }
+        self.denoised = Some(out.clone());
+        out
+    }
+
+}
+
+
+fn sharpen(img: &GrayImage, amount: f32) -> GrayImage {
+    let blurred = imageops::blur(img, SHARPEN_SIGMA);
+    let mut out = img.clone();
+    for (x, y, pxl) in out.enumerate_pixels_mut() {
+        let diff = pxl[0] as f32 - blurred.get_pixel(x, y)[0] as f32;
+        pxl[0] = bound!(pxl[0] as f32 + diff * amount, 0.0, 255.0).round() as u8;
+    }
+    out
+}
+
+fn edges(img: &GrayImage, amount: f32) -> GrayImage {
+    // Sobel edge strength, pixels past the border repeat the edge
+    let (width, height) = img.dimensions();
+    let get = |x: i64, y: i64| img.get_pixel(bound!(x, 0, width as i64 - 1) as u32, bound!(y, 0, height as i64 - 1) as u32)[0] as f32;
+    let mut out = img.clone();
+    for (x, y, pxl) in out.enumerate_pixels_mut() {
+        let (x, y) = (x as i64, y as i64);
+        let gx = get(x+1, y-1) + 2.0 * get(x+1, y) + get(x+1, y+1) - get(x-1, y-1) - 2.0 * get(x-1, y) - get(x-1, y+1);
+        let gy = get(x-1, y+1) + 2.0 * get(x, y+1) + get(x+1, y+1) - get(x-1, y-1) - 2.0 * get(x, y-1) - get(x+1, y-1);
+        let strength = (gx * gx + gy * gy).sqrt() / 4.0;
+        pxl[0] = bound!(pxl[0] as f32 - strength * amount, 0.0, 255.0).round() as u8;
+    }
+    out
+}
@@ -0,0 +1,170 @@
+use std::fs;
+use crate::helper::macros::passerr;
+
+
+// Calibration profiles, how bright each of the 4 shades the player can show looks on a calculator
+// and the gamma curve to apply to frames before dithering to them
+// Either default, or a JSON file like:
+//   {"shades": [232, 165, 68, 25], "gamma": 1.0}
+// Shades go from lightest (both planes off) to darkest (both planes on) and are 0-255. A gamma above
+// 1 darkens the midtones, below 1 lightens them
+
+
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Profile {
+    pub shades: [u8; 4],
+    pub gamma: f64,
+}
+
+// The shades the converter was first tuned on, a JSON profile can be made for any calculator that
+// looks different
+const DEFAULT: Profile = Profile {shades: [232, 165, 68, 25], gamma: 1.0};
+
+
+pub fn find(name: &str) -> Result<Profile, String> {
+    // The default profile, or one loaded from a JSON file
+    if name.to_lowercase() == "default" {
+        return Ok(DEFAULT);
+    }
+    if !name.to_lowercase().ends_with(".json") {
+        return Err(format!("Unknown calibration profile {}, use default or a .json file", name));
+    }
+    let text = passerr!(fs::read_to_string(name), "Error reading calibration profile: {}");
+    parse(&text).map_err(|err| format!("Calibration profile {}: {}", name, err))
+}
+
+
+fn parse(text: &str) -> Result<Profile, String> {
+    // Only the flat object in the comment at the top is accepted, shades and an optional gamma
+    let mut rest = match text.trim().strip_prefix('{').and_then(|body| body.strip_suffix('}')) {
+        Some(body) => body.trim(),
+        None => return Err("must be a single JSON object".to_string()),
+    };
+    let mut shades: Option<Vec<u8>> = None;
+    let mut gamma: Option<f64> = None;
+    while !rest.is_empty() {
+        let (key, value, after) = member(rest)?;
+        match key {
+            "shades" if shades.is_none() => {
+                let list = match value.strip_prefix('[').and_then(|list| list.strip_suffix(']')) {
+                    Some(list) => list,
+                    None => return Err("shades must be a list".to_string()),
+                };
+                shades = Some(passerr!(list.split(',').map(|shade| shade.trim().parse::<u8>()).collect::<Result<Vec<u8>, _>>(), "shades must be 0-255: {}"));
+            },
+            "gamma" if gamma.is_none() => {
+                gamma = Some(passerr!(value.parse::<f64>(), "gamma must be a number: {}"));
+            },
+            "shades" | "gamma" => return Err(format!("{} is given more than once", key)),
+            _ => return Err(format!("unknown key {}, only shades & gamma can be given", key)),
+        }
+        rest = match after.strip_prefix(',') {
+            Some(after) if !after.trim().is_empty() => after.trim_start(),
+            Some(_) => return Err("trailing comma".to_string()),
+            None if after.is_empty() => after,
+            None => return Err(format!("expected a comma before {}", after)),
+        };
+    }
+    let shades: [u8; 4] = match shades {
+        Some(shades) => match shades.try_into() {
+            Ok(shades) => shades,
+            Err(_) => return Err("there must be 4 shades".to_string()),
+        },
+        None => return Err("missing shades".to_string()),
+    };
+    if shades.windows(2).any(|pair| pair[0] <= pair[1]) {
+        return Err("shades must go from lightest to darkest".to_string());
+    }
+    let gamma = gamma.unwrap_or(1.0);
+    if !(gamma > 0.0 && gamma.is_finite()) {
+        return Err("gamma must be above 0".to_string());
+    }
+    Ok(Profile {shades, gamma})
+}
+
+fn member(text: &str) -> Result<(&str, &str, &str), String> {
+    // Splits the "key": value at the start of the text from whatever follows it, values are either
+    // a list or a number so neither can hold a comma outside of brackets
+    let (key, rest) = match text.strip_prefix('"').and_then(|text| text.split_once('"')) {
+        Some(member) => member,
+        None => return Err(format!("expected a key in quotes at {}", text)),
+    };
+    let rest = match rest.trim_start().strip_prefix(':') {
+        Some(rest) => rest.trim_start(),
+        None => return Err(format!("expected a colon after {}", key)),
+    };
+    let end = match rest.starts_with('[') {
+        true => match rest.find(']') {
+            Some(end) => end + 1,
+            None => return Err(format!("{} is missing its closing bracket", key)),
+        },
+        false => rest.find(',').unwrap_or(rest.len()),
+    };
+    Ok((key, rest[..end].trim(), rest[end..].trim_start()))
+}
+
+
+impl Profile {
+
+    pub fn apply_gamma(&self, grey: u8) -> u8 {
+        (255.0 * (grey as f64 / 255.0).powf(self.gamma)).round() as u8
+    }
+
+    pub fn shade_index(&self, shade: u8) -> u8 {
+        // Which of the 4 shades a dithered pixel is, that's also the two bits for its planes
+        match self.shades.iter().position(|&s| s == shade) {
+            Some(index) => index as u8,
+            None => self.shades.iter().enumerate().min_by_key(|(_, &s)| s.abs_diff(shade)).map(|(index, _)| index as u8).unwrap(),
+        }
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn valid() {
+        assert_eq!(parse(r#"{"shades": [232, 165, 68, 25], "gamma": 1.0}"#), Ok(DEFAULT));
+        assert_eq!(parse("{\n  \"gamma\":0.8,\n  \"shades\":[255,170,85,0]\n}\n"), Ok(Profile {shades: [255, 170, 85, 0], gamma: 0.8}));
+        assert_eq!(parse(r#"{"shades": [200, 150, 100, 50]}"#), Ok(Profile {shades: [200, 150, 100, 50], gamma: 1.0}));
+    }
+    
+    #[test]
+    fn wrong_shade_count() {
+        assert!(parse(r#"{"shades": [232, 165, 68], "gamma": 1.0}"#).is_err());
+        assert!(parse(r#"{"shades": [232, 165, 68, 25, 0], "gamma": 1.0}"#).is_err());
+        assert!(parse(r#"{"shades": [], "gamma": 1.0}"#).is_err());
+        assert!(parse(r#"{"gamma": 1.0}"#).is_err());
+    }
+    
+    #[test]
+    fn unordered_shades() {
+        assert!(parse(r#"{"shades": [25, 68, 165, 232]}"#).is_err());
+        assert!(parse(r#"{"shades": [232, 165, 165, 25]}"#).is_err());
+        assert!(parse(r#"{"shades": [232, 256, 68, 25]}"#).is_err());
+    }
+    
+    #[test]
+    fn gamma_not_positive() {
+        assert!(parse(r#"{"shades": [232, 165, 68, 25], "gamma": 0}"#).is_err());
+        assert!(parse(r#"{"shades": [232, 165, 68, 25], "gamma": -1.5}"#).is_err());
+        assert!(parse(r#"{"shades": [232, 165, 68, 25], "gamma": NaN}"#).is_err());
+        assert!(parse(r#"{"shades": [232, 165, 68, 25], "gamma": "1.0"}"#).is_err());
+    }
+    
+    #[test]
+    fn not_flat() {
+        // Keys only count at the top level of the object, and nothing else may be in it
+        assert!(parse(r#"{"name": "shades", "shades": [232, 165, 68, 25]}"#).is_err());
+        assert!(parse(r#"{"model": {"shades": [232, 165, 68, 25]}}"#).is_err());
+        assert!(parse(r#"{"shades": [232, 165, 68, 25], "shades": [200, 150, 100, 50]}"#).is_err());
+        assert!(parse(r#"{"shades": [232, 165, 68, 25],}"#).is_err());
+        assert!(parse(r#"{"shades": [232, 165, 68, 25] "gamma": 1.0}"#).is_err());
+        assert!(parse(r#"[{"shades": [232, 165, 68, 25]}]"#).is_err());
+    }
+    
+}
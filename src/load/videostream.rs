@@ -0,0 +1,188 @@
+use std::io::{Read, Write, BufReader, BufWriter, ErrorKind};
+use std::fs::File;
+use std::process::{Command, Child, Stdio};
+use std::thread::{self, JoinHandle};
+use std::path::Path;
+use image::GrayImage;
+use crate::helper::macros::{passerr, strcat};
+use crate::load::loadimg;
+
+
+// Iterator over a video's frames at the calculator's framerate & resolution
+// Frames either stream straight out of ffmpeg, come from the raw frames saved alongside a
+// previously extracted video, or from the frame images older versions extracted videos to
+
+
+
+pub const FRAME_WIDTH: u32 = 96;
+pub const FRAME_HEIGHT: u32 = 64;
+const FRAME_SIZE: usize = (FRAME_WIDTH * FRAME_HEIGHT) as usize;
+
+
+enum Source {
+    Pipe(Child, Box<dyn Read>, Option<JoinHandle<String>>),    // ffmpeg, its output & what it printed to stderr
+    Raw(Box<dyn Read>),
+    Images(String, f64, f64, bool),     // Folder, video fps, calculator fps, show full frame
+}
+
+
+pub struct FrameIter {
+    source: Source,
+    save: Option<BufWriter<File>>,
+    count: usize,
+    total: usize,
+    dbg_out: bool,
+    ended: bool,
+}
+impl Iterator for FrameIter {
+    type Item = Result<GrayImage, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.ended {
+            return None;
+        }
+        let frame = match &mut self.source {
+            Source::Pipe(_, out, _) | Source::Raw(out) => {
+                // Read next raw frame, stop at the end of the stream
+                let mut buf = vec![0; FRAME_SIZE];
+                match read_frame(out, &mut buf) {
+                    Ok(true) => (),
+                    Ok(false) => {
+                        self.ended = true;
+                        return self.check_ended().err().map(Err);
+                    },
+                    Err(err) => {
+                        // ffmpeg's own error explains it better if it failed
+                        self.ended = true;
+                        return Some(Err(self.check_ended().err().unwrap_or(err)));
+                    },
+                }
+                if let Some(save) = &mut self.save {
+                    if let Err(err) = save.write_all(&buf) {
+                        return Some(Err(format!("Error saving video frame: {}", err)));
+                    }
+                }
+                GrayImage::from_raw(FRAME_WIDTH, FRAME_HEIGHT, buf).unwrap()
+            },
+            Source::Images(folder, fps, calc_fps, show_full) => {
+                // Pick the image closest to this frame's time
+                let src_frame = ((self.count as f64 / *calc_fps) * *fps) as usize + 1;
+                let path = strcat!(folder, "frame", src_frame.to_string(), ".png");
+                if !Path::new(&path).exists() {
+                    return None;
+                }
+                let img = match image::open(&path) {
+                    Ok(img) => img,
+                    Err(err) => return Some(Err(format!("Error during image frame load: {}", err))),
+                };
+                match loadimg::scale_img(img, self.dbg_out, *show_full) {
+                    Ok(img) => img,
+                    Err(err) => return Some(Err(err)),
+                }
+            },
+        };
+        self.count += 1;
+        Some(Ok(frame))
+    }
+}
+
+impl FrameIter {
+
+    pub fn from_video(vid_path: &str, calc_fps: f64, show_full: bool, save_path: Option<&str>, dbg_out: bool) -> Result<FrameIter, String> {
+        // Have ffmpeg scale, crop and convert each frame to greyscale at the calculator's
+        // framerate, then write them to stdout
+        let scale = match show_full {
+            true => format!("scale={}:{}:force_original_aspect_ratio=decrease:flags=lanczos,pad={}:{}:(ow-iw)/2:(oh-ih)/2", FRAME_WIDTH, FRAME_HEIGHT, FRAME_WIDTH, FRAME_HEIGHT),
+            false => format!("crop='min(iw,ih*3/2)':'min(ih,iw*2/3)',scale={}:{}:flags=lanczos", FRAME_WIDTH, FRAME_HEIGHT),
+        };
+        let filter = format!("fps={},{},format=gray", calc_fps, scale);
+        let mut child = passerr!(Command::new("ffmpeg").args(["-v", "error", "-i", vid_path, "-an", "-vf", &filter, "-f", "rawvideo", "-pix_fmt", "gray", "-"])
+                                    .stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn(),
+                                 "{}: Failed to run ffmpeg, double-check installation instructions");
+        let out = Box::new(BufReader::new(child.stdout.take().unwrap()));
+        // Collect any errors on another thread so ffmpeg can't block writing them while we're
+        // still reading frames
+        let mut err_out = child.stderr.take().unwrap();
+        let errors = thread::spawn(move || {
+            let mut errors = String::new();
+            let _ = err_out.read_to_string(&mut errors);
+            errors
+        });
+        // Keep a copy of every frame if the video is being extracted to a folder
+        let save = match save_path {
+            Some(path) => Some(BufWriter::new(passerr!(File::create(path), "Error creating video frames file: {}"))),
+            None => None,
+        };
+        Ok(FrameIter {source: Source::Pipe(child, out, Some(errors)), save, count: 0, total: 0, dbg_out, ended: false})
+    }
+
+    pub fn from_raw(path: &str, dbg_out: bool) -> Result<FrameIter, String> {
+        let file = passerr!(File::open(path), "Error opening video frames file: {}");
+        let total = passerr!(file.metadata()).len() as usize / FRAME_SIZE;
+        Ok(FrameIter {source: Source::Raw(Box::new(BufReader::new(file))), save: None, count: 0, total, dbg_out, ended: false})
+    }
+
+    pub fn from_images(folder: &str, fps: f64, calc_fps: f64, total: usize, show_full: bool, dbg_out: bool) -> FrameIter {
+        FrameIter {source: Source::Images(folder.to_string(), fps, calc_fps, show_full), save: None, count: 0, total, dbg_out, ended: false}
+    }
+
+    pub fn finish(mut self) -> Result<usize, String> {
+        // Stop reading frames, returns the total number of frames in the source
+        if let Source::Pipe(_, _, _) = self.source {
+            // Read any remaining frames into the saved copy so it holds the entire video
+            if self.save.is_some() {
+                for frame in self.by_ref() {
+                    frame?;
+                }
+            }
+            // ffmpeg was checked once it finished, otherwise the rest of the video isn't needed
+            if !self.ended {
+                if let Source::Pipe(child, _, _) = &mut self.source {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                }
+            }
+            if let Some(save) = &mut self.save {
+                passerr!(save.flush(), "Error saving video frames: {}");
+            }
+            self.total = self.count;
+        }
+        Ok(self.total)
+    }
+
+    fn check_ended(&mut self) -> Result<(), String> {
+        // Once ffmpeg closes its output make sure it got through the whole video
+        if let Source::Pipe(child, _, errors) = &mut self.source {
+            let status = passerr!(child.wait(), "Error waiting for ffmpeg: {}");
+            let errors = errors.take().and_then(|errors| errors.join().ok()).unwrap_or_default();
+            let errors = match errors.trim() {
+                "" => String::new(),
+                errors => strcat!(":\n", errors),
+            };
+            if !status.success() {
+                return Err(format!("ffmpeg failed while reading the video ({}){}", status, errors));
+            }
+            if self.count == 0 {
+                return Err(format!("ffmpeg didn't return any frames from the video{}", errors));
+            }
+        }
+        Ok(())
+    }
+
+}
+
+
+fn read_frame(out: &mut Box<dyn Read>, buf: &mut [u8]) -> Result<bool, String> {
+    // Fills buf with the next frame, false if the stream ended before it
+    let mut filled = 0;
+    while filled < buf.len() {
+        match out.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err("Video frames ended partway through a frame".to_string()),
+            Ok(len) => filled += len,
+            Err(err) if err.kind() == ErrorKind::Interrupted => (),
+            Err(err) => return Err(format!("Error reading video frames: {}", err)),
+        }
+    }
+    Ok(true)
+}
@@ -7,6 +7,9 @@ mod video;
 mod args;
 mod load;
 mod compress;
+mod decompress;
+#[cfg(feature = "emulator")]
+mod emulator;
 
 use crate::video::video::*;
 use crate::args::*;
@@ -15,7 +18,14 @@ use crate::args::*;
 
 fn process() -> Result<(), String> {
     // Get command-line arguments
-    let args = getargs()?;
+    let mut args = getargs()?;
+    if args.bench {
+        return video::bench::run(&args);
+    }
+    // Work out what fits before converting
+    if args.fit != 0 {
+        video::fit::fit_args(&mut args)?;
+    }
     // Load video
     let mut vid = Video::new(&args)?;
     // Convert video
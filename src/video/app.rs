@@ -1,6 +1,13 @@
 use crate::Video;
 use crate::helper::funcs::*;
 use crate::compress;
+use crate::compress::rate::RateControl;
+use crate::video::preview::Preview;
+use crate::video::pool::CompFrame;
+use crate::video::schedule::{self, Schedule};
+use crate::compress::cycle_limit::CycleInstr;
+use crate::compress::codec::{CodecInput, Codecs};
+use crate::load::layout::Layout;
 use std::io::{Seek, SeekFrom};
 use crate::VArgs;
 use std::fs::{self, File};
@@ -16,6 +23,24 @@ use std::io::stdout;
 const PAGE_SIZE: usize = 16384;
 const FRAMESCALE_ADDR: usize = 16789 - PAGE_SIZE;   // Will have to update this every time the base app gets re-assembled
 const START_SAMPLE: u8 = 0;
+pub const IMG_BUFFERS: usize = 3;   // Number of image buffers the player rotates between
+pub const PAGES_84P: usize = 30;    // Most pages an app can have on each calculator
+pub const PAGES_84PSE: usize = 94;
+
+
+type PooledInstrs = (Option<Vec<Box<dyn CycleInstr>>>, Vec<(Layout, Vec<Box<dyn CycleInstr>>)>);    // Image compressed ahead of time interleaved & in other layouts
+type LossyFrame = (Vec<u8>, CompLayout);    // Image after rate control, compressed frame
+
+
+struct CompLayout {
+    img_comp: Vec<u8>,
+    aud_comp: Vec<u8>,
+    last_samp: u8,
+    img_cycles: usize,
+    aud_cycles: usize,
+    layout: Layout,
+    sizes: Vec<(Layout, usize)>,    // Image bytes every layout tried took
+}
 
 
 pub struct App<'a> {
@@ -33,21 +58,133 @@ pub struct App<'a> {
     data_size: usize,
     frame_imgs: Vec<Vec<u8>>,
     frame_auds: Vec<Vec<u8>>,
+    frame_layouts: Vec<Layout>,
     frame_sizes: Vec<usize>,
+    frame_cycles: Vec<usize>,
     prev_samp: u8,
+    dec_imgs: Vec<Vec<u8>>,
+    rate: Option<RateControl>,
+    lossy_frames: usize,
+    layout_frames: Vec<(Layout, usize)>,
+    schedule: Schedule,
+    preview: Option<Preview>,
     args: &'a VArgs
 }
+pub fn load_base(args: &VArgs) -> Result<Vec<u8>, String> {
+    // Load the app's code, this goes at the start of the first page
+    match &args.app_source {
+        Some(path) => Ok(passerr!(fs::read(path))),
+        None => {
+            let path = passerr!(find_file_exe("audVid.bin", &[Path::new("z80").to_path_buf(), Path::new("./").to_path_buf()]));
+            Ok(passerr!(fs::read(path)))
+        },
+    }
+}
+
+
+pub fn compress_frame(codecs: &Codecs, img_instrs: Vec<Box<dyn CycleInstr>>, aud: &[u8], start_samp: u8, cycle_limit: usize, aud_limit: usize) -> (Vec<u8>, Vec<u8>, u8, usize, usize) {   // Image data, audio data, last sample, image cycles, audio cycles
+    // Generate audio instructions to go with the image's
+    let aud_input = CodecInput {data: aud, prev: None, target: None, start_samp};
+    let (aud_instrs, last_samp) = codecs.aud.compress(&aud_input, cycle_limit, &|_| 0);
+    // Reduce cycle cost, the image was already compressed to leave the audio enough cycles once
+    // it's been reduced so try that first
+    let img_cycles = codecs.img.cycles(&img_instrs);
+    let mut aud_instrs = vec![aud_instrs];
+    // The player's own work on each frame comes out of the limits first
+    let aud_limit = aud_limit.min(cycle_limit.saturating_sub(img_cycles)).saturating_sub(codecs.aud.frame_cycles());
+    if compress::cycle_limit::get_total_cycles(&aud_instrs) > aud_limit {
+        compress::cycle_limit::reduce_cycles_to(&mut aud_instrs, aud_limit);
+    }
+    let mut instrs = vec![img_instrs, aud_instrs.pop().unwrap()];
+    let cycle_limit = cycle_limit.saturating_sub(codecs.img.frame_cycles() + codecs.aud.frame_cycles());
+    if compress::cycle_limit::get_total_cycles(&instrs) > cycle_limit {
+        compress::cycle_limit::reduce_cycles_to(&mut instrs, cycle_limit);
+    }
+    let img_cycles = codecs.img.cycles(&instrs[0]);
+    let aud_cycles = codecs.aud.cycles(&instrs[1]);
+    // Convert to bytecode, the image doesn't need its references for that
+    let img_comp = codecs.img.bytecode(&instrs[0], &CodecInput {data: &[], prev: None, target: None, start_samp});
+    let aud_comp = codecs.aud.bytecode(&instrs[1], &aud_input);
+    (img_comp, aud_comp, last_samp, img_cycles, aud_cycles)
+}
+
+
+pub fn compress_image(codecs: &Codecs, img: &[u8], prev: Option<&[u8]>, target: Option<&[u8]>, aud: &[u8], cycle_limit: usize) -> Vec<Box<dyn CycleInstr>> {
+    // Compress a frame's image so it fits in the cycle limit along with its audio, trading audio
+    // bytes for cycles at the same price as the image's. The audio takes the same number of bytes &
+    // cycles no matter which sample it starts from
+    let (aud_instrs, _) = codecs.aud.compress(&CodecInput {data: aud, prev: None, target: None, start_samp: 0}, cycle_limit, &|_| 0);
+    let img_input = CodecInput {data: img, prev, target, start_samp: 0};
+    let cycle_limit = cycle_limit.saturating_sub(codecs.img.frame_cycles() + codecs.aud.frame_cycles());
+    codecs.img.compress(&img_input, cycle_limit, &|lambda| compress::cycle_limit::priced_cycles(&aud_instrs, lambda)).0
+}
+
+
+pub fn compress_layout(codecs: &Codecs, img: &[u8], layout: Layout, aud: &[u8], cycle_limit: usize) -> Option<Vec<Box<dyn CycleInstr>>> {
+    // Compress a frame's image in another layout, leaving the player enough cycles to rearrange it.
+    // It gets decompressed into a scratch buffer so it can't reference other images
+    match cycle_limit > layout.cycles() {
+        true => Some(compress_image(codecs, &layout.arrange(img), None, None, aud, cycle_limit - layout.cycles())),
+        false => None,
+    }
+}
+
+
+pub fn buffer_refs(dec_imgs: &[Vec<u8>], inter_frame: bool) -> (Option<&[u8]>, Option<&[u8]>) {    // Previous image, image in the buffer being decoded to
+    // Get images left over in the player's image buffers, given the last few decoded images
+    match inter_frame {
+        true => (dec_imgs.last().map(|img| img.as_slice()),
+                 match dec_imgs.len() >= IMG_BUFFERS {
+                     true => Some(dec_imgs[0].as_slice()),
+                     false => None,
+                 }),
+        false => (None, None),
+    }
+}
+
+
+fn layout_flag(layout: Layout) -> u8 {
+    // Images stored in layouts other than interleaved have bit 14 of their pointer flipped in the
+    // dictionary
+    match layout {
+        Layout::Interleaved => 0x00,
+        _ => 0x40,
+    }
+}
+
+
+pub fn count_pages(first_page_start: usize, frame_sizes: &[usize]) -> usize {
+    // Number of pages an app holding frames of the given sizes would take, packing frames the same
+    // way add_page does
+    // The first data page shares its frames with the space left after the code in the first page
+    let mut pages = 1;
+    let mut extra = PAGE_SIZE - first_page_start;
+    let mut frames = frame_sizes.iter().peekable();
+    while frames.peek().is_some() {
+        let mut used = 4;
+        while let Some(&&size) = frames.peek() {
+            if used + size + 4 > PAGE_SIZE + extra {
+                break;
+            }
+            used += size + 4;
+            frames.next();
+        }
+        if used == 4 {
+            // Frame bigger than a page, can't happen with real frames
+            frames.next();
+        }
+        extra = 0;
+        pages += 1;
+    }
+    pages
+}
+
+
 impl<'a> App<'a> {
     
     pub fn new(args: &'a VArgs, vid: &Video) -> Result<App<'a>, String> {
         // Load first page
-        let mut first_page = match &args.app_source {
-            Some(path) => passerr!(fs::read(path)),
-            None => {
-                let path = passerr!(find_file_exe("audVid.bin", &[Path::new("z80").to_path_buf(), Path::new("./").to_path_buf()]));
-                passerr!(fs::read(path))
-            },
-        };
+        let mut first_page = load_base(args)?;
         let first_page_start = first_page.len();
         first_page.resize(PAGE_SIZE, 255);
         // Setup output file
@@ -71,34 +208,97 @@ impl<'a> App<'a> {
             data_size: 0,
             frame_imgs: Vec::new(),
             frame_auds: Vec::new(),
+            frame_layouts: Vec::new(),
             frame_sizes: Vec::new(),
+            frame_cycles: Vec::new(),
             prev_samp: START_SAMPLE,
+            dec_imgs: Vec::with_capacity(IMG_BUFFERS),
+            rate: match (args.fit_budgets.is_empty(), args.budget) {
+                (false, _) => Some(RateControl::scheduled(args.fit_budgets.clone())),
+                (true, 0) => None,
+                (true, budget) => Some(RateControl::new(budget)),
+            },
+            lossy_frames: 0,
+            layout_frames: [Layout::Interleaved].into_iter().chain(args.layouts.iter().copied()).map(|layout| (layout, 0)).collect(),
+            schedule: Schedule::new(args),
+            preview: match args.preview.len() {
+                0 => None,
+                _ => Some(Preview::new(&args.preview, args.calc_fps, args.temporal, &args.profile)?),
+            },
             args: args,
         } )
     }
     
-    pub fn add_frame(&mut self, img: &[u8], aud: &[u8]) -> Result<(), String> {
+    pub fn add_frame(&mut self, frame: CompFrame) -> Result<(), String> {
+        let (img, aud) = (frame.img.as_slice(), frame.aud.as_slice());
         if self.prev_samp == 0 {
-            self.prev_samp = aud[0];
+            self.prev_samp = aud[0] & 0xFE;     // Samples are stored at half resolution
         }
-        // Generate image & audio instructions
-        let start_samp = self.prev_samp;
-        let mut instrs = vec![compress::lzss_alt::compress(img), {let (comp, last) = compress::nib_diff::compress(aud, self.prev_samp); self.prev_samp = last; comp}];
-        // Reduce cycle cost
-        let mut cycle_cost = compress::cycle_limit::get_total_cycles(&instrs);
-        if cycle_cost > self.args.cycle_limit {
-            cycle_cost = compress::cycle_limit::reduce_cycles_to(&mut instrs, self.args.cycle_limit);
+        // Get images left over in the player's image buffers
+        let (prev_img, target_img) = buffer_refs(&self.dec_imgs, self.args.inter_frame);
+        // The player only starts on a frame early when it's on the same page as the frame before
+        let mut run_ahead = self.page_num > 1 && !self.frame_imgs.is_empty();
+        // The image was compressed ahead of time assuming earlier frames were lossless and with the
+        // most cycles any frame could get, if rate control changed them or this frame gets fewer
+        // cycles than the image needs it has to be compressed again
+        let budget = self.schedule.budget(run_ahead);
+        let pooled_cycles = self.args.codecs.img.cycles(&frame.img_instrs);
+        let codecs = &self.args.codecs;
+        let aud_cycles = codecs.aud.cycles(&codecs.aud.compress(&CodecInput {data: aud, prev: None, target: None, start_samp: 0}, budget, &|_| 0).0);
+        let mut img_instrs = match prev_img == frame.prev.as_deref() && target_img == frame.target.as_deref()
+                                   && (budget >= schedule::max_budget(self.args) || pooled_cycles + aud_cycles <= budget) {
+            true => Some(frame.img_instrs),
+            false => None,
+        };
+        let mut layout_instrs: Vec<(Layout, Vec<Box<dyn CycleInstr>>)> = frame.layout_instrs.into_iter().filter(|(layout, instrs)| {
+            budget >= schedule::max_budget(self.args) || codecs.img.cycles(instrs) + layout.cycles() + aud_cycles <= budget
+        }).collect();
+        let (lossy_img, comp) = loop {
+            let (lossy_img, comp) = self.compress_lossy(img, aud, prev_img, target_img, (img_instrs.take(), std::mem::take(&mut layout_instrs)), self.schedule.budget(run_ahead));
+            // A frame that doesn't fit in what's left of this page starts the next one, where the
+            // player can't start on it early
+            if run_ahead && self.est_size + comp.img_comp.len() + comp.aud_comp.len() + 4 > PAGE_SIZE {
+                run_ahead = false;
+                if comp.img_cycles + comp.aud_cycles > self.schedule.budget(false) {
+                    continue;
+                }
+            }
+            break (lossy_img, comp);
+        };
+        let CompLayout {img_comp, aud_comp, last_samp, img_cycles, aud_cycles, layout, sizes} = comp;
+        let img_cycles = img_cycles + self.schedule.ahead_cycles();
+        if let Some(rate) = &mut self.rate {
+            rate.spend(img_comp.len());
+            if lossy_img != img {
+                self.lossy_frames += 1;
+            }
         }
+        let img = lossy_img.as_slice();
+        // Check the player will have finished decompressing the frame by the time it's shown
+        if let Err(err) = self.schedule.add_frame(img_cycles, aud_cycles, run_ahead) {
+            return Err(format!("Frame {}: {}", self.frame_num, err));
+        }
+        let cycle_cost = img_cycles + aud_cycles;
+        self.prev_samp = last_samp;
         self.total_cycle_cost += cycle_cost;
-        // Convert to bytecode
-        let img_comp = compress::instr::gen_bytecode(&instrs[0]);
-        let mut aud_comp = compress::instr::gen_bytecode(&instrs[1]);   aud_comp.push(start_samp);
+        self.frame_cycles.push(cycle_cost);
+        // Check the frame decompresses back to what we started with
+        if self.args.verify {
+            self.verify_frame(img, aud, &img_comp, &aud_comp, layout)?;
+        }
+        // Render what the calculator will show
+        if let Some(preview) = &mut self.preview {
+            let mut samps = vec![0; aud.len()];
+            self.args.codecs.aud.decompress(&aud_comp, &mut samps, &CodecInput {data: aud, prev: None, target: None, start_samp: 0})?;
+            preview.add_frame(img, &samps)?;
+        }
         // Output to debug file
         if self.args.dbg_out {
             let mut file = passerr!(File::create(strcat!("dbg/img_", self.frame_num.to_string(), ".bin")));     passerr!(file.write_all(img));
             let mut file = passerr!(File::create(strcat!("dbg/imgc_", self.frame_num.to_string(), ".bin")));    passerr!(file.write_all(&img_comp));
             let mut file = passerr!(File::create(strcat!("dbg/aud_", self.frame_num.to_string(), ".bin")));     passerr!(file.write_all(aud));
             let mut file = passerr!(File::create(strcat!("dbg/audc_", self.frame_num.to_string(), ".bin")));    passerr!(file.write_all(&aud_comp));
+            self.save_layout_sizes(&sizes, layout)?;
         }
         // Add frame to list
         let frame_size = img_comp.len() + aud_comp.len();
@@ -110,6 +310,15 @@ impl<'a> App<'a> {
         self.frame_sizes.push(aud_comp.len());
         self.frame_imgs.push(img_comp);
         self.frame_auds.push(aud_comp);
+        self.frame_layouts.push(layout);
+        if let Some(count) = self.layout_frames.iter_mut().find(|(counted, _)| *counted == layout) {
+            count.1 += 1;
+        }
+        // Keep decoded image around for the next frames to reference
+        if self.dec_imgs.len() >= IMG_BUFFERS {
+            self.dec_imgs.remove(0);
+        }
+        self.dec_imgs.push(img.to_vec());
         // Write page to app if we've exceeded this page's capacity
         if self.est_size >= self.target_size {
             self.add_page(true)?;
@@ -118,6 +327,127 @@ impl<'a> App<'a> {
         Ok(())
     }
     
+    fn compress_lossy(&self, img: &[u8], aud: &[u8], prev_img: Option<&[u8]>, target_img: Option<&[u8]>, pooled: PooledInstrs, budget: usize) -> LossyFrame {
+        // Compress a frame so it takes at most budget cycles, then simplify the image until it fits
+        // in the rate control budget keeping the smallest attempt
+        let mut comp = self.compress_layouts(img, aud, prev_img, target_img, pooled, budget);
+        let mut lossy_img = img.to_vec();
+        if let Some(rate) = &self.rate {
+            let refs: Vec<&[u8]> = [prev_img, target_img].into_iter().flatten().collect();
+            let mut level = 0;
+            while comp.img_comp.len() > rate.allowed() && level < compress::rate::MAX_LOSS {
+                level += 1;
+                let attempt_img = compress::rate::degrade(img, &refs, level);
+                let attempt = self.compress_layouts(&attempt_img, aud, prev_img, target_img, (None, Vec::new()), budget);
+                if attempt.img_comp.len() < comp.img_comp.len() {
+                    comp = attempt;
+                    lossy_img = attempt_img;
+                }
+            }
+        }
+        (lossy_img, comp)
+    }
+    
+    fn compress_layouts(&self, img: &[u8], aud: &[u8], prev_img: Option<&[u8]>, target_img: Option<&[u8]>, pooled: PooledInstrs, budget: usize) -> CompLayout {
+        // Compress a frame with its image interleaved and in every other layout being tried, keeping
+        // whichever takes the fewest bytes
+        let aud_limit = self.schedule.aud_limit();
+        let codecs = &self.args.codecs;
+        let (img_instrs, mut layout_instrs) = pooled;
+        let img_instrs = img_instrs.unwrap_or_else(|| compress_image(codecs, img, prev_img, target_img, aud, budget));
+        let (img_comp, aud_comp, last_samp, img_cycles, aud_cycles) = compress_frame(codecs, img_instrs, aud, self.prev_samp, budget, aud_limit);
+        let mut best = CompLayout {sizes: vec![(Layout::Interleaved, img_comp.len())], img_comp, aud_comp, last_samp, img_cycles, aud_cycles, layout: Layout::Interleaved};
+        for &layout in self.args.layouts.iter() {
+            let instrs = match layout_instrs.iter().position(|(pooled, _)| *pooled == layout) {
+                Some(pos) => Some(layout_instrs.swap_remove(pos).1),
+                None => compress_layout(codecs, img, layout, aud, budget),
+            };
+            let Some(instrs) = instrs else {
+                continue;
+            };
+            // The player reads which layout the image is in from its first byte
+            let (comp, aud_comp, last_samp, img_cycles, aud_cycles) = compress_frame(codecs, instrs, aud, self.prev_samp, budget - layout.cycles(), aud_limit);
+            let img_comp = [vec![layout.id()], comp].concat();
+            best.sizes.push((layout, img_comp.len()));
+            if img_comp.len() + aud_comp.len() < best.img_comp.len() + best.aud_comp.len() {
+                best = CompLayout {img_comp, aud_comp, last_samp, img_cycles: img_cycles + layout.cycles(), aud_cycles, layout, sizes: best.sizes};
+            }
+        }
+        best
+    }
+    
+    fn save_layout_sizes(&self, sizes: &[(Layout, usize)], chosen: Layout) -> Result<(), String> {
+        // Add how big the image was in every layout tried to the debug output, for comparing them
+        let mut file = passerr!(File::options().append(self.frame_num != 0).write(true).create(true).truncate(self.frame_num == 0).open("dbg/layouts.csv"));
+        if self.frame_num == 0 {
+            passerr!(file.write_all(b"frame,layout,bytes,chosen\n"));
+        }
+        for &(layout, bytes) in sizes.iter() {
+            passerr!(file.write_all(format!("{},{},{},{}\n", self.frame_num, layout.name(), bytes, layout == chosen).as_bytes()));
+        }
+        Ok(())
+    }
+    
+    fn verify_frame(&self, img: &[u8], aud: &[u8], img_comp: &[u8], aud_comp: &[u8], layout: Layout) -> Result<(), String> {
+        // Decompress image into what the player will have left in the buffer, if that isn't known
+        // yet start from the inverse of the image so any bytes left untouched will not match
+        // Other layouts go through the player's scratch buffer instead, without references, after
+        // the byte saying which layout they're in
+        let interleaved = layout == Layout::Interleaved;
+        let (img_comp, arranged) = match interleaved {
+            true => (img_comp, img.to_vec()),
+            false => (&img_comp[1..], layout.arrange(img)),
+        };
+        let img = arranged.as_slice();
+        let mut dec_img: Vec<u8> = match self.dec_imgs.len() >= IMG_BUFFERS && interleaved {
+            true => self.dec_imgs[0].clone(),
+            false => img.iter().map(|b| !b).collect(),
+        };
+        let prev_img = match self.args.inter_frame && interleaved {
+            true => self.dec_imgs.last().map(|img| img.as_slice()),
+            false => None,
+        };
+        let codecs = &self.args.codecs;
+        let read = match codecs.img.decompress(img_comp, &mut dec_img, &CodecInput {data: img, prev: prev_img, target: None, start_samp: 0}) {
+            Ok(read) => read,
+            Err(err) => return Err(format!("Frame {} image failed to decompress: {}", self.frame_num, err)),
+        };
+        if read != img_comp.len() {
+            return Err(format!("Frame {} image decompression stopped after {} of {} bytes", self.frame_num, read, img_comp.len()));
+        }
+        if let Some(pos) = (0..img.len()).find(|&i| img[i] != dec_img[i]) {
+            return Err(format!("Frame {} image decompressed incorrectly at byte {}", self.frame_num, pos));
+        }
+        // Decompress audio, samples only keep their upper 7 bits
+        let mut dec_aud = vec![0; aud.len()];
+        let read = match codecs.aud.decompress(aud_comp, &mut dec_aud, &CodecInput {data: aud, prev: None, target: None, start_samp: 0}) {
+            Ok(read) => read,
+            Err(err) => return Err(format!("Frame {} audio failed to decompress: {}", self.frame_num, err)),
+        };
+        if read != aud_comp.len() {
+            return Err(format!("Frame {} audio decompression stopped after {} of {} bytes", self.frame_num, read, aud_comp.len()));
+        }
+        if let Some(pos) = (0..aud.len()).find(|&i| aud[i] & 0xFE != dec_aud[i]) {
+            return Err(format!("Frame {} audio decompressed incorrectly at sample {}", self.frame_num, pos));
+        }
+        Ok(())
+    }
+    
+    pub fn lossy_frames(&self) -> usize {
+        // Number of frames rate control had to simplify
+        self.lossy_frames
+    }
+    
+    pub fn layout_frames(&self) -> &[(Layout, usize)] {
+        // Number of frames with their image stored in each layout
+        &self.layout_frames
+    }
+    
+    pub fn frame_cycles(&self) -> &[usize] {
+        // Estimated cycle cost of every frame added so far
+        &self.frame_cycles
+    }
+    
     pub fn finish(&mut self) -> Result<(usize, usize, usize, usize), String> {
         // Finish writing app pages
         while self.frame_imgs.len() > 0 {
@@ -141,6 +471,9 @@ impl<'a> App<'a> {
         let scale: u16 = ((100000.0 / 512.0 / 8.0) / self.args.calc_fps * 256.0) as u16;
         self.first_page[FRAMESCALE_ADDR] = scale as u8;
         self.first_page[FRAMESCALE_ADDR+1] = (scale / 256) as u8;
+        if let Some(preview) = self.preview.take() {
+            preview.finish()?;
+        }
         // Write first page to file
         self.out.seek(SeekFrom::Start(0x00)).unwrap();
         passerr!(self.out.write(&self.first_page));
@@ -150,6 +483,7 @@ impl<'a> App<'a> {
     fn add_page(&mut self, force_write_to_end: bool) -> Result<(), String> {
         let mut next_frame_imgs: Vec<Vec<u8>> = Vec::new();
         let mut next_frame_auds: Vec<Vec<u8>> = Vec::new();
+        let mut next_frame_layouts: Vec<Layout> = Vec::new();
         let mut pos;
         let mut next_est_size = 4;
         let mut next_data_size = 0;
@@ -167,6 +501,7 @@ impl<'a> App<'a> {
                     // Not enough space, move last frame into new vectors
                     let last_img = self.frame_imgs.pop().unwrap();
                     let last_aud = self.frame_auds.pop().unwrap();
+                    next_frame_layouts.insert(0, self.frame_layouts.pop().unwrap());
                     self.data_size -= last_img.len() + last_aud.len();
                     next_data_size += last_img.len() + last_aud.len();
                     next_est_size += last_img.len() + last_aud.len() + 4;
@@ -193,7 +528,7 @@ impl<'a> App<'a> {
                     vec_copy(&mut self.page, pos, img_comp, 0, img_comp.len());
                     // Write position in dictionary
                     self.page[(i*4)+4] = pos as u8;
-                    self.page[(i*4)+5] = (pos / 256 + 0x80) as u8;
+                    self.page[(i*4)+5] = (pos / 256 + 0x80) as u8 ^ layout_flag(self.frame_layouts[i]);
                     pos += img_comp.len();
                 }
                 for (i, aud_comp) in self.frame_auds.iter().enumerate() {
@@ -229,6 +564,7 @@ impl<'a> App<'a> {
                     // Not enough space, move last frame into new vectors
                     let last_img = self.frame_imgs.pop().unwrap();
                     let last_aud = self.frame_auds.pop().unwrap();
+                    next_frame_layouts.insert(0, self.frame_layouts.pop().unwrap());
                     next_data_size += last_img.len() + last_aud.len();
                     next_est_size += last_img.len() + last_aud.len() + 4;
                     next_frame_imgs.insert(0, last_img);
@@ -248,15 +584,15 @@ impl<'a> App<'a> {
                         // Get position in dictionary
                         let dict_pos = (2 * i) + 4;
                         // Get data
-                        let data = match i % 2 {
-                            0 => &self.frame_imgs[i / 2],
-                            _ => &self.frame_auds[i / 2]
+                        let (data, flag) = match i % 2 {
+                            0 => (&self.frame_imgs[i / 2], layout_flag(self.frame_layouts[i / 2])),
+                            _ => (&self.frame_auds[i / 2], 0x00)
                         };
                         // Copy data into page
                         vec_copy(&mut self.first_page, pos, data, 0, data.len());
                         // Write data position
                         self.page[dict_pos] = (pos % 256) as u8;
-                        self.page[dict_pos+1] = (pos / 256 + 0x40) as u8;
+                        self.page[dict_pos+1] = (pos / 256 + 0x40) as u8 ^ flag;
                         pos += data.len();
                     }
                 }
@@ -267,15 +603,15 @@ impl<'a> App<'a> {
                         // Get position in dictionary
                         let dict_pos = (2 * i) + 4;
                         // Get data
-                        let data = match i % 2 {
-                            0 => &self.frame_imgs[i / 2],
-                            _ => &self.frame_auds[i / 2]
+                        let (data, flag) = match i % 2 {
+                            0 => (&self.frame_imgs[i / 2], layout_flag(self.frame_layouts[i / 2])),
+                            _ => (&self.frame_auds[i / 2], 0x00)
                         };
                         // Copy data into page
                         vec_copy(&mut self.page, pos, data, 0, data.len());
                         // Write data position
                         self.page[dict_pos] = (pos % 256) as u8;
-                        self.page[dict_pos+1] = (pos / 256 + 0x80) as u8;
+                        self.page[dict_pos+1] = (pos / 256 + 0x80) as u8 ^ flag;
                         pos += data.len();
                     }
                 }
@@ -300,6 +636,7 @@ impl<'a> App<'a> {
         // Setup next page
         self.frame_imgs = next_frame_imgs;
         self.frame_auds = next_frame_auds;
+        self.frame_layouts = next_frame_layouts;
         self.page_num += 1;
         self.est_size = next_est_size;
         self.data_size = next_data_size;
@@ -309,11 +646,11 @@ impl<'a> App<'a> {
     
     pub fn print_progress(&self, total_frames: usize, total_pages: usize) {
         if !self.args.mute {
-            let base = match self.page_num <= 30 {
+            let base = match self.page_num <= PAGES_84P {
                 true => 'X',
                 false => ' ',
             };
-            let se = match self.page_num <= 94 {
+            let se = match self.page_num <= PAGES_84PSE {
                 true => 'X',
                 false => ' ',
             };
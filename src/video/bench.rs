@@ -0,0 +1,146 @@
+use std::fs;
+use std::time::Instant;
+use crate::VArgs;
+use crate::Video;
+use crate::compress::codec::{self, CodecInput, Stream};
+use crate::video::app::{self, IMG_BUFFERS};
+use crate::load::{loadimg, audiostream};
+use crate::load::dither::FrameDither;
+use crate::helper::funcs::{print_if, print_ln_if};
+use crate::helper::macros::{passerr, strcat};
+
+
+// The bench subcommand, compresses every frame of an extracted video folder with every registered
+// codec and saves each one's size, cycles & encode time so compression regressions can be tracked
+// Images get the same references they would in an app (with -r). Every codec compresses as small
+// as it can, without a cycle limit or rate control, so only the codecs themselves get compared
+
+
+
+struct Row {
+    frame: usize,
+    stream: Stream,
+    codec: &'static str,
+    bytes: usize,
+    cycles: usize,
+    micros: u128,
+}
+
+
+pub fn run(args: &VArgs) -> Result<(), String> {
+    let mut vid = Video::new(args)?;
+    let rows = bench_frames(&mut vid)?;
+    vid.close()?;
+    // Save results
+    let out = match args.out.to_lowercase().ends_with(".json") {
+        true => to_json(&rows),
+        false => to_csv(&rows),
+    };
+    passerr!(fs::write(&args.out, out));
+    print_ln_if(strcat!("\n", summary(&rows)), !args.mute);
+    print_ln_if(format!("Saved results to {}", args.out), !args.mute);
+    Ok(())
+}
+
+
+fn bench_frames(vid: &mut Video) -> Result<Vec<Row>, String> {
+    let args = vid.args;
+    let mut auditer = audiostream::AudIter::new(&strcat!(vid.folder, "audio.wav"), 8, 120, (vid.calc_fps * 512.0) as usize)?;
+    let mut frames = match vid.frames.take() {
+        Some(frames) => frames,
+        None => return Err("Video frames have already been converted".to_string()),
+    };
+    // Skip audio & video before start of encoded video
+    for _i in 0..(vid.start as isize + args.audoff as isize) {
+        auditer.next().unwrap()?;
+    }
+    for _i in 0..vid.start {
+        if let Some(frame) = frames.next() {
+            frame?;
+        }
+    }
+    let codecs = codec::all();
+    let mut rows: Vec<Row> = Vec::new();
+    let mut history: Vec<Vec<u8>> = Vec::with_capacity(IMG_BUFFERS);
+    let mut frame_dither = FrameDither::new(args.profile, &args.preprocess, args.hold, args.temporal);
+    // Each audio codec carries its own start sample on to the next frame
+    let mut start_samps: Vec<Option<u8>> = vec![None; codecs.len()];
+    let mut frame_num = 0;
+    loop {
+        if vid.durr != 0 && frame_num >= vid.durr {
+            break;
+        }
+        let frame = match frames.next() {
+            Some(frame) => frame?,
+            None => break,
+        };
+        let img = loadimg::load_interleaved(&frame, args.dither, &mut frame_dither, false)?;
+        let aud = auditer.next().unwrap()?;
+        let (prev, target) = app::buffer_refs(&history, args.inter_frame);
+        for (i, codec) in codecs.iter().enumerate() {
+            let start_samp = start_samps[i].unwrap_or(aud[0] & 0xFE);
+            let input = match codec.stream() {
+                Stream::Image => CodecInput {data: &img, prev, target, start_samp: 0},
+                Stream::Audio => CodecInput {data: &aud, prev: None, target: None, start_samp},
+            };
+            let start = Instant::now();
+            let (instrs, last_samp) = codec.compress(&input, usize::MAX, &|_| 0);
+            let bytes = codec.bytecode(&instrs, &input).len();
+            let micros = start.elapsed().as_micros();
+            if codec.stream() == Stream::Audio {
+                start_samps[i] = Some(last_samp);
+            }
+            rows.push(Row {frame: frame_num, stream: codec.stream(), codec: codec.name(), bytes, cycles: codec.cycles(&instrs), micros});
+        }
+        if history.len() >= IMG_BUFFERS {
+            history.remove(0);
+        }
+        history.push(img);
+        frame_num += 1;
+        print_if(format!("\rBenchmarked frame {}", frame_num), !args.mute);
+    }
+    vid.num_frames = frames.finish()?;
+    Ok(rows)
+}
+
+
+fn stream_name(stream: Stream) -> &'static str {
+    match stream {
+        Stream::Image => "image",
+        Stream::Audio => "audio",
+    }
+}
+
+
+fn to_csv(rows: &[Row]) -> String {
+    let mut csv = "frame,stream,codec,bytes,cycles,micros\n".to_string();
+    for row in rows.iter() {
+        csv += &format!("{},{},{},{},{},{}\n", row.frame, stream_name(row.stream), row.codec, row.bytes, row.cycles, row.micros);
+    }
+    csv
+}
+
+fn to_json(rows: &[Row]) -> String {
+    // Codec names are plain identifiers, nothing needs escaping
+    let lines: Vec<String> = rows.iter().map(|row| {
+        format!("  {{\"frame\": {}, \"stream\": \"{}\", \"codec\": \"{}\", \"bytes\": {}, \"cycles\": {}, \"micros\": {}}}",
+                row.frame, stream_name(row.stream), row.codec, row.bytes, row.cycles, row.micros)
+    }).collect();
+    format!("[\n{}\n]\n", lines.join(",\n"))
+}
+
+
+fn summary(rows: &[Row]) -> String {
+    // Totals for each codec, in registry order
+    let mut summary = "Codec Benchmark:".to_string();
+    for codec in codec::all().iter() {
+        let rows: Vec<&Row> = rows.iter().filter(|row| row.stream == codec.stream() && row.codec == codec.name()).collect();
+        let frames = rows.len().max(1);
+        let bytes: usize = rows.iter().map(|row| row.bytes).sum();
+        let cycles: usize = rows.iter().map(|row| row.cycles).sum();
+        let micros: u128 = rows.iter().map(|row| row.micros).sum();
+        summary += &format!("\n{:<6}{:<10} {} bytes ({} avg.), {} cycles avg. in {:.2}s",
+                            stream_name(codec.stream()), codec.name(), bytes, bytes / frames, cycles / frames, micros as f64 / 1e6);
+    }
+    summary
+}
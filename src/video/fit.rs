@@ -0,0 +1,248 @@
+use crate::VArgs;
+use crate::Video;
+use crate::video::app::{self, IMG_BUFFERS};
+use crate::load::{loadimg, audiostream};
+use crate::load::dither::FrameDither;
+use crate::compress::rate;
+use crate::helper::funcs::print_ln_if;
+use crate::helper::macros::strcat;
+
+
+// Picks the framerate, duration, cycle limit & byte budget so a video fits in a given number of pages
+// A quick pass compresses a sample of the frames to estimate how big each frame will be, the
+// framerate gets lowered first and only once that isn't enough is the end of the video cut off
+// The estimate can be off by a page or more, so each frame then gets a byte budget of its estimated
+// image size scaled to fill the pages, and rate control simplifies frames once the video runs
+// bigger than estimated
+// The source gets read in full for the estimate and again to convert it. Its frames can't be
+// kept for the conversion as a lower framerate needs different frames from the source, so fitting
+// costs another pass of decoding the video, though only one in SAMPLE_STEP frames is compressed
+
+
+
+const SAMPLE_STEP: usize = 8;   // Only one frame in each group of this many gets compressed while estimating
+const SAMPLE_SHIFT: usize = 3;  // How far the sampled frame moves along each group, so the samples don't line up with repeated frames
+const MIN_FPS: f64 = 19.0;      // Lowest framerate fitting will drop to
+const FPS_STEP: f64 = 0.25;
+const SIZE_MARGIN: f64 = 1.03;  // Sampled sizes are only an estimate, leave some room
+const SCALE_ONE: usize = 1024;  // Image budgets are the estimated sizes times a scale in 1/SCALE_ONEs
+const MAX_SCALE: usize = SCALE_ONE * 16;
+
+
+pub fn fit_args(args: &mut VArgs) -> Result<(), String> {
+    print_ln_if(format!("Estimating video size to fit it in {} pages", args.fit), !args.mute);
+    let first_page_start = app::load_base(args)?.len();
+    // Estimate the size of every frame at the current framerate
+    let (sizes, calc_fps) = {
+        let mut vid = Video::new(args)?;
+        let sizes = estimate(&mut vid)?;
+        let calc_fps = vid.calc_fps;
+        vid.close()?;
+        (sizes, calc_fps)
+    };
+    if sizes.is_empty() {
+        return Err("Video has no frames to fit".to_string());
+    }
+    // The framerate can only change when extracting from a video file, extracted folders have
+    // their audio at a fixed sample rate
+    let mut fps_options = vec![calc_fps];
+    if !args.vid_file.is_empty() {
+        let mut fps = calc_fps - FPS_STEP;
+        while fps >= MIN_FPS {
+            fps_options.push(fps);
+            fps -= FPS_STEP;
+        }
+    }
+    // Use the fastest framerate where the whole video fits, otherwise trim the slowest one
+    let mut choice = None;
+    for &fps in fps_options.iter() {
+        let est = resample(&sizes, calc_fps, fps);
+        if fits(first_page_start, &est, SCALE_ONE, args.fit) {
+            choice = Some((fps, est.len(), false));
+            break;
+        }
+    }
+    let (fps, num_frames, trimmed) = match choice {
+        Some(choice) => choice,
+        None => {
+            let fps = *fps_options.last().unwrap();
+            let est = resample(&sizes, calc_fps, fps);
+            // Binary search for the most frames that fit
+            let (mut low, mut high) = (0, est.len());
+            while low < high {
+                let mid = (low + high).div_ceil(2);
+                if fits(first_page_start, &est[..mid], SCALE_ONE, args.fit) {
+                    low = mid;
+                } else {
+                    high = mid - 1;
+                }
+            }
+            if low == 0 {
+                return Err(format!("Not a single frame fits in {} pages", args.fit));
+            }
+            (fps, low, true)
+        },
+    };
+    // A lower framerate leaves the player more time to decompress each frame
+    args.cycle_limit = (args.cycle_limit as f64 * calc_fps / fps) as usize;
+    args.start = (args.start as f64 * fps / calc_fps).round() as usize;
+    if trimmed || args.dur != 0 {
+        args.dur = num_frames;
+    }
+    args.calc_fps = fps;
+    // Keep the images to what the pages can hold, a budget given on the command line can only
+    // lower that
+    let est = resample(&sizes, calc_fps, fps);
+    let scale = fit_scale(first_page_start, &est[..num_frames], args.fit);
+    if let Some(scale) = scale {
+        args.fit_budgets = est[..num_frames].iter().map(|&(img, _)| {
+            let budget = scaled(img, scale);
+            match args.budget {
+                0 => budget,
+                max => budget.min(max),
+            }
+        }).collect();
+    }
+    print_ln_if(format!("Fitting {} frames ({:.1}s{}) at {}fps with a cycle limit of {}", num_frames, num_frames as f64 / fps,
+                        match trimmed { true => ", end trimmed", false => "" }, fps, args.cycle_limit), !args.mute);
+    if let Some(scale) = scale {
+        print_ln_if(format!("Images get simplified once they run over {}% of their estimated size", scale * 100 / SCALE_ONE), !args.mute);
+    }
+    Ok(())
+}
+
+
+fn scaled(img: usize, scale: usize) -> usize {
+    (img * scale / SCALE_ONE).max(1)
+}
+
+
+fn fits(first_page_start: usize, est: &[(usize, usize)], scale: usize, pages: usize) -> bool {
+    // Whether frames with their images scaled from the estimate fit, leaving room for the most
+    // rate control lets frames overspend their budgets by
+    let budgets: Vec<usize> = est.iter().map(|&(img, _)| scaled(img, scale)).collect();
+    let mut sizes: Vec<usize> = est.iter().zip(budgets.iter()).map(|(&(_, aud), budget)| aud + budget).collect();
+    sizes.push(rate::overdraft(&budgets));
+    app::count_pages(first_page_start, &sizes) <= pages
+}
+
+
+fn fit_scale(first_page_start: usize, est: &[(usize, usize)], pages: usize) -> Option<usize> {
+    // Largest scale of the estimated image sizes that still fits
+    if !fits(first_page_start, est, 1, pages) {
+        return None;
+    }
+    let (mut low, mut high) = (1, MAX_SCALE);
+    while low < high {
+        let mid = (low + high).div_ceil(2);
+        if fits(first_page_start, est, mid, pages) {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    Some(low)
+}
+
+
+fn estimate(vid: &mut Video) -> Result<Vec<(usize, usize)>, String> {   // Estimated image & audio size of each frame
+    let args = vid.args;
+    let mut auditer = audiostream::AudIter::new(&strcat!(vid.folder, "audio.wav"), 8, 120, (vid.calc_fps * 512.0) as usize)?;
+    let mut frames = match vid.frames.take() {
+        Some(frames) => frames,
+        None => return Err("Video frames have already been converted".to_string()),
+    };
+    // Skip audio & video before start of encoded video
+    for _i in 0..(vid.start as isize + args.audoff as isize) {
+        auditer.next().unwrap()?;
+    }
+    for _i in 0..vid.start {
+        if let Some(frame) = frames.next() {
+            frame?;
+        }
+    }
+    let mut sizes: Vec<(usize, usize)> = Vec::new();
+    let mut dec_imgs: Vec<Vec<u8>> = Vec::with_capacity(IMG_BUFFERS);
+    let mut frame_dither = FrameDither::new(args.profile, &args.preprocess, args.hold, args.temporal);
+    let mut size = (0, 0);
+    loop {
+        if vid.durr != 0 && sizes.len() >= vid.durr {
+            break;
+        }
+        let frame = match frames.next() {
+            Some(frame) => frame?,
+            None => break,
+        };
+        let aud = auditer.next().unwrap()?;
+        // Frames between samples are only loaded if later frames can reference them
+        let sample = sampled(sizes.len());
+        if sample || args.inter_frame || args.hold != 0 || !args.preprocess.is_empty() {
+            let img = loadimg::load_interleaved(&frame, args.dither, &mut frame_dither, false)?;
+            if sample {
+                let (prev_img, target_img) = app::buffer_refs(&dec_imgs, args.inter_frame);
+                let img_instrs = app::compress_image(&args.codecs, &img, prev_img, target_img, &aud, args.cycle_limit);
+                let (img_comp, aud_comp, _, _, _) = app::compress_frame(&args.codecs, img_instrs, &aud, aud[0] & 0xFE, args.cycle_limit, args.cycle_limit);
+                size = (img_comp.len(), aud_comp.len());
+            }
+            if dec_imgs.len() >= IMG_BUFFERS {
+                dec_imgs.remove(0);
+            }
+            dec_imgs.push(img);
+        }
+        sizes.push(size);
+    }
+    vid.num_frames = frames.finish()?;
+    Ok(sizes)
+}
+
+
+fn sampled(frame: usize) -> bool {
+    frame % SAMPLE_STEP == frame / SAMPLE_STEP * SAMPLE_SHIFT % SAMPLE_STEP
+}
+
+
+fn resample(sizes: &[(usize, usize)], from_fps: f64, to_fps: f64) -> Vec<(usize, usize)> {
+    // Frame sizes at a different framerate, assuming a frame's size doesn't depend on framerate
+    let len = (sizes.len() as f64 * to_fps / from_fps) as usize;
+    (0..len).map(|i| {
+        let (img, aud) = sizes[((i as f64 * from_fps / to_fps) as usize).min(sizes.len() - 1)];
+        ((img as f64 * SIZE_MARGIN) as usize, (aud as f64 * SIZE_MARGIN) as usize)
+    }).collect()
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn scale_fills_pages() {
+        // Frames alternating between cheap and busy images, with a first page holding no frames
+        let est: Vec<(usize, usize)> = (0..300).map(|i| (if i % 40 < 20 {200} else {1500}, 260)).collect();
+        let first_page_start = 16384;
+        for pages in [20, 30, 40, 60] {
+            let scale = fit_scale(first_page_start, &est, pages).unwrap();
+            assert!(fits(first_page_start, &est, scale, pages));
+            assert!(scale == MAX_SCALE || !fits(first_page_start, &est, scale + 1, pages));
+            // Even with every frame overspending as much as rate control allows
+            let budgets: Vec<usize> = est.iter().map(|&(img, _)| scaled(img, scale)).collect();
+            let total: usize = est.iter().zip(budgets.iter()).map(|(&(_, aud), budget)| aud + budget + 4).sum::<usize>() + rate::overdraft(&budgets);
+            assert!(total <= (pages - 1) * 16384, "{} pages overfilled with {} bytes", pages, total);
+        }
+        assert_eq!(fit_scale(first_page_start, &est, 2), None);
+    }
+    
+    #[test]
+    fn samples_every_position() {
+        // Samples move through every frame of a group so repeated frames can't line up with them
+        let mut positions = [false; SAMPLE_STEP];
+        for frame in 0..SAMPLE_STEP * SAMPLE_STEP {
+            if sampled(frame) {
+                positions[frame % SAMPLE_STEP] = true;
+            }
+        }
+        assert!(positions.iter().all(|&sampled| sampled));
+    }
+    
+}
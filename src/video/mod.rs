@@ -2,4 +2,10 @@
 pub mod video;
 pub mod app;
 pub mod extract;
-
+pub mod sign;
+pub mod fit;
+pub mod preview;
+pub mod pool;
+pub mod schedule;
+pub mod solver_bench;
+pub mod bench;
@@ -0,0 +1,200 @@
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::panic;
+use std::collections::HashMap;
+use crate::compress::cycle_limit::CycleInstr;
+use crate::compress::codec::Codecs;
+use crate::load::layout::Layout;
+use crate::video::app::{self, IMG_BUFFERS};
+use crate::load::loadimg;
+use crate::load::dither::FrameDither;
+use image::GrayImage;
+
+
+// Compresses frames' images on several threads at once, and dithers them when nothing carries over
+// from one frame's dithering to the next
+// Images are compressed assuming the frames before them decode losslessly, which is what App will
+// have in the player's buffers unless rate control changed one of them. App checks the references
+// match before using the compressed image, and compresses the image again itself if they don't
+// Frames come back out in the same order they went in
+
+
+
+pub struct CompFrame {
+    pub img: Vec<u8>,
+    pub aud: Vec<u8>,
+    pub prev: Option<Vec<u8>>,      // References the image was compressed with
+    pub target: Option<Vec<u8>>,
+    pub img_instrs: Vec<Box<dyn CycleInstr>>,
+    pub layout_instrs: Vec<(Layout, Vec<Box<dyn CycleInstr>>)>,    // Image compressed in the other layouts being tried
+}
+
+struct Job {
+    img: Vec<u8>,
+    aud: Vec<u8>,
+    prev: Option<Vec<u8>>,
+    target: Option<Vec<u8>>,
+}
+
+
+struct Workers<J, R> {
+    // Threads running the same work over jobs, handing results back in the order jobs went in
+    jobs: Option<mpsc::Sender<(usize, J)>>,
+    results: mpsc::Receiver<(usize, Result<R, String>)>,
+    threads: Vec<thread::JoinHandle<()>>,
+    finished: HashMap<usize, R>,
+    next_in: usize,
+    next_out: usize,
+    capacity: usize,
+}
+impl<J: Send + 'static, R: Send + 'static> Workers<J, R> {
+
+    fn new<F: Fn(usize, J) -> R + Clone + Send + 'static>(threads: usize, name: &'static str, work: F) -> Workers<J, R> {
+        let threads = match threads {
+            0 => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            threads => threads,
+        };
+        let (job_send, job_recv) = mpsc::channel::<(usize, J)>();
+        let (result_send, results) = mpsc::channel();
+        let job_recv = Arc::new(Mutex::new(job_recv));
+        let workers = (0..threads).map(|_| {
+            let job_recv = Arc::clone(&job_recv);
+            let result_send = result_send.clone();
+            let work = work.clone();
+            thread::spawn(move || loop {
+                // Stop once the pool is dropped
+                let (index, job) = match job_recv.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+                let result = match panic::catch_unwind(panic::AssertUnwindSafe(|| work(index, job))) {
+                    Ok(result) => Ok(result),
+                    Err(_) => Err(format!("{} thread crashed on frame {}", name, index)),
+                };
+                if result_send.send((index, result)).is_err() {
+                    break;
+                }
+            })
+        }).collect();
+        Workers {jobs: Some(job_send), results, threads: workers, finished: HashMap::new(), next_in: 0, next_out: 0, capacity: threads * 2}
+    }
+
+    fn is_full(&self) -> bool {
+        // Enough jobs queued to keep every thread busy
+        self.next_in - self.next_out >= self.capacity
+    }
+
+    fn push(&mut self, job: J) -> Result<(), String> {
+        if let Some(jobs) = &self.jobs {
+            if jobs.send((self.next_in, job)).is_err() {
+                return Err("Worker threads stopped unexpectedly".to_string());
+            }
+        }
+        self.next_in += 1;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<R>, String> {
+        // Wait for the next result in order, None once every queued job has been returned
+        if self.next_out >= self.next_in {
+            return Ok(None);
+        }
+        while !self.finished.contains_key(&self.next_out) {
+            match self.results.recv() {
+                Ok((index, result)) => {
+                    self.finished.insert(index, result?);
+                },
+                Err(_) => return Err("Worker threads stopped unexpectedly".to_string()),
+            }
+        }
+        let result = self.finished.remove(&self.next_out);
+        self.next_out += 1;
+        Ok(result)
+    }
+
+}
+impl<J, R> Drop for Workers<J, R> {
+    fn drop(&mut self) {
+        // Closing the job queue lets the threads finish
+        self.jobs = None;
+        for worker in self.threads.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+
+pub struct FramePool {
+    workers: Workers<Job, CompFrame>,
+    history: Vec<Vec<u8>>,
+    inter_frame: bool,
+}
+impl FramePool {
+
+    pub fn new(threads: usize, codecs: Codecs, inter_frame: bool, layouts: &[Layout], cycle_limit: usize) -> FramePool {
+        let layouts = layouts.to_vec();
+        let workers = Workers::new(threads, "Compression", move |_, job: Job| {
+            let img_instrs = app::compress_image(&codecs, &job.img, job.prev.as_deref(), job.target.as_deref(), &job.aud, cycle_limit);
+            let layout_instrs = layouts.iter().filter_map(|&layout| app::compress_layout(&codecs, &job.img, layout, &job.aud, cycle_limit).map(|instrs| (layout, instrs))).collect();
+            CompFrame {img: job.img, aud: job.aud, prev: job.prev, target: job.target, img_instrs, layout_instrs}
+        });
+        FramePool {workers, history: Vec::with_capacity(IMG_BUFFERS), inter_frame}
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.workers.is_full()
+    }
+
+    pub fn push(&mut self, img: Vec<u8>, aud: Vec<u8>) -> Result<(), String> {
+        // Queue a frame to be compressed
+        let (prev, target) = app::buffer_refs(&self.history, self.inter_frame);
+        let job = Job {img: img.clone(), aud, prev: prev.map(|img| img.to_vec()), target: target.map(|img| img.to_vec())};
+        self.workers.push(job)?;
+        if self.history.len() >= IMG_BUFFERS {
+            self.history.remove(0);
+        }
+        self.history.push(img);
+        Ok(())
+    }
+
+    pub fn next(&mut self) -> Result<Option<CompFrame>, String> {
+        // Wait for the next frame in order, None once every queued frame has been returned
+        self.workers.next()
+    }
+
+}
+
+
+type Loaded = (Vec<u8>, Vec<u8>);     // Dithered image & audio
+
+pub struct LoadPool {
+    // Dithers frames on several threads, only usable when dithering doesn't carry anything over
+    // from one frame to the next
+    workers: Workers<(GrayImage, Vec<u8>), Result<Loaded, String>>,
+}
+impl LoadPool {
+
+    pub fn new(threads: usize, dither: char, frame_dither: &FrameDither) -> LoadPool {
+        let frame_dither = frame_dither.clone();
+        let workers = Workers::new(threads, "Dithering", move |index, (grey, aud): (GrayImage, Vec<u8>)| {
+            let mut frame_dither = frame_dither.at_frame(index);
+            loadimg::load_interleaved(&grey, dither, &mut frame_dither, false).map(|img| (img, aud))
+        });
+        LoadPool {workers}
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.workers.is_full()
+    }
+
+    pub fn push(&mut self, grey: GrayImage, aud: Vec<u8>) -> Result<(), String> {
+        // Queue a frame to be dithered along with its audio
+        self.workers.push((grey, aud))
+    }
+
+    pub fn next(&mut self) -> Result<Option<Loaded>, String> {
+        // Wait for the next frame's image & audio in order, None once every queued frame has been returned
+        self.workers.next()?.transpose()
+    }
+
+}
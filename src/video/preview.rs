@@ -0,0 +1,153 @@
+use std::fs::{self, File};
+use std::io::{Write, Seek, SeekFrom, BufWriter};
+use image::{GrayImage, Luma, DynamicImage, Frame, Delay};
+use image::codecs::gif::{GifEncoder, Repeat};
+use crate::helper::macros::{passerr, strcat};
+use crate::load::profile::Profile;
+
+
+// Renders what the calculator will show & play
+// The LCD only has black & white, the player gets its 4 shades by drawing the screen over & over
+// and picking a pixel from the darker plane 2 out of every 3 draws, and from the lighter plane the
+// rest of the time. The pattern shifts by one every row, column & draw. Each frame of the preview
+// is the average of the draws the player makes while that frame is up, or while it & the frame before
+// are up with temporal dithering, as that alternates pixels between shades every frame
+// Output is either a gif with a wave file next to it, or a folder of png frames with the audio
+
+
+
+const MASK: [u8; 3] = [0b11011011, 0b01101101, 0b10110110];     // Greyscale mask the player uses
+const MASK_STARTS: [usize; 3] = [1, 0, 2];      // Where in the mask each draw starts
+const FRAME_SAMPLES: usize = 512;
+const DRAW_SAMPLES: usize = 768 / 3 + 0x27;     // 3 bytes drawn per sample then the default refresh delay
+
+
+pub struct Preview {
+    gif: Option<GifEncoder<BufWriter<File>>>,   // Otherwise png frames
+    folder: String,
+    wav: BufWriter<File>,
+    sample_rate: usize,
+    num_samples: usize,
+    frame_num: usize,
+    draw_num: usize,
+    shown_ms: u32,
+    blend: bool,
+    lcd_off: f64,       // Brightness of off & on pixels, the calibration profile's lightest & darkest shades
+    lcd_on: f64,
+    prev_draws: (Vec<usize>, usize),    // Dark draws of each pixel in the last frame, and how many draws it got
+}
+impl Preview {
+
+    pub fn new(path: &str, calc_fps: f64, blend: bool, profile: &Profile) -> Result<Preview, String> {
+        let sample_rate = (calc_fps * FRAME_SAMPLES as f64) as usize;
+        let (gif, folder, wav_path) = match path.to_lowercase().ends_with(".gif") {
+            true => {
+                let file = passerr!(File::create(path), "Error creating preview: {}");
+                let mut gif = GifEncoder::new_with_speed(BufWriter::new(file), 10);
+                passerr!(gif.set_repeat(Repeat::Infinite), "Error creating preview: {}");
+                (Some(gif), "".to_string(), strcat!(path[..path.len()-4], ".wav"))
+            },
+            false => {
+                let folder = match path.ends_with('/') {
+                    true => path.to_string(),
+                    false => strcat!(path, "/"),
+                };
+                passerr!(fs::create_dir_all(&folder), "Error creating preview folder: {}");
+                let wav_path = strcat!(folder, "audio.wav");
+                (None, folder, wav_path)
+            },
+        };
+        let mut wav = BufWriter::new(passerr!(File::create(wav_path), "Error creating preview audio: {}"));
+        passerr!(write_wav_header(&mut wav, sample_rate, 0), "Error writing preview audio: {}");
+        Ok(Preview {gif, folder, wav, sample_rate, num_samples: 0, frame_num: 0, draw_num: 0, shown_ms: 0,
+                    blend, lcd_off: profile.shades[0] as f64, lcd_on: profile.shades[3] as f64, prev_draws: (vec![0; 96 * 64], 0)})
+    }
+
+    pub fn add_frame(&mut self, img: &[u8], samples: &[u8]) -> Result<(), String> {
+        // Add a frame from its interleaved image & decoded audio samples
+        // Go through every draw that starts while this frame is up
+        let frame_end = (self.frame_num + 1) * FRAME_SAMPLES;
+        let mut dark = vec![0usize; 96 * 64];
+        let mut draws = 0;
+        while self.draw_num * DRAW_SAMPLES < frame_end {
+            let start = MASK_STARTS[self.draw_num % 3];
+            for y in 0..64 {
+                for x in 0..96 {
+                    let col = x / 8;
+                    let bit = 7 - (x % 8);
+                    let pos = (col * 64 + y) * 2;
+                    let mask = MASK[(start + col + y) % 3];
+                    let plane = match (mask >> bit) & 1 {
+                        1 => img[pos],
+                        _ => img[pos+1],
+                    };
+                    dark[y * 96 + x] += ((plane >> bit) & 1) as usize;
+                }
+            }
+            self.draw_num += 1;
+            draws += 1;
+        }
+        let (mut shown, mut shown_draws) = (dark.clone(), draws);
+        if self.blend {
+            for (shown, prev) in shown.iter_mut().zip(self.prev_draws.0.iter()) {
+                *shown += prev;
+            }
+            shown_draws += self.prev_draws.1;
+        }
+        let frame = GrayImage::from_fn(96, 64, |x, y| {
+            let level = match shown_draws {
+                0 => 0.0,
+                _ => shown[(y * 96 + x) as usize] as f64 / shown_draws as f64,
+            };
+            Luma([(self.lcd_off - level * (self.lcd_off - self.lcd_on)).round() as u8])
+        });
+        self.prev_draws = (dark, draws);
+        self.frame_num += 1;
+        // Write frame
+        match &mut self.gif {
+            Some(gif) => {
+                // Gifs only time frames in 10ms steps, keep the total in step with the audio
+                let end_ms = ((self.frame_num * FRAME_SAMPLES * 100 / self.sample_rate) * 10) as u32;
+                let delay = Delay::from_numer_denom_ms(end_ms - self.shown_ms, 1);
+                self.shown_ms = end_ms;
+                let rgba = DynamicImage::ImageLuma8(frame).to_rgba8();
+                passerr!(gif.encode_frame(Frame::from_parts(rgba, 0, 0, delay)), "Error writing preview frame: {}");
+            },
+            None => {
+                passerr!(frame.save(strcat!(self.folder, "frame", self.frame_num.to_string(), ".png")), "Error writing preview frame: {}");
+            },
+        }
+        // Write audio, the player's samples are 7-bit PWM duty cycles
+        let audio: Vec<u8> = samples.iter().map(|s| s.saturating_mul(2)).collect();
+        passerr!(self.wav.write_all(&audio), "Error writing preview audio: {}");
+        self.num_samples += audio.len();
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        // Fill in the audio's length
+        passerr!(self.wav.seek(SeekFrom::Start(0)), "Error writing preview audio: {}");
+        passerr!(write_wav_header(&mut self.wav, self.sample_rate, self.num_samples), "Error writing preview audio: {}");
+        passerr!(self.wav.flush(), "Error writing preview audio: {}");
+        Ok(())
+    }
+
+}
+
+
+fn write_wav_header(wav: &mut BufWriter<File>, sample_rate: usize, num_samples: usize) -> std::io::Result<()> {
+    // 8-bit mono PCM
+    wav.write_all(b"RIFF")?;
+    wav.write_all(&(36 + num_samples as u32).to_le_bytes())?;
+    wav.write_all(b"WAVEfmt ")?;
+    wav.write_all(&16u32.to_le_bytes())?;
+    wav.write_all(&1u16.to_le_bytes())?;
+    wav.write_all(&1u16.to_le_bytes())?;
+    wav.write_all(&(sample_rate as u32).to_le_bytes())?;
+    wav.write_all(&(sample_rate as u32).to_le_bytes())?;
+    wav.write_all(&1u16.to_le_bytes())?;
+    wav.write_all(&8u16.to_le_bytes())?;
+    wav.write_all(b"data")?;
+    wav.write_all(&(num_samples as u32).to_le_bytes())?;
+    Ok(())
+}
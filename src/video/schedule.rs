@@ -0,0 +1,76 @@
+use crate::VArgs;
+
+
+// Models when the player's decoder gets to work on each frame so a frame can borrow the cycles the
+// frame before it left over
+// Once the decoder finishes a frame it starts straight away on the next frame's image, going into
+// the buffer that was shown two frames ago, as long as the next frame is on the same page. The
+// audio has to wait for the frame end that frees up its buffer and all of it has to be done by the
+// frame end that shows it, otherwise the player stops with a decompression error
+
+
+
+const AHEAD_CYCLES: usize = 319;    // Extra the player takes on an image it starts early, checking it can & noting it's done
+
+
+pub struct Schedule {
+    cycle_limit: usize,     // Decoder cycles between frame ends
+    borrow: bool,
+    slack: usize,           // Cycles between the decoder finishing the last frame & it being shown
+    started: bool,          // Whether any frames have been added yet
+}
+
+
+pub fn max_budget(args: &VArgs) -> usize {
+    // Most cycles any frame could get, a frame can borrow at most a whole frame's worth
+    match args.borrow {
+        true => args.cycle_limit * 2,
+        false => args.cycle_limit,
+    }
+}
+
+
+impl Schedule {
+
+    pub fn new(args: &VArgs) -> Schedule {
+        Schedule {cycle_limit: args.cycle_limit, borrow: args.borrow, slack: 0, started: false}
+    }
+
+    fn lead(&self, run_ahead: bool) -> usize {
+        // Cycles the decoder spends on the next frame's image before the frame end it would
+        // otherwise start at
+        match self.borrow && run_ahead {
+            true => self.slack,
+            false => 0,
+        }
+    }
+
+    pub fn ahead_cycles(&self) -> usize {
+        // The player starts early on every image it can, borrowing or not. Which page a frame ends
+        // up on isn't settled until the page is written, so any frame but the first might be
+        self.started as usize * AHEAD_CYCLES
+    }
+
+    pub fn budget(&self, run_ahead: bool) -> usize {
+        // Cycles the next frame can take altogether, run_ahead is whether it's on the same page as
+        // the frame before it
+        (self.cycle_limit + self.lead(run_ahead)).saturating_sub(self.ahead_cycles())
+    }
+
+    pub fn aud_limit(&self) -> usize {
+        // Audio can't borrow, it only starts once the previous frame is shown
+        self.cycle_limit
+    }
+
+    pub fn add_frame(&mut self, img_cycles: usize, aud_cycles: usize, run_ahead: bool) -> Result<(), String> {
+        // Move on to the next frame, checking the decoder finishes this one before it's shown
+        let used = img_cycles.saturating_sub(self.lead(run_ahead)) + aud_cycles;
+        if used > self.cycle_limit {
+            return Err(format!("the player's decoder falls {} cycles behind and would stop on a decompression error", used - self.cycle_limit));
+        }
+        self.slack = self.cycle_limit - used;
+        self.started = true;
+        Ok(())
+    }
+
+}
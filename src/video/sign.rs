@@ -0,0 +1,366 @@
+use num::{BigUint, One};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+
+// Signs a finished app and packages it into an 8xk file
+// Apps are signed with RSA using a public exponent of 17, the signature being the MD5 hash of the
+// app (header included) raised to the private exponent. The key files hold the modulus and both
+// of its factors, each on their own line as a length byte followed by the number's bytes in
+// little-endian order, all in hex
+
+
+const PAGE_SIZE: usize = 16384;
+const PUBLIC_EXP: u32 = 17;
+const SIG_SPACE: usize = 96;        // Space the signature needs to stay within the last page
+const HEX_RECORD_LEN: usize = 32;
+// 8xk header values
+const TIFL_SIG: &[u8] = b"**TIFL**";
+const TIFL_OBJ_TYPE: u8 = 0x88;
+const TIFL_DEVICE: u8 = 0x73;       // TI-83+ family
+const TIFL_DATA_APP: u8 = 0x24;
+// App header fields, the field id is the first 12 bits of each field
+const FIELD_MASTER: u16 = 0x800;
+const FIELD_NAME: u16 = 0x804;
+const FIELD_PAGES: u16 = 0x808;
+const FIELD_END: u16 = 0x807;
+
+
+pub enum SignError {
+    KeyRead(String),
+    KeyFormat(String),
+    KeyMismatch,
+    Header(String),
+    TooLarge(usize),
+    Write(String),
+}
+impl fmt::Display for SignError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignError::KeyRead(err) => write!(f, "Could not read key file: {}", err),
+            SignError::KeyFormat(err) => write!(f, "Key file is not formatted correctly: {}", err),
+            SignError::KeyMismatch => write!(f, "Key file's modulus does not match its factors"),
+            SignError::Header(err) => write!(f, "App header is not formatted correctly: {}", err),
+            SignError::TooLarge(pages) => write!(f, "App is too large to sign ({} pages)", pages),
+            SignError::Write(err) => write!(f, "Could not write signed app: {}", err),
+        }
+    }
+}
+
+
+pub struct Key {
+    n: BigUint,
+    d: BigUint,
+}
+impl Key {
+
+    pub fn load(path: &Path) -> Result<Key, SignError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) => return Err(SignError::KeyRead(err.to_string())),
+        };
+        let mut lines = text.lines().map(|line| line.trim()).filter(|line| !line.is_empty());
+        let mut nums = Vec::with_capacity(3);
+        for name in ["modulus", "first factor", "second factor"] {
+            match lines.next() {
+                Some(line) => nums.push(parse_key_num(line)?),
+                None => return Err(SignError::KeyFormat(format!("missing {}", name))),
+            }
+        }
+        let (n, p, q) = (nums[0].clone(), nums[1].clone(), nums[2].clone());
+        if &p * &q != n {
+            return Err(SignError::KeyMismatch);
+        }
+        // Get private exponent from the factors
+        let phi = (&p - BigUint::one()) * (&q - BigUint::one());
+        let d = match BigUint::from(PUBLIC_EXP).modinv(&phi) {
+            Some(d) => d,
+            None => return Err(SignError::KeyFormat(format!("factors do not allow an exponent of {}", PUBLIC_EXP))),
+        };
+        Ok(Key {n, d})
+    }
+
+}
+
+
+fn parse_key_num(line: &str) -> Result<BigUint, SignError> {
+    // Length byte followed by little-endian bytes
+    let bytes = match (0..line.len()).step_by(2).map(|i| line.get(i..i+2).and_then(|hex| u8::from_str_radix(hex, 16).ok())).collect::<Option<Vec<u8>>>() {
+        Some(bytes) => bytes,
+        None => return Err(SignError::KeyFormat(format!("\"{}\" is not a hex number", line))),
+    };
+    match bytes.split_first() {
+        Some((&len, num)) if len as usize == num.len() && len > 0 => Ok(BigUint::from_bytes_le(num)),
+        _ => Err(SignError::KeyFormat(format!("\"{}\" has the wrong length", line))),
+    }
+}
+
+
+
+pub fn sign_app(app: &mut Vec<u8>, key: &Key) -> Result<(), SignError> {
+    // Fixes up the app header and appends the signature to the end of the app
+    // Leave space for the signature in the last page, starting a new page if needed
+    if PAGE_SIZE - ((app.len() - 1) % PAGE_SIZE + 1) < SIG_SPACE {
+        let len = app.len().div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        app.resize(len, 0xFF);
+    }
+    let pages = (app.len() + SIG_SPACE).div_ceil(PAGE_SIZE);
+    if pages > u8::MAX as usize {
+        return Err(SignError::TooLarge(pages));
+    }
+    // Fill in app length & page count
+    let (pages_pos, _) = find_field(app, FIELD_PAGES)?;
+    let (master_pos, _) = find_field(app, FIELD_MASTER)?;
+    app[pages_pos] = pages as u8;
+    let len = (app.len() - master_pos) as u32;
+    app[master_pos-4..master_pos].copy_from_slice(&len.to_be_bytes());
+    // Sign MD5 hash of the entire app
+    let hash = BigUint::from_bytes_le(&md5::compute(&app[..]).0);
+    let sig = hash.modpow(&key.d, &key.n).to_bytes_le();
+    app.extend_from_slice(&[0x02, 0x2D, sig.len() as u8]);
+    app.extend_from_slice(&sig);
+    Ok(())
+}
+
+
+fn find_field(app: &[u8], find: u16) -> Result<(usize, usize), SignError> {
+    // Get the position & size of a field's data in the app header
+    let mut pos = 0;
+    while pos + 2 <= app.len() && pos < 128 {
+        let id = ((app[pos] as u16) << 4) | (app[pos+1] as u16 >> 4);
+        let (size_len, size) = match app[pos+1] & 0x0F {
+            0x0D => (1, app.get(pos+2).map_or(0, |&s| s as usize)),
+            0x0E => (2, app.get(pos+2..pos+4).map_or(0, |s| u16::from_be_bytes([s[0], s[1]]) as usize)),
+            0x0F => (4, 0),     // Only the master field uses this, the rest of the header is inside it
+            size => (0, size as usize),
+        };
+        let start = pos + 2 + size_len;
+        if id == find {
+            return Ok((start, size));
+        }
+        if id == FIELD_END {
+            break;
+        }
+        pos = start + size;
+    }
+    Err(SignError::Header(format!("field {:03X} not found", find)))
+}
+
+
+
+pub fn write_8xk(path: &Path, app: &[u8]) -> Result<(), SignError> {
+    let out = tifl_file(app, date_today())?;
+    match fs::write(path, out) {
+        Ok(()) => Ok(()),
+        Err(err) => Err(SignError::Write(err.to_string())),
+    }
+}
+
+
+fn tifl_file(app: &[u8], date: (usize, usize, usize)) -> Result<Vec<u8>, SignError> {
+    // Package the signed app as Intel hex with a TI flash file header
+    let mut hex = String::with_capacity(app.len() * 3);
+    for (page_num, page) in app.chunks(PAGE_SIZE).enumerate() {
+        hex.push_str(&hex_record(0, 0x02, &(page_num as u16).to_be_bytes()));
+        for (i, rec) in page.chunks(HEX_RECORD_LEN).enumerate() {
+            hex.push_str(&hex_record((PAGE_SIZE + i * HEX_RECORD_LEN) as u16, 0x00, rec));
+        }
+    }
+    hex.push_str(&hex_record(0, 0x01, &[]));
+    // Header
+    let (name_pos, name_len) = find_field(app, FIELD_NAME)?;
+    let name = &app[name_pos..name_pos + name_len.min(8)];
+    let (year, month, day) = date;
+    let mut out = Vec::with_capacity(78 + hex.len());
+    out.extend_from_slice(TIFL_SIG);
+    out.extend_from_slice(&[0x01, 0x01, 0x01, TIFL_OBJ_TYPE]);
+    out.extend_from_slice(&[to_bcd(day), to_bcd(month), to_bcd(year / 100), to_bcd(year % 100)]);
+    out.push(name.len() as u8);
+    out.extend_from_slice(name);
+    out.resize(48, 0x00);
+    out.extend_from_slice(&[TIFL_DEVICE, TIFL_DATA_APP]);
+    out.resize(74, 0x00);
+    out.extend_from_slice(&(hex.len() as u32).to_le_bytes());
+    out.extend_from_slice(hex.as_bytes());
+    Ok(out)
+}
+
+
+fn hex_record(addr: u16, rec_type: u8, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&addr.to_be_bytes());
+    bytes.push(rec_type);
+    bytes.extend_from_slice(data);
+    let checksum = bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)).wrapping_neg();
+    bytes.push(checksum);
+    let mut rec: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    rec.insert(0, ':');
+    rec.push_str("\r\n");
+    rec
+}
+
+
+fn to_bcd(val: usize) -> u8 {
+    (((val / 10) << 4) | (val % 10)) as u8
+}
+
+
+fn date_today() -> (usize, usize, usize) {
+    // Convert days since 1970 to a civil date
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() / 86400) as i64 + 719468;
+    let era = days / 146097;
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year as usize, month as usize, day as usize)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const DATE: (usize, usize, usize) = (2026, 10, 18);
+    
+    fn test_app() -> Vec<u8> {
+        // The player's app header with the length & page count left for signing to fill in, then
+        // a body long enough to need several hex records
+        let mut app = vec![0x80, 0x0F, 0x00, 0x00, 0x00, 0x00, 0x80, 0x12, 0x01, 0x04, 0x80, 0x48];
+        app.extend_from_slice(b"TEST    ");
+        app.extend_from_slice(&[0x80, 0x81, 0x00, 0x80, 0x90, 0x03, 0x22, 0x09, 0x00, 0x02, 0x00, 0x80, 0x70]);
+        app.extend((0..100).map(|i| (i * 7) as u8));
+        app
+    }
+    
+    fn signed_app() -> (Vec<u8>, Key) {
+        let key = match Key::load(Path::new("0104.key")) {
+            Ok(key) => key,
+            Err(err) => panic!("{}", err),
+        };
+        let mut app = test_app();
+        if let Err(err) = sign_app(&mut app, &key) {
+            panic!("{}", err);
+        }
+        (app, key)
+    }
+    
+    #[test]
+    fn header_filled_in() {
+        let (app, _) = signed_app();
+        let len = test_app().len();
+        assert_eq!(app[..2], [0x80, 0x0F]);
+        assert_eq!(app[2..6], ((len - 6) as u32).to_be_bytes());
+        assert_eq!(app[22], 1);
+        assert_eq!(app[6..22], test_app()[6..22]);
+        assert_eq!(app[23..len], test_app()[23..]);
+    }
+    
+    #[test]
+    fn signature_verifies() {
+        // The calculator raises the signature to the public exponent and compares it with the hash
+        let (app, key) = signed_app();
+        let len = test_app().len();
+        assert_eq!(app[len..len+2], [0x02, 0x2D]);
+        let sig = &app[len+3..];
+        assert_eq!(sig.len(), app[len+2] as usize);
+        let hash = BigUint::from_bytes_le(&md5::compute(&app[..len]).0);
+        assert_eq!(BigUint::from_bytes_le(sig).modpow(&BigUint::from(PUBLIC_EXP), &key.n), hash);
+    }
+    
+    #[test]
+    fn tifl_header() {
+        let (app, _) = signed_app();
+        let out = match tifl_file(&app, DATE) {
+            Ok(out) => out,
+            Err(err) => panic!("{}", err),
+        };
+        let mut header = b"**TIFL**".to_vec();
+        header.extend_from_slice(&[0x01, 0x01, 0x01, 0x88, 0x18, 0x10, 0x20, 0x26, 0x08]);
+        header.extend_from_slice(b"TEST    ");
+        header.resize(48, 0x00);
+        header.extend_from_slice(&[0x73, 0x24]);
+        header.resize(74, 0x00);
+        assert_eq!(out[..74], header);
+        assert_eq!(out[74..78], ((out.len() - 78) as u32).to_le_bytes());
+    }
+    
+    #[test]
+    fn hex_records() {
+        let (app, _) = signed_app();
+        let out = match tifl_file(&app, DATE) {
+            Ok(out) => out,
+            Err(err) => panic!("{}", err),
+        };
+        let hex = String::from_utf8(out[78..].to_vec()).unwrap();
+        let lines: Vec<&str> = hex.split_terminator("\r\n").collect();
+        assert_eq!(lines[0], ":020000020000FC");
+        assert_eq!(lines[1], ":20400000800F0000007F8012010480485445535420202020808101809003220900020080B1");
+        assert_eq!(*lines.last().unwrap(), ":00000001FF");
+        // Every data record checks out and they hold the whole app in order
+        let mut data = Vec::new();
+        for (i, line) in lines[1..lines.len()-1].iter().enumerate() {
+            let bytes: Vec<u8> = (1..line.len()).step_by(2).map(|i| u8::from_str_radix(&line[i..i+2], 16).unwrap()).collect();
+            assert_eq!(bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)), 0);
+            assert_eq!(bytes[0] as usize, bytes.len() - 5);
+            assert_eq!(u16::from_be_bytes([bytes[1], bytes[2]]) as usize, 0x4000 + i * HEX_RECORD_LEN);
+            assert_eq!(bytes[3], 0x00);
+            data.extend_from_slice(&bytes[4..bytes.len()-1]);
+        }
+        assert_eq!(data, app);
+    }
+    
+    #[test]
+    fn signature_on_new_page() {
+        // Without room left for the signature the last page gets padded out and it starts another
+        let key = match Key::load(Path::new("0104.key")) {
+            Ok(key) => key,
+            Err(err) => panic!("{}", err),
+        };
+        let mut app = test_app();
+        app.resize(PAGE_SIZE - SIG_SPACE + 1, 0x55);
+        if let Err(err) = sign_app(&mut app, &key) {
+            panic!("{}", err);
+        }
+        assert!(app[PAGE_SIZE - SIG_SPACE + 1..PAGE_SIZE].iter().all(|&b| b == 0xFF));
+        assert_eq!(app[PAGE_SIZE..PAGE_SIZE+2], [0x02, 0x2D]);
+        assert_eq!(app[22], 2);
+        let out = match tifl_file(&app, DATE) {
+            Ok(out) => out,
+            Err(err) => panic!("{}", err),
+        };
+        let hex = String::from_utf8(out[78..].to_vec()).unwrap();
+        let page_recs: Vec<&str> = hex.split_terminator("\r\n").filter(|line| line.starts_with(":02000002")).collect();
+        assert_eq!(page_recs, [":020000020000FC", ":020000020001FB"]);
+        assert!(hex.contains(":020000020001FB\r\n:20400000022D"));
+    }
+    
+    fn load_text(name: &str, text: &str) -> Result<Key, SignError> {
+        let path = std::env::temp_dir().join(format!("tiVidConvert_{}.key", name));
+        fs::write(&path, text).unwrap();
+        let key = Key::load(&path);
+        fs::remove_file(&path).unwrap();
+        key
+    }
+    
+    #[test]
+    fn key_errors() {
+        assert!(matches!(Key::load(Path::new("missing.key")), Err(SignError::KeyRead(_))));
+        assert!(matches!(load_text("empty", ""), Err(SignError::KeyFormat(_))));
+        assert!(matches!(load_text("short", "0107\n0107\n"), Err(SignError::KeyFormat(_))));
+        assert!(matches!(load_text("not_hex", "01XY\n0107\n0107\n"), Err(SignError::KeyFormat(_))));
+        assert!(matches!(load_text("odd", "0131\n010\n0107\n"), Err(SignError::KeyFormat(_))));
+        assert!(matches!(load_text("length", "0231\n0107\n0107\n"), Err(SignError::KeyFormat(_))));
+        assert!(matches!(load_text("non_ascii", "01\u{e9}1\n0107\n0107\n"), Err(SignError::KeyFormat(_))));
+        assert!(matches!(load_text("mismatch", "0132\n0107\n0107\n"), Err(SignError::KeyMismatch)));
+        // 3 * 103, 17 divides 103 - 1 so there's no private exponent
+        assert!(matches!(load_text("exponent", "023501\n0103\n0167\n"), Err(SignError::KeyFormat(_))));
+    }
+    
+}
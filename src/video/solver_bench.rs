@@ -0,0 +1,72 @@
+use std::time::{Duration, Instant};
+use crate::compress;
+use crate::compress::instr;
+use crate::video::app::{self, IMG_BUFFERS};
+
+
+// Compares the graph solver against the older pruned one it replaced
+// Every image gets compressed by both, with the same references the app's compression uses, and
+// the totals get printed once the video is done. Neither cycle limiting nor rate control is applied,
+// nor fills, so only the solvers themselves get compared
+
+
+
+pub struct SolverBench {
+    history: Vec<Vec<u8>>,
+    inter_frame: bool,
+    frames: usize,
+    new_size: usize,
+    old_size: usize,
+    new_time: Duration,
+    old_time: Duration,
+    smaller: usize,     // Frames where each solver did better
+    larger: usize,
+}
+impl SolverBench {
+
+    pub fn new(inter_frame: bool) -> SolverBench {
+        SolverBench {history: Vec::with_capacity(IMG_BUFFERS), inter_frame, frames: 0, new_size: 0, old_size: 0,
+                     new_time: Duration::ZERO, old_time: Duration::ZERO, smaller: 0, larger: 0}
+    }
+
+    pub fn add_frame(&mut self, img: &[u8]) {
+        let (prev, target) = app::buffer_refs(&self.history, self.inter_frame);
+        // The compression threads run alongside, alternate which solver goes first so neither
+        // keeps getting slowed down more
+        let mut new_size = 0;
+        let mut old_size = 0;
+        for turn in 0..2 {
+            let start = Instant::now();
+            if (turn + self.frames).is_multiple_of(2) {
+                new_size = instr::gen_bytecode(&compress::lzss_alt::compress(img, prev, target, false, usize::MAX, &|_| 0)).len();
+                self.new_time += start.elapsed();
+            } else {
+                old_size = instr::gen_bytecode(&compress::lzss_alt::compress_pruned(img, prev, target, false)).len();
+                self.old_time += start.elapsed();
+            }
+        }
+        self.new_size += new_size;
+        self.old_size += old_size;
+        if new_size < old_size {
+            self.smaller += 1;
+        } else if new_size > old_size {
+            self.larger += 1;
+        }
+        self.frames += 1;
+        if self.history.len() >= IMG_BUFFERS {
+            self.history.remove(0);
+        }
+        self.history.push(img.to_vec());
+    }
+
+    pub fn report(&self) -> String {
+        let frames = self.frames.max(1);
+        format!("Solver Benchmark:   {} frames\n\
+                 State Solver:  {} bytes ({} avg.) in {:.2}s\n\
+                 Pruned Solver: {} bytes ({} avg.) in {:.2}s\n\
+                 Smaller Frames: {}, Larger Frames: {}",
+                self.frames, self.new_size, self.new_size / frames, self.new_time.as_secs_f64(),
+                self.old_size, self.old_size / frames, self.old_time.as_secs_f64(), self.smaller, self.larger)
+    }
+
+}
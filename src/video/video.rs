@@ -1,28 +1,25 @@
 use crate::load::{loadimg, audiostream};
+use crate::load::dither::FrameDither;
+use crate::load::videostream::FrameIter;
 use crate::helper::macros::{passerr, strcat};
 use crate::args::VArgs;
-use std::process::Command;
 use crate::video::app::App;
-use std::sync::mpsc::Receiver;
+use crate::video::pool::{FramePool, LoadPool};
+use crate::video::schedule;
+use crate::video::solver_bench::SolverBench;
+use crate::video::sign::{self, Key};
 use crate::video::extract::{load_vid_data, save_vid_data};
 use crate::helper::funcs::{print_ln_if, find_file_exe};
-use std::io::{self, Write};
+use std::fs;
+use image::GrayImage;
 use std::path::Path;
 
 
-#[cfg(target_os = "windows")]
-const RABBIT_EXE: &str = "rabbitsign.exe";
-#[cfg(not(target_os = "windows"))]
-const RABBIT_EXE: &str = "rabbitsign";
-
-
-pub enum NumFrames {
-    Rec(Receiver<usize>),
-    Num(usize)
-}
 pub struct Video<'a> {
     pub args: &'a VArgs,
-    pub num_frames: NumFrames,
+    pub frames: Option<FrameIter>,
+    pub num_frames: usize,
+    pub total_frames: usize,
     pub folder: String,
     pub file: String,
     pub name: String,
@@ -37,64 +34,133 @@ impl<'a> Video<'a> {
     
     pub fn new(args: &'a VArgs) -> Result<Video<'a>, String> {
         // Setup video struct
-        let mut vid = Video { args: args, num_frames: NumFrames::Num(0), folder: args.vid_folder.clone(), file: args.vid_file.clone(), name: args.name.clone(), out: args.out.clone(), fps: 0.0, calc_fps: args.calc_fps, durr: args.dur, start: args.start, temp: false };
-        load_vid_data(&mut vid, args)?;
+        let mut vid = Video { args, frames: None, num_frames: 0, total_frames: 0, folder: args.vid_folder.clone(), file: args.vid_file.clone(), name: args.name.clone(), out: args.out.clone(), fps: 0.0, calc_fps: args.calc_fps, durr: args.dur, start: args.start, temp: false };
+        vid.frames = Some(load_vid_data(&mut vid, args)?);
         Ok(vid)
     }
 
+    fn read_frame(&self, frames: &mut FrameIter, auditer: &mut audiostream::AudIter, read: &mut usize) -> Result<Option<(GrayImage, Vec<u8>)>, String> {
+        // Next frame & its audio, None once past the end of the video or the durration being converted
+        if self.durr != 0 && *read >= self.durr {
+            return Ok(None);
+        }
+        let frame = match frames.next() {
+            Some(frame) => frame?,
+            None => return Ok(None),
+        };
+        *read += 1;
+        Ok(Some((frame, auditer.next().unwrap()?)))
+    }
+
     pub fn create_app(&mut self) -> Result<(), String> {
         print_ln_if("".to_string(), !self.args.mute);
         let mut app = App::new(self.args, &self)?;
         let mut cur_frame = 0;
-        let mut auditer = audiostream::AudIter::new(&strcat!(self.folder, "audio.wav"), 8, 120)?;
+        let mut auditer = audiostream::AudIter::new(&strcat!(self.folder, "audio.wav"), 8, 120, (self.calc_fps * 512.0) as usize)?;
+        let mut frames = match self.frames.take() {
+            Some(frames) => frames,
+            None => return Err("Video frames have already been converted".to_string()),
+        };
+        // Number of frames to show in progress, might only be an estimate
+        let total_frames = match self.durr {
+            0 => self.total_frames.saturating_sub(self.start),
+            durr => durr,
+        };
         
-        // Skip audio before start of encoded video
+        // Skip audio & video before start of encoded video
         // TODO: Make negative offset work with start at 0
         for _i in 0..(self.start as isize + self.args.audoff as isize) {
-            let _ = auditer.next().unwrap();
+            auditer.next().unwrap()?;
         }
+        for _i in 0..self.start {
+            if let Some(frame) = frames.next() {
+                frame?;
+            }
+        }
+        let mut frame_dither = FrameDither::new(self.args.profile, &self.args.preprocess, self.args.hold, self.args.temporal);
+        let mut pool = FramePool::new(self.args.threads, self.args.codecs, self.args.inter_frame, &self.args.layouts, schedule::max_budget(self.args));
+        // Frames get dithered on the main thread when they follow on from the one before, or to save
+        // every frame's debug images in order
+        let mut loader = match frame_dither.carries_state() || self.args.dbg_out {
+            true => None,
+            false => Some(LoadPool::new(self.args.threads, self.args.dither, &frame_dither)),
+        };
+        let mut bench = match self.args.solver_bench {
+            true => Some(SolverBench::new(self.args.inter_frame)),
+            false => None,
+        };
+        let mut reading = true;
+        let mut read = 0;
+        let mut loading = true;
         loop {
-            // Get frame number to encode
-            let src_frame = (((cur_frame + self.start) as f64 / self.calc_fps) * self.fps) as usize + 1;
-            // Check on ffmpeg thread
-            // Wait for next frame to exist (currently outputing from ffmpeg) or thread has
-            // finished
-            loop {
-                // Check if thread finished
-                if self.try_recv() {
-                    break;
+            // Keep the compression threads busy while frames get added to the app in order
+            while loading && !pool.is_full() {
+                // Keep the dithering threads ahead of the compression threads
+                while let Some(loader) = loader.as_mut().filter(|loader| reading && !loader.is_full()) {
+                    match self.read_frame(&mut frames, &mut auditer, &mut read)? {
+                        Some((frame, aud)) => loader.push(frame, aud)?,
+                        None => reading = false,
+                    }
                 }
-                
-                // Check if frame after current frame exists
-                let frame_name = strcat!(self.folder, "frame", (src_frame+1).to_string(), ".png");
-                if std::path::Path::new(&frame_name).exists() {
-                    break;
+                // Load image & audio data
+                let loaded = match &mut loader {
+                    Some(loader) => loader.next()?,
+                    None => match self.read_frame(&mut frames, &mut auditer, &mut read)? {
+                        Some((frame, aud)) => Some((loadimg::load_interleaved(&frame, self.args.dither, &mut frame_dither, self.args.dbg_out)?, aud)),
+                        None => None,
+                    },
+                };
+                let (img, aud) = match loaded {
+                    Some(loaded) => loaded,
+                    None => {
+                        loading = false;
+                        break;
+                    },
+                };
+                if let Some(bench) = &mut bench {
+                    bench.add_frame(&img);
                 }
-                // Cannot continue yet, sleep for a little bit
-                std::thread::sleep(std::time::Duration::from_millis(400));
+                pool.push(img, aud)?;
             }
-            // Check if done with encoding frames
-            if self.durr != 0 && cur_frame >= self.durr {
-                break;
-            }
-            
-            // Load image & audio data
-            let fpath = strcat!(self.folder, "frame", src_frame.to_string(), ".png");
-            let img = loadimg::load_interleaved(&fpath, self.args.dither, self.args.dbg_out, self.args.show_full)?;
-            let aud = auditer.next().unwrap();
+            let comp = match pool.next()? {
+                Some(comp) => comp,
+                None => break,
+            };
             // Add to app
-            app.add_frame(&img, &aud)?;
+            app.add_frame(comp)?;
             // Print progress
-            app.print_progress(self.durr, 0);
+            app.print_progress(total_frames, 0);
             cur_frame += 1;
         }
+        self.num_frames = frames.finish()?;
         // Finish app
         let (num_pages, avg_img, avg_aud, avg_cycle) = app.finish()?;
-        app.print_progress(self.durr, num_pages);
+        app.print_progress(cur_frame, num_pages);
         print_ln_if("\nFinished converting app".to_string(), !self.args.mute);
         print_ln_if(format!("Avg. Img Frame Size: {}", avg_img), !self.args.mute);
         print_ln_if(format!("Avg. Aud Frame Size: {}", avg_aud), !self.args.mute);
         print_ln_if(format!("Avg.  Frame  Cycles: {}", avg_cycle), !self.args.mute);
+        if self.args.budget != 0 || !self.args.fit_budgets.is_empty() {
+            print_ln_if(format!("Simplified Frames:  {}", app.lossy_frames()), !self.args.mute);
+        }
+        if !self.args.layouts.is_empty() {
+            let counts: Vec<String> = app.layout_frames().iter().map(|(layout, frames)| format!("{} {}", frames, layout.name())).collect();
+            print_ln_if(format!("Image Layouts:      {}", counts.join(", ")), !self.args.mute);
+        }
+        if let Some(bench) = &bench {
+            print_ln_if(strcat!("\n", bench.report()), !self.args.mute);
+        }
+        if !self.args.codecs.playable() {
+            print_ln_if(format!("Warning: the player can't decompress {} images & {} audio, the app is only good for comparing sizes", self.args.codecs.img.name(), self.args.codecs.aud.name()), !self.args.mute);
+        }
+        if self.args.fit != 0 && num_pages > self.args.fit {
+            print_ln_if(format!("Warning: app takes {} pages but was fit to {}, try a smaller fit", num_pages, self.args.fit), !self.args.mute);
+        }
+        // Check the app on an emulated calculator
+        #[cfg(feature = "emulator")]
+        if self.args.emulate {
+            crate::emulator::player::check_app(&strcat!(self.folder, "out.bin"), app.frame_cycles(), self.args)?;
+        }
         // Get keyfile location
         let key_path = match &self.args.key_source {
             None => {
@@ -103,59 +169,17 @@ impl<'a> Video<'a> {
                                                       Path::new("./").to_path_buf() ]))
             }, Some(path_str) => Path::new(&path_str).to_path_buf(),
         };
-        let bin_path = strcat!(self.folder, "out.bin");
-        // Get rabbitsign location
-        let exe_path = match find_file_exe(RABBIT_EXE, &[ Path::new("rabbitsign").to_path_buf(),
-                                                            Path::new("./").to_path_buf()]) {
-            Ok(path) => path,
-            Err(_) => Path::new(RABBIT_EXE).to_path_buf(),
-        };
-        // Run rabbitsign
-        let output = passerr!(Command::new(exe_path).args(["-g", "-v", "-P", "-p", "-k", key_path.to_str().unwrap(), &bin_path, "-o", &strcat!(self.args.out, ".8xk")]).output(), "{}: Could not find rabbitsign program, double-check installation instructions");
-        if !output.status.success() {
-            io::stdout().write_all(&output.stderr).unwrap();
-            io::stdout().write_all(&output.stdout).unwrap();
-            Err("Failed to sign app, see above".to_string())
-        } else {
-            print_ln_if("\nFinished signing app".to_string(), !self.args.mute);
-            Ok(())
-        }
-    }
-    
-    fn try_recv(&mut self) -> bool{
-        match &self.num_frames {
-            NumFrames::Rec(rec) => {
-                // Waiting on thread
-                match rec.try_recv() {
-                    Ok(num) => {
-                        // Thread has finished and given us the total number of frames
-                        self.num_frames = NumFrames::Num(num);
-                        // Set durration
-                        self.set_durr(num);
-                        true
-                    },
-                    Err(_) => false
-                }
-            },
-            NumFrames::Num(num) => {
-                self.set_durr(*num);    // Why??????
-                true
-            },
-        }
-    }
-    fn set_durr(&mut self, max: usize) {
-        let max_durr = (((max-1) as f64 / self.fps) * self.calc_fps) as usize - self.start;
-        if self.durr == 0 || self.durr > max_durr {
-            self.durr = max_durr;
-        }
+        // Sign app
+        let key = passerr!(Key::load(&key_path));
+        let mut bin = passerr!(fs::read(strcat!(self.folder, "out.bin")), "Error reading app to sign: {}");
+        passerr!(sign::sign_app(&mut bin, &key));
+        passerr!(sign::write_8xk(Path::new(&strcat!(self.args.out, ".8xk")), &bin));
+        print_ln_if("\nFinished signing app".to_string(), !self.args.mute);
+        Ok(())
     }
     
-    pub fn close(mut self) -> Result<(), String> {
-        if !self.temp {
-            while !self.try_recv() {}
-            save_vid_data(&self, self.args)?;
-        }
-        Ok(())
+    pub fn close(self) -> Result<(), String> {
+        save_vid_data(&self, self.args)
     }
     
 